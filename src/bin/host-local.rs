@@ -0,0 +1,14 @@
+use rust_cni::ipam::host_local::HostLocal;
+use rust_cni::skel;
+
+fn main() {
+    skel::plugin_main(
+        &HostLocal,
+        &HostLocal::plugin_info(),
+        concat!(
+            "host-local IPAM plugin (rust-cni ",
+            env!("CARGO_PKG_VERSION"),
+            ")"
+        ),
+    );
+}
@@ -0,0 +1,14 @@
+use rust_cni::ipam::static_ipam::StaticIpam;
+use rust_cni::skel;
+
+fn main() {
+    skel::plugin_main(
+        &StaticIpam,
+        &StaticIpam::plugin_info(),
+        concat!(
+            "static IPAM plugin (rust-cni ",
+            env!("CARGO_PKG_VERSION"),
+            ")"
+        ),
+    );
+}
@@ -1,14 +1,22 @@
 use libcni::{
     api::{CNIConfig, CNI},
     exec::RawExec,
-    types::Config,
+    registry::PluginRegistry,
+    retry::RetryPolicy,
+    types::{Config, IfnamePolicy, SetupMode},
+    validate::validate_ifname,
 };
-use log::{debug, error};
+use log::{debug, error, warn};
+use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::thread;
 
 use crate::{
     libcni,
-    namespace::{Namespace, Network},
+    loopback::NativeLoopback,
+    namespace::{Namespace, Network, NetworkSelection},
+    verify::VerifyReport,
 };
 
 pub struct Libcni {
@@ -20,18 +28,17 @@ pub struct Libcni {
 
 impl Default for Libcni {
     fn default() -> Self {
+        let config = Config {
+            plugin_dirs: vec!["/opt/cni/bin".to_string()],
+            plugin_conf_dir: "/etc/cni/net.d".to_string(),
+            plugin_max_conf_num: 1,
+            prefix: "vethcni".to_string(),
+            cache_dir: "/var/lib/cni/cache".to_string(),
+            ..Default::default()
+        };
         Libcni {
-            config: Config {
-                plugin_dirs: vec!["/opt/cni/bin".to_string()],
-                plugin_conf_dir: "/etc/cni/net.d".to_string(),
-                plugin_max_conf_num: 1,
-                prefix: "vethcni".to_string(),
-            },
-            cni_interface: Arc::new(Box::new(CNIConfig {
-                path: vec!["/opt/cni/bin".to_string()],
-                exec: RawExec::default(),
-                cache_dir: "/var/lib/cni/cache".to_string(),
-            })),
+            cni_interface: Self::new_cni_interface(&config),
+            config,
             network_count: 1,
             networks: Vec::default(),
         }
@@ -39,6 +46,70 @@ impl Default for Libcni {
 }
 
 impl Libcni {
+    fn new_cni_interface(config: &Config) -> Arc<Box<dyn CNI + Send + Sync>> {
+        Arc::new(Box::new(CNIConfig {
+            path: config.plugin_dirs.clone(),
+            exec: RawExec::default(),
+            cache_dir: config.cache_dir.clone(),
+            retry: config.retry.clone(),
+            registry: config.registry.clone(),
+            ..Default::default()
+        }))
+    }
+
+    // Networks hold their own handle, so they are re-pointed as well. Those
+    // using another implementation, like the native loopback, are kept.
+    fn rebuild_cni_interface(&mut self) {
+        let old = std::mem::replace(
+            &mut self.cni_interface,
+            Self::new_cni_interface(&self.config),
+        );
+        for net in &mut self.networks {
+            if Arc::ptr_eq(&net.cni, &old) {
+                net.cni = self.cni_interface.clone();
+            }
+        }
+    }
+
+    /// Native plugins to call in-process instead of exec'ing a binary.
+    pub fn set_plugin_registry(&mut self, registry: PluginRegistry) {
+        debug!("Setting native plugin registry: {:?}", registry);
+        self.config.registry = registry;
+        self.rebuild_cni_interface();
+    }
+
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        debug!("Setting plugin retry policy: {:?}", retry);
+        self.config.retry = retry;
+        self.rebuild_cni_interface();
+    }
+
+    pub fn set_setup_mode(&mut self, mode: SetupMode) {
+        debug!("Setting network setup mode: {:?}", mode);
+        self.config.setup_mode = mode;
+    }
+
+    /// Verifies each network against the namespace after attaching or
+    /// checking it, see `Network::verify`.
+    pub fn set_verify_results(&mut self, verify: bool) {
+        debug!("Setting result verification: {}", verify);
+        self.config.verify_results = verify;
+    }
+
+    pub fn set_max_conf_num(&mut self, max_conf_num: i64) {
+        debug!(
+            "Setting maximum number of loaded networks: {}",
+            max_conf_num
+        );
+        self.config.plugin_max_conf_num = max_conf_num;
+    }
+
+    /// Applies to networks loaded afterwards.
+    pub fn set_ifname_policy(&mut self, policy: IfnamePolicy) {
+        debug!("Setting interface naming policy: {:?}", policy);
+        self.config.ifname_policy = policy;
+    }
+
     pub fn load_default_conf(&mut self) {
         debug!(
             "Loading default CNI configuration from {}",
@@ -76,11 +147,7 @@ impl Libcni {
                         match libcni::conf::ConfigFile::read_configlist_file(configfile.clone()) {
                             Some(config) => {
                                 debug!("Loaded CNI network config: {}", config.name);
-                                networks.push(Network {
-                                    cni: self.cni_interface.clone(),
-                                    config,
-                                    ifname: self.config.prefix.clone() + &cnt.to_string(),
-                                });
+                                networks.push(self.new_network(networks.len(), config));
                             }
                             None => error!("Failed to read config list file: {}", configfile),
                         }
@@ -91,11 +158,7 @@ impl Libcni {
                                 // Convert single config to config list
                                 let config_list =
                                     libcni::conf::ConfigFile::convert_to_config_list(config);
-                                networks.push(Network {
-                                    cni: self.cni_interface.clone(),
-                                    config: config_list,
-                                    ifname: self.config.prefix.clone() + &cnt.to_string(),
-                                });
+                                networks.push(self.new_network(networks.len(), config_list));
                             }
                             None => error!("Failed to read config file: {}", configfile),
                         }
@@ -113,6 +176,22 @@ impl Libcni {
         }
     }
 
+    // Names the interface of the index-th loaded network
+    fn new_network(&self, index: usize, config: libcni::api::NetworkConfigList) -> Network {
+        let ifname = self
+            .config
+            .ifname_policy
+            .ifname(&self.config.prefix, index, &config);
+        if let Err(e) = validate_ifname(&ifname) {
+            error!("Network {}: {}", config.name, e);
+        }
+        Network {
+            cni: self.cni_interface.clone(),
+            config,
+            ifname,
+        }
+    }
+
     pub fn new(
         plugin_dirs: Option<Vec<String>>,
         conf_dir: Option<String>,
@@ -122,23 +201,24 @@ impl Libcni {
         let plugin_dirs = plugin_dirs.unwrap_or(vec!["/opt/cni/bin".to_string()]);
         let conf_dir = conf_dir.unwrap_or("/etc/cni/net.d".to_string());
         let cache_dir = cache_dir.unwrap_or("/var/lib/cni/cache".to_string());
+        let config = Config {
+            plugin_dirs,
+            plugin_conf_dir: conf_dir,
+            plugin_max_conf_num: 1,
+            prefix: "vethcni".to_string(),
+            cache_dir,
+            ..Default::default()
+        };
         Libcni {
-            config: Config {
-                plugin_dirs: plugin_dirs.clone(),
-                plugin_conf_dir: conf_dir.clone(),
-                plugin_max_conf_num: 1,
-                prefix: "vethcni".to_string(),
-            },
-            cni_interface: Arc::new(Box::new(CNIConfig {
-                path: plugin_dirs.clone(),
-                exec: RawExec::default(),
-                cache_dir: cache_dir.clone(),
-            })),
+            cni_interface: Self::new_cni_interface(&config),
+            config,
             network_count: 1,
             networks: Vec::default(),
         }
     }
 
+    /// Loads the networks of `conf_dir`. New `plugin_dirs` replace the
+    /// configured ones, the cache dir and the other settings are kept.
     pub fn load(
         &mut self,
         conf_dir: Option<String>,
@@ -154,11 +234,7 @@ impl Libcni {
             self.config.plugin_dirs = plugin_dirs;
 
             // Update CNI interface with new plugin paths
-            self.cni_interface = Arc::new(Box::new(CNIConfig {
-                path: self.config.plugin_dirs.clone(),
-                exec: RawExec::default(),
-                cache_dir: String::default(),
-            }));
+            self.rebuild_cni_interface();
         }
 
         self.load_default_conf();
@@ -167,6 +243,18 @@ impl Libcni {
 
     pub fn add_lo_network(&mut self) -> Result<(), String> {
         debug!("Adding loopback network configuration");
+        let cni = self.cni_interface.clone();
+        self.push_lo_network(cni)
+    }
+
+    /// Like `add_lo_network`, but brings `lo` up in-process instead of
+    /// exec'ing the loopback plugin binary.
+    pub fn add_native_lo_network(&mut self) -> Result<(), String> {
+        debug!("Adding native loopback network configuration");
+        self.push_lo_network(Arc::new(Box::new(NativeLoopback)))
+    }
+
+    fn push_lo_network(&mut self, cni: Arc<Box<dyn CNI + Send + Sync>>) -> Result<(), String> {
         let datas = r#"{
             "cniVersion": "0.3.1",
             "name": "cni-loopback",
@@ -180,7 +268,7 @@ impl Libcni {
             Ok(loconfig) => {
                 debug!("Loopback network configuration added");
                 self.networks.push(Network {
-                    cni: self.cni_interface.clone(),
+                    cni,
                     config: loconfig,
                     ifname: "lo".to_string(),
                 });
@@ -214,34 +302,45 @@ impl Libcni {
     }
 
     pub fn setup(&self, id: String, path: String) -> Result<(), String> {
+        self.setup_namespace(&Namespace::new(id, path))
+    }
+
+    /// Like `setup`, with the args and capabilities of `namespace` passed to
+    /// every network.
+    pub fn setup_namespace(&self, namespace: &Namespace) -> Result<(), String> {
+        let id = namespace.get_id();
         debug!("Setting up networks for container: {}", id);
 
         // Check status
         self.status()?;
 
-        // Create namespace
-        let namespace = Namespace::new(id.clone(), path.clone());
-
         // Attach networks
-        self.attach_networks(&namespace)?;
+        let targets: Vec<_> = self
+            .networks
+            .iter()
+            .map(|net| (net, namespace.clone()))
+            .collect();
+        self.attach_networks(&targets)?;
 
         debug!("Networks setup completed for container: {}", id);
         Ok(())
     }
 
     pub fn remove(&self, id: String, path: String) -> Result<(), String> {
+        self.remove_namespace(&Namespace::new(id, path))
+    }
+
+    pub fn remove_namespace(&self, namespace: &Namespace) -> Result<(), String> {
+        let id = namespace.get_id();
         debug!("Removing networks for container: {}", id);
 
         // Check status
         self.status()?;
 
-        // Create namespace
-        let namespace = Namespace::new(id.clone(), path.clone());
-
         // Remove networks
         let mut errors = Vec::new();
         for net in &self.networks {
-            match net.remove(&namespace) {
+            match net.remove(namespace) {
                 Ok(_) => debug!("Removed network {} for container {}", net.config.name, id),
                 Err(e) => {
                     let err_msg = format!(
@@ -262,18 +361,23 @@ impl Libcni {
     }
 
     pub fn check(&self, id: String, path: String) -> Result<(), String> {
+        self.check_namespace(&Namespace::new(id, path))
+    }
+
+    pub fn check_namespace(&self, namespace: &Namespace) -> Result<(), String> {
+        let id = namespace.get_id();
         debug!("Checking networks for container: {}", id);
 
         // Check status
         self.status()?;
 
-        // Create namespace
-        let namespace = Namespace::new(id.clone(), path.clone());
-
         // Check networks
         let mut errors = Vec::new();
         for net in &self.networks {
-            match net.check(&namespace) {
+            match net
+                .check(namespace)
+                .and_then(|_| self.verify_if_enabled(net, namespace))
+            {
                 Ok(_) => debug!(
                     "Network {} is correctly configured for container {}",
                     net.config.name, id
@@ -295,12 +399,273 @@ impl Libcni {
         Ok(())
     }
 
-    fn attach_networks(&self, ns: &Namespace) -> Result<(), String> {
-        debug!("Attaching {} networks", self.networks.len());
+    /// Verifies every network against the namespace, see `Network::verify`.
+    /// Returns the reports by network name, or an error listing what differs.
+    pub fn verify_namespace(
+        &self,
+        namespace: &Namespace,
+    ) -> Result<Vec<(String, VerifyReport)>, String> {
+        let id = namespace.get_id();
+        debug!("Verifying networks for container: {}", id);
 
+        // Check status
+        self.status()?;
+
+        let mut reports = Vec::new();
         let mut errors = Vec::new();
         for net in &self.networks {
-            match net.attach(ns) {
+            match net.verify(namespace) {
+                Ok(report) => {
+                    if !report.is_ok() {
+                        errors.push(format!(
+                            "Network {} verification failed for container {}: {}",
+                            net.config.name, id, report
+                        ));
+                    }
+                    reports.push((net.config.name.clone(), report));
+                }
+                Err(e) => errors.push(e),
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        debug!("Networks verification completed for container: {}", id);
+        Ok(reports)
+    }
+
+    /// Attaches only the selected networks. What was selected is recorded in
+    /// the cache dir, `remove_networks` and `check_networks` act on that.
+    pub fn setup_networks(
+        &self,
+        id: String,
+        path: String,
+        selections: Vec<NetworkSelection>,
+    ) -> Result<(), String> {
+        self.setup_namespace_networks(&Namespace::new(id, path), selections)
+    }
+
+    /// Like `setup_networks`. Per-network args and capabilities are added to
+    /// those of `namespace`.
+    pub fn setup_namespace_networks(
+        &self,
+        namespace: &Namespace,
+        selections: Vec<NetworkSelection>,
+    ) -> Result<(), String> {
+        let id = namespace.get_id();
+        debug!(
+            "Setting up {} selected networks for container: {}",
+            selections.len(),
+            id
+        );
+
+        // Check status
+        self.status()?;
+
+        let mut selected = Vec::new();
+        let mut attachments = Vec::new();
+        for selection in selections {
+            let (net, ns) = self.select_network(namespace, &selection)?;
+            if selected
+                .iter()
+                .any(|(other, _): &(Network, Namespace)| other.ifname == net.ifname)
+            {
+                return Err(format!(
+                    "Interface {} is selected more than once for container {}",
+                    net.ifname, id
+                ));
+            }
+            attachments.push(NetworkSelection {
+                ifname: Some(net.ifname.clone()),
+                ..selection
+            });
+            selected.push((net, ns));
+        }
+
+        // Recorded up front, so a failed setup can still be cleaned up
+        self.write_attachments(id, &attachments)?;
+
+        let targets: Vec<_> = selected.iter().map(|(net, ns)| (net, ns.clone())).collect();
+        self.attach_networks(&targets)?;
+
+        debug!("Selected networks setup completed for container: {}", id);
+        Ok(())
+    }
+
+    /// Removes the networks recorded by `setup_networks`, in reverse order.
+    pub fn remove_networks(&self, id: String, path: String) -> Result<(), String> {
+        self.remove_namespace_networks(&Namespace::new(id, path))
+    }
+
+    pub fn remove_namespace_networks(&self, namespace: &Namespace) -> Result<(), String> {
+        let id = namespace.get_id();
+        debug!("Removing selected networks for container: {}", id);
+
+        let attachments = match self.read_attachments(id)? {
+            Some(attachments) => attachments,
+            None => {
+                warn!("No networks recorded for container {}", id);
+                return Ok(());
+            }
+        };
+
+        let mut errors = Vec::new();
+        for attachment in attachments.iter().rev() {
+            let removed = self
+                .select_network(namespace, attachment)
+                .and_then(|(net, ns)| net.remove(&ns));
+            if let Err(e) = removed {
+                errors.push(format!(
+                    "Failed to remove network {} for container {}: {}",
+                    attachment.name, id, e
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+
+        let record = self.attachments_path(id)?;
+        if let Err(e) = fs::remove_file(&record) {
+            warn!("Failed to remove {}: {}", record.display(), e);
+        }
+        debug!("Selected networks removal completed for container: {}", id);
+        Ok(())
+    }
+
+    /// Checks the networks recorded by `setup_networks`.
+    pub fn check_networks(&self, id: String, path: String) -> Result<(), String> {
+        self.check_namespace_networks(&Namespace::new(id, path))
+    }
+
+    pub fn check_namespace_networks(&self, namespace: &Namespace) -> Result<(), String> {
+        let id = namespace.get_id();
+        debug!("Checking selected networks for container: {}", id);
+
+        let attachments = self
+            .read_attachments(id)?
+            .ok_or_else(|| format!("No networks recorded for container {}", id))?;
+
+        let mut errors = Vec::new();
+        for attachment in &attachments {
+            let checked = self
+                .select_network(namespace, attachment)
+                .and_then(|(net, ns)| {
+                    net.check(&ns)?;
+                    self.verify_if_enabled(&net, &ns)
+                });
+            if let Err(e) = checked {
+                errors.push(format!(
+                    "Network {} check failed for container {}: {}",
+                    attachment.name, id, e
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors.join("; "));
+        }
+        debug!("Selected networks check completed for container: {}", id);
+        Ok(())
+    }
+
+    // Resolves a selection against the loaded networks
+    fn select_network(
+        &self,
+        namespace: &Namespace,
+        selection: &NetworkSelection,
+    ) -> Result<(Network, Namespace), String> {
+        let net = self
+            .networks
+            .iter()
+            .find(|net| net.config.name == selection.name)
+            .ok_or_else(|| format!("No network named {} is loaded", selection.name))?;
+
+        let mut ns = namespace.clone();
+        for (key, value) in &selection.args {
+            ns.add_arg(key, value);
+        }
+        for (key, value) in &selection.capabilities {
+            ns.add_capability(key, value);
+        }
+
+        let net = Network {
+            cni: net.cni.clone(),
+            config: net.config.clone(),
+            ifname: selection
+                .ifname
+                .clone()
+                .unwrap_or_else(|| net.ifname.clone()),
+        };
+        Ok((net, ns))
+    }
+
+    fn attachments_path(&self, id: &str) -> Result<PathBuf, String> {
+        if id.is_empty() || id.contains('/') || id == "." || id == ".." {
+            return Err(format!("Invalid container id {:?}", id));
+        }
+        Ok(PathBuf::from(&self.config.cache_dir)
+            .join("attachments")
+            .join(format!("{}.json", id)))
+    }
+
+    fn write_attachments(&self, id: &str, attachments: &[NetworkSelection]) -> Result<(), String> {
+        let record = self.attachments_path(id)?;
+        debug!(
+            "Recording networks of container {} in {}",
+            id,
+            record.display()
+        );
+        if let Some(dir) = record.parent() {
+            fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let data = serde_json::to_vec(attachments)
+            .map_err(|e| format!("Failed to encode attachments: {}", e))?;
+        fs::write(&record, data).map_err(|e| format!("Failed to write {}: {}", record.display(), e))
+    }
+
+    fn read_attachments(&self, id: &str) -> Result<Option<Vec<NetworkSelection>>, String> {
+        let record = self.attachments_path(id)?;
+        let data = match fs::read(&record) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read {}: {}", record.display(), e)),
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse {}: {}", record.display(), e))
+    }
+
+    fn attach_networks(&self, targets: &[(&Network, Namespace)]) -> Result<(), String> {
+        debug!(
+            "Attaching {} networks in {:?} mode",
+            targets.len(),
+            self.config.setup_mode
+        );
+
+        // Nothing is attached unless every interface name is usable
+        for (net, _) in targets {
+            validate_ifname(&net.ifname)
+                .map_err(|e| format!("Failed to attach network {} : {}", net.config.name, e))?;
+        }
+
+        let results: Vec<_> = match self.config.setup_mode {
+            SetupMode::Serial => targets.iter().map(|(net, ns)| net.attach(ns)).collect(),
+            SetupMode::Parallel => Self::attach_networks_parallel(targets),
+        };
+        // Verified once every network is attached, a later one may add routes
+        let results = targets
+            .iter()
+            .zip(results)
+            .map(|((net, ns), result)| result.and_then(|_| self.verify_if_enabled(net, ns)));
+
+        // Errors are reported in load order, whatever order the chains finished in
+        let mut errors = Vec::new();
+        for ((net, _), result) in targets.iter().zip(results) {
+            match result {
                 Ok(_) => debug!("Attached network {} successfully", net.config.name),
                 Err(e) => {
                     let err_msg = format!("Failed to attach network {} : {}", net.config.name, e);
@@ -316,4 +681,48 @@ impl Libcni {
 
         Ok(())
     }
+
+    // Turns the mismatches of `Network::verify` into an error, when enabled
+    fn verify_if_enabled(&self, net: &Network, namespace: &Namespace) -> Result<(), String> {
+        if !self.config.verify_results {
+            return Ok(());
+        }
+        let report = net.verify(namespace)?;
+        if !report.is_ok() {
+            return Err(format!("Verification failed: {}", report));
+        }
+        Ok(())
+    }
+
+    fn attach_networks_parallel(targets: &[(&Network, Namespace)]) -> Vec<Result<(), String>> {
+        let mut results: Vec<Option<Result<(), String>>> = vec![None; targets.len()];
+
+        // Other plugins may rely on lo being up in the container
+        for (i, (net, ns)) in targets.iter().enumerate() {
+            if net.is_loopback() {
+                results[i] = Some(net.attach(ns));
+            }
+        }
+
+        thread::scope(|s| {
+            let handles: Vec<_> = targets
+                .iter()
+                .enumerate()
+                .filter(|(_, (net, _))| !net.is_loopback())
+                .map(|(i, (net, ns))| (i, s.spawn(move || net.attach(ns))))
+                .collect();
+            for (i, handle) in handles {
+                results[i] = Some(
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err("network attach thread panicked".to_string())),
+                );
+            }
+        });
+
+        results
+            .into_iter()
+            .map(|r| r.unwrap_or_else(|| Err("network was not attached".to_string())))
+            .collect()
+    }
 }
@@ -0,0 +1,129 @@
+use log::debug;
+
+use crate::libcni::{
+    exec::{parse_environ, Exec, RawExec},
+    result::{result100, ResultCNI},
+    types::NetConf,
+    CNIError,
+};
+use crate::skel::CmdArgs;
+
+/// Runs the IPAM plugin of a network on behalf of the plugin being invoked.
+/// The delegate gets the invocation's environment, with `CNI_COMMAND` set
+/// to the delegated command, and the parent config bytes on stdin.
+pub struct Delegate<E: Exec = RawExec> {
+    exec: E,
+    environ: Vec<String>,
+}
+
+impl Delegate<RawExec> {
+    /// Delegates with the environment of the current process.
+    pub fn from_env() -> Self {
+        Self::new(
+            RawExec::default(),
+            std::env::vars()
+                .map(|(k, v)| format!("{}={}", k, v))
+                .collect(),
+        )
+    }
+
+    /// Delegates with the invocation in `args`, for plugins that are not
+    /// running in a process of their own.
+    pub fn from_args(args: &CmdArgs) -> Self {
+        let cni_env = [
+            ("CNI_CONTAINERID", &args.container_id),
+            ("CNI_NETNS", &args.netns),
+            ("CNI_IFNAME", &args.ifname),
+            ("CNI_ARGS", &args.args),
+            ("CNI_PATH", &args.path),
+        ];
+        let mut environ: Vec<String> = std::env::vars()
+            .filter(|(k, _)| !cni_env.iter().any(|(key, _)| key == k))
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        for (k, v) in cni_env {
+            environ.push(format!("{}={}", k, v));
+        }
+        Self::new(RawExec::default(), environ)
+    }
+}
+
+impl<E: Exec> Delegate<E> {
+    pub fn new(exec: E, environ: Vec<String>) -> Self {
+        Delegate { exec, environ }
+    }
+
+    pub fn add(&self, plugin: &str, netconf: &[u8]) -> ResultCNI<result100::Result> {
+        let output = self.exec_delegate("ADD", plugin, netconf)?;
+        serde_json::from_slice(&output).map_err(|e| {
+            Box::new(CNIError::VarDecode(format!(
+                "failed to decode result of IPAM plugin {}: {}",
+                plugin, e
+            )))
+        })
+    }
+
+    pub fn check(&self, plugin: &str, netconf: &[u8]) -> ResultCNI<()> {
+        self.exec_delegate("CHECK", plugin, netconf).map(|_| ())
+    }
+
+    pub fn del(&self, plugin: &str, netconf: &[u8]) -> ResultCNI<()> {
+        self.exec_delegate("DEL", plugin, netconf).map(|_| ())
+    }
+
+    fn exec_delegate(&self, command: &str, plugin: &str, netconf: &[u8]) -> ResultCNI<Vec<u8>> {
+        let envs = parse_environ(&self.environ);
+        let paths: Vec<String> = envs
+            .get("CNI_PATH")
+            .map(|p| {
+                p.split(':')
+                    .filter(|p| !p.is_empty())
+                    .map(|p| p.to_string())
+                    .collect()
+            })
+            .unwrap_or_default();
+        let plugin_path = self.exec.find_in_path(plugin.to_string(), paths)?;
+
+        debug!("Delegating {} to IPAM plugin {}", command, plugin_path);
+        let mut environ: Vec<String> = self
+            .environ
+            .iter()
+            .filter(|e| !e.starts_with("CNI_COMMAND="))
+            .cloned()
+            .collect();
+        environ.push(format!("CNI_COMMAND={}", command));
+        self.exec.exec_plugins(plugin_path, netconf, environ)
+    }
+}
+
+/// The `ipam.type` of a network config.
+pub fn ipam_type(netconf: &[u8]) -> ResultCNI<String> {
+    let conf: NetConf = serde_json::from_slice(netconf).map_err(|e| {
+        Box::new(CNIError::VarDecode(format!(
+            "failed to decode config: {}",
+            e
+        )))
+    })?;
+    match conf.ipam {
+        Some(ipam) if !ipam._type.is_empty() => Ok(ipam._type),
+        _ => Err(Box::new(CNIError::Config(format!(
+            "network {} has no IPAM plugin",
+            conf.name
+        )))),
+    }
+}
+
+/// Runs ADD of `plugin` with the environment of the current process.
+pub fn exec_add(plugin: &str, netconf: &[u8]) -> ResultCNI<result100::Result> {
+    Delegate::from_env().add(plugin, netconf)
+}
+
+/// Runs CHECK of `plugin` with the environment of the current process.
+pub fn exec_check(plugin: &str, netconf: &[u8]) -> ResultCNI<()> {
+    Delegate::from_env().check(plugin, netconf)
+}
+
+/// Runs DEL of `plugin` with the environment of the current process.
+pub fn exec_del(plugin: &str, netconf: &[u8]) -> ResultCNI<()> {
+    Delegate::from_env().del(plugin, netconf)
+}
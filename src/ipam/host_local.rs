@@ -0,0 +1,384 @@
+use ipnetwork::IpNetwork;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs;
+use std::net::IpAddr;
+
+use super::range::{Range, RangeSet};
+use super::store::Store;
+use crate::libcni::{
+    plugin::{native_plugin_versions, PluginInfoT},
+    result::{result100, APIResult, ResultCNI},
+    types::{Route, DNS},
+    CNIError,
+};
+use crate::skel::{CmdArgs, NativeSkel, Plugin};
+
+/// The `ipam` section of a network using host-local.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostLocalConf {
+    #[serde(rename = "type", default)]
+    pub _type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ranges: Vec<RangeSet>,
+    // The single range of older configs, put in front of `ranges`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subnet: Option<IpNetwork>,
+    #[serde(
+        rename = "rangeStart",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub range_start: Option<IpAddr>,
+    #[serde(rename = "rangeEnd", default, skip_serializing_if = "Option::is_none")]
+    pub range_end: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+    #[serde(rename = "dataDir", default, skip_serializing_if = "String::is_empty")]
+    pub data_dir: String,
+    #[serde(
+        rename = "resolvConf",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub resolv_conf: String,
+    /// Addresses asked for with `IP` in CNI_ARGS or the `ips` capability.
+    #[serde(skip)]
+    pub requested_ips: Vec<IpAddr>,
+    /// Keys without a field, kept so the config survives a round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HostLocalConf {
+    pub fn new() -> Self {
+        HostLocalConf {
+            _type: "host-local".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a range set holding only `range`.
+    pub fn with_range(self, range: Range) -> Self {
+        self.with_range_set(RangeSet(vec![range]))
+    }
+
+    pub fn with_range_set(mut self, set: RangeSet) -> Self {
+        self.ranges.push(set);
+        self
+    }
+
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    pub fn with_data_dir(mut self, dir: impl Into<String>) -> Self {
+        self.data_dir = dir.into();
+        self
+    }
+
+    pub fn with_resolv_conf(mut self, path: impl Into<String>) -> Self {
+        self.resolv_conf = path.into();
+        self
+    }
+
+    /// Merges the older single range into `ranges` and checks the ranges.
+    pub fn canonicalize(&mut self) -> ResultCNI<()> {
+        if let Some(subnet) = self.subnet.take() {
+            let range = Range {
+                subnet,
+                range_start: self.range_start.take(),
+                range_end: self.range_end.take(),
+                gateway: self.gateway.take(),
+            };
+            self.ranges.insert(0, RangeSet(vec![range]));
+        }
+        if self.ranges.is_empty() {
+            return Err(Box::new(CNIError::Config(
+                "no IP ranges specified".to_string(),
+            )));
+        }
+        for set in &mut self.ranges {
+            set.canonicalize()?;
+        }
+        for (i, a) in self.ranges.iter().enumerate() {
+            if self.ranges[i + 1..].iter().any(|b| a.overlaps(b)) {
+                return Err(Box::new(CNIError::Config(format!(
+                    "range set {} overlaps with another range set",
+                    i
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct NetConf {
+    #[serde(default)]
+    name: String,
+    ipam: Option<HostLocalConf>,
+    #[serde(rename = "runtimeConfig", default)]
+    runtime_config: RuntimeConfig,
+}
+
+#[derive(Default, Deserialize)]
+struct RuntimeConfig {
+    #[serde(default)]
+    ips: Vec<String>,
+}
+
+/// Reads the network name and the canonical IPAM config from the network
+/// config, with the addresses requested in `cni_args`.
+pub fn load_conf(stdin: &[u8], cni_args: &str) -> ResultCNI<(String, HostLocalConf)> {
+    let conf: NetConf = serde_json::from_slice(stdin).map_err(|e| {
+        Box::new(CNIError::VarDecode(format!(
+            "failed to decode IPAM config: {}",
+            e
+        )))
+    })?;
+    let mut ipam = conf.ipam.ok_or_else(|| {
+        Box::new(CNIError::Config(
+            "IPAM config missing 'ipam' key".to_string(),
+        ))
+    })?;
+    ipam.canonicalize()?;
+
+    let from_args = cni_args
+        .split(';')
+        .filter_map(|kv| kv.split_once('='))
+        .filter(|(k, _)| *k == "IP")
+        .flat_map(|(_, v)| v.split(','));
+    for ip in conf
+        .runtime_config
+        .ips
+        .iter()
+        .map(|s| s.as_str())
+        .chain(from_args)
+    {
+        // Addresses may come with a prefix length
+        let ip = ip.split('/').next().unwrap_or_default();
+        let ip: IpAddr = ip
+            .parse()
+            .map_err(|_| Box::new(CNIError::Config(format!("invalid requested IP {:?}", ip))))?;
+        ipam.requested_ips.push(ip);
+    }
+    Ok((conf.name, ipam))
+}
+
+/// IPAM plugin compatible with the reference host-local plugin: same
+/// config and same allocation store.
+pub struct HostLocal;
+
+impl HostLocal {
+    pub fn plugin_info() -> PluginInfoT {
+        PluginInfoT {
+            cni_version: String::new(),
+            supported_versions: native_plugin_versions(),
+        }
+    }
+
+    /// host-local as a native plugin, for `PluginRegistry`.
+    pub fn native() -> NativeSkel<HostLocal> {
+        NativeSkel::new(HostLocal, Self::plugin_info())
+    }
+}
+
+impl Plugin for HostLocal {
+    fn add(&self, args: &CmdArgs) -> ResultCNI<Box<dyn APIResult>> {
+        let (name, conf) = load_conf(&args.stdin_data, &args.args)?;
+        for ip in &conf.requested_ips {
+            if !conf.ranges.iter().any(|set| set.contains(*ip)) {
+                return Err(Box::new(CNIError::Config(format!(
+                    "requested IP {} is not in any range",
+                    ip
+                ))));
+            }
+        }
+
+        // Before anything is reserved, a failed ADD is not followed by a DEL
+        let dns = if conf.resolv_conf.is_empty() {
+            None
+        } else {
+            Some(parse_resolv_conf(&conf.resolv_conf)?)
+        };
+
+        let store = Store::open(&conf.data_dir, &name)?;
+        let ips = store.locked(|store| {
+            let mut ips: Vec<result100::IPConfig> = Vec::new();
+            for (i, set) in conf.ranges.iter().enumerate() {
+                let requested: Vec<IpAddr> = conf
+                    .requested_ips
+                    .iter()
+                    .copied()
+                    .filter(|ip| set.contains(*ip))
+                    .collect();
+                let allocated = match requested.as_slice() {
+                    [] | [_] => allocate(store, set, i, args, requested.first().copied()),
+                    _ => Err(Box::new(CNIError::Config(format!(
+                        "cannot request more than one IP from range set {}",
+                        i
+                    )))),
+                };
+                match allocated {
+                    Ok(ip) => ips.push(ip),
+                    Err(e) => {
+                        // Only what this call reserved is given back
+                        for ip in ips.iter().filter_map(|ip| ip.address) {
+                            let _ = store.release(ip.ip());
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+            Ok(ips)
+        })?;
+
+        Ok(Box::new(result100::Result {
+            cni_version: None,
+            interfaces: None,
+            ips: Some(ips),
+            routes: (!conf.routes.is_empty()).then_some(conf.routes),
+            dns,
+        }))
+    }
+
+    fn del(&self, args: &CmdArgs) -> ResultCNI<()> {
+        let (name, conf) = load_conf(&args.stdin_data, &args.args)?;
+        let store = Store::open(&conf.data_dir, &name)?;
+        store.locked(|store| store.release_by_id(&args.container_id, &args.ifname))
+    }
+
+    fn check(&self, args: &CmdArgs) -> ResultCNI<()> {
+        let (name, conf) = load_conf(&args.stdin_data, &args.args)?;
+        let store = Store::open(&conf.data_dir, &name)?;
+        let ips = store.locked(|store| store.find_by_id(&args.container_id, &args.ifname))?;
+        if ips.is_empty() {
+            return Err(Box::new(CNIError::ExecuteError(format!(
+                "host-local: failed to find address added by container {}",
+                args.container_id
+            ))));
+        }
+        Ok(())
+    }
+
+    fn gc(&self, args: &CmdArgs) -> ResultCNI<()> {
+        #[derive(Deserialize)]
+        struct Attachment {
+            #[serde(rename = "containerID")]
+            container_id: String,
+            ifname: String,
+        }
+        #[derive(Deserialize)]
+        struct GcConf {
+            #[serde(rename = "cni.dev/valid-attachments", default)]
+            valid_attachments: Vec<Attachment>,
+        }
+
+        let (name, conf) = load_conf(&args.stdin_data, &args.args)?;
+        let valid: GcConf = serde_json::from_slice(&args.stdin_data)
+            .map_err(|e| Box::new(CNIError::VarDecode(e.to_string())))?;
+        let store = Store::open(&conf.data_dir, &name)?;
+        store.locked(|store| {
+            for allocation in store.allocations()? {
+                let in_use = valid
+                    .valid_attachments
+                    .iter()
+                    .any(|a| allocation.is_held_by(&a.container_id, &a.ifname));
+                if !in_use {
+                    debug!(
+                        "Collecting {} of {}/{}",
+                        allocation.ip, allocation.container_id, allocation.ifname
+                    );
+                    store.release(allocation.ip)?;
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn allocate(
+    store: &Store,
+    set: &RangeSet,
+    index: usize,
+    args: &CmdArgs,
+    requested: Option<IpAddr>,
+) -> ResultCNI<result100::IPConfig> {
+    let range_id = index.to_string();
+    let (id, ifname) = (&args.container_id, &args.ifname);
+    if let Some(ip) = store
+        .find_by_id(id, ifname)?
+        .into_iter()
+        .find(|ip| set.contains(*ip))
+    {
+        return Err(Box::new(CNIError::Config(format!(
+            "{} has been allocated to {}, duplicate allocation is not allowed",
+            ip, id
+        ))));
+    }
+
+    let (ip, range) = match requested {
+        Some(ip) => {
+            let range = set.range_for(ip).filter(|r| r.gateway != Some(ip));
+            let range = match range {
+                Some(range) if store.reserve(id, ifname, ip, &range_id)? => range,
+                _ => {
+                    return Err(Box::new(CNIError::ExecuteError(format!(
+                        "requested IP address {} is not available in range set {}",
+                        ip, index
+                    ))))
+                }
+            };
+            (ip, range)
+        }
+        None => {
+            let mut found = None;
+            for (ip, range) in set.iter_from(store.last_reserved_ip(&range_id)) {
+                if store.reserve(id, ifname, ip, &range_id)? {
+                    found = Some((ip, range));
+                    break;
+                }
+            }
+            found.ok_or_else(|| {
+                let ranges: Vec<String> = set.0.iter().map(|r| r.to_string()).collect();
+                Box::new(CNIError::ExecuteError(format!(
+                    "no IP addresses available in range set: {}",
+                    ranges.join(",")
+                )))
+            })?
+        }
+    };
+
+    Ok(result100::IPConfig {
+        interface: None,
+        address: IpNetwork::new(ip, range.subnet.prefix()).ok(),
+        gateway: range.gateway,
+    })
+}
+
+fn parse_resolv_conf(path: &str) -> ResultCNI<DNS> {
+    let data = fs::read_to_string(path).map_err(|e| Box::new(CNIError::Io(Box::new(e))))?;
+    let mut dns = DNS::default();
+    for line in data.lines() {
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("nameserver") => dns
+                .nameservers
+                .get_or_insert_with(Vec::new)
+                .extend(fields.map(|f| f.to_string())),
+            Some("domain") => dns.domain = fields.next().map(|d| d.to_string()),
+            Some("search") => dns.search = Some(fields.map(|f| f.to_string()).collect()),
+            Some("options") => dns
+                .options
+                .get_or_insert_with(Vec::new)
+                .extend(fields.map(|f| f.to_string())),
+            _ => {}
+        }
+    }
+    Ok(dns)
+}
@@ -0,0 +1,13 @@
+pub mod delegate;
+pub mod host_local;
+pub mod range;
+pub mod static_ipam;
+pub mod store;
+
+pub use delegate::{exec_add, exec_check, exec_del, ipam_type, Delegate};
+
+use crate::libcni::CNIError;
+
+fn config_error(msg: String) -> Box<CNIError> {
+    Box::new(CNIError::Config(msg))
+}
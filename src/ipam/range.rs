@@ -0,0 +1,241 @@
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use super::config_error;
+use crate::libcni::result::ResultCNI;
+
+/// A range of addresses to allocate from, as in the reference plugins.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Range {
+    pub subnet: IpNetwork,
+    #[serde(
+        rename = "rangeStart",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub range_start: Option<IpAddr>,
+    #[serde(rename = "rangeEnd", default, skip_serializing_if = "Option::is_none")]
+    pub range_end: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+}
+
+impl Range {
+    pub fn new(subnet: IpNetwork) -> Self {
+        Range {
+            subnet,
+            range_start: None,
+            range_end: None,
+            gateway: None,
+        }
+    }
+
+    pub fn with_range_start(mut self, ip: IpAddr) -> Self {
+        self.range_start = Some(ip);
+        self
+    }
+
+    pub fn with_range_end(mut self, ip: IpAddr) -> Self {
+        self.range_end = Some(ip);
+        self
+    }
+
+    pub fn with_gateway(mut self, ip: IpAddr) -> Self {
+        self.gateway = Some(ip);
+        self
+    }
+
+    /// Checks the range and fills in the defaults: the range covers the
+    /// whole subnet, but the network and IPv4 broadcast addresses, and the
+    /// gateway is the first address.
+    pub fn canonicalize(&mut self) -> ResultCNI<()> {
+        let subnet = self.subnet;
+        if subnet.ip() != subnet.network() {
+            return Err(config_error(format!(
+                "network {} has host bits set; did you mean {}/{}?",
+                subnet,
+                subnet.network(),
+                subnet.prefix()
+            )));
+        }
+        let bits: u32 = if subnet.is_ipv4() { 32 } else { 128 };
+        if subnet.prefix() as u32 > bits - 2 {
+            return Err(config_error(format!(
+                "network {} too small to allocate from",
+                subnet
+            )));
+        }
+
+        let network = to_u128(subnet.network());
+        let last = network | (u128::MAX >> (128 - bits + subnet.prefix() as u32));
+        let last = if subnet.is_ipv4() { last - 1 } else { last };
+
+        let start = self
+            .range_start
+            .unwrap_or_else(|| from_u128(network + 1, subnet.is_ipv4()));
+        let end = self
+            .range_end
+            .unwrap_or_else(|| from_u128(last, subnet.is_ipv4()));
+        for (what, ip) in [("rangeStart", start), ("rangeEnd", end)] {
+            if !subnet.contains(ip) {
+                return Err(config_error(format!(
+                    "{} {} not in network {}",
+                    what, ip, subnet
+                )));
+            }
+        }
+        if to_u128(start) > to_u128(end) {
+            return Err(config_error(format!(
+                "rangeStart {} is after rangeEnd {}",
+                start, end
+            )));
+        }
+        if let Some(gw) = self.gateway {
+            if !subnet.contains(gw) {
+                return Err(config_error(format!(
+                    "gateway {} not in network {}",
+                    gw, subnet
+                )));
+            }
+        }
+
+        self.range_start = Some(start);
+        self.range_end = Some(end);
+        self.gateway = self
+            .gateway
+            .or_else(|| Some(from_u128(network + 1, subnet.is_ipv4())));
+        Ok(())
+    }
+
+    /// Whether `ip` is between the start and the end of a canonical range.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.range_start, self.range_end) {
+            (Some(start), Some(end)) if ip.is_ipv4() == start.is_ipv4() => {
+                (to_u128(start)..=to_u128(end)).contains(&to_u128(ip))
+            }
+            _ => false,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Range) -> bool {
+        match (other.range_start, other.range_end) {
+            (Some(start), Some(end)) => {
+                self.contains(start)
+                    || self.contains(end)
+                    || other.contains(self.range_start.unwrap_or(start))
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.range_start, self.range_end) {
+            (Some(start), Some(end)) => write!(f, "{}-{}", start, end),
+            _ => write!(f, "{}", self.subnet),
+        }
+    }
+}
+
+/// Ranges of one address family, an address is allocated from one of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RangeSet(pub Vec<Range>);
+
+impl RangeSet {
+    pub fn canonicalize(&mut self) -> ResultCNI<()> {
+        if self.0.is_empty() {
+            return Err(config_error("empty range set".to_string()));
+        }
+        for range in &mut self.0 {
+            range.canonicalize()?;
+        }
+        let v4 = self.0[0].subnet.is_ipv4();
+        if self.0.iter().any(|r| r.subnet.is_ipv4() != v4) {
+            return Err(config_error(
+                "mixed address families in a range set".to_string(),
+            ));
+        }
+        for (i, a) in self.0.iter().enumerate() {
+            if let Some(b) = self.0[i + 1..].iter().find(|b| a.overlaps(b)) {
+                return Err(config_error(format!("range {} overlaps with {}", a, b)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The range `ip` belongs to.
+    pub fn range_for(&self, ip: IpAddr) -> Option<&Range> {
+        self.0.iter().find(|r| r.contains(ip))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.range_for(ip).is_some()
+    }
+
+    pub fn overlaps(&self, other: &RangeSet) -> bool {
+        self.0.iter().any(|a| other.0.iter().any(|b| a.overlaps(b)))
+    }
+
+    /// Every address of the set once, starting after `last` if it is in the
+    /// set and at the first address otherwise. Gateways are skipped.
+    pub fn iter_from(&self, last: Option<IpAddr>) -> impl Iterator<Item = (IpAddr, &Range)> {
+        let ranges = &self.0;
+        let bounds = |r: &Range| {
+            (
+                to_u128(r.range_start.unwrap_or(r.subnet.network())),
+                to_u128(r.range_end.unwrap_or(r.subnet.network())),
+            )
+        };
+        let next = move |(ri, ip): (usize, u128)| {
+            if ip >= bounds(&ranges[ri]).1 {
+                let ri = (ri + 1) % ranges.len();
+                (ri, bounds(&ranges[ri]).0)
+            } else {
+                (ri, ip + 1)
+            }
+        };
+
+        let last = last.and_then(|ip| Some((ranges.iter().position(|r| r.contains(ip))?, ip)));
+        let first = match last {
+            Some((ri, ip)) => next((ri, to_u128(ip))),
+            None => (0, ranges.first().map(|r| bounds(r).0).unwrap_or_default()),
+        };
+        let total: u128 = ranges
+            .iter()
+            .map(|r| {
+                let (start, end) = bounds(r);
+                end - start + 1
+            })
+            .fold(0, u128::saturating_add);
+
+        let v4 = ranges.first().is_some_and(|r| r.subnet.is_ipv4());
+        let mut pos = first;
+        (0..total.min(usize::MAX as u128) as usize)
+            .map(move |_| {
+                let cur = pos;
+                pos = next(pos);
+                cur
+            })
+            .map(move |(ri, ip)| (from_u128(ip, v4), &ranges[ri]))
+            .filter(|(ip, range)| range.gateway != Some(*ip))
+    }
+}
+
+pub(crate) fn to_u128(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u32::from(ip) as u128,
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+pub(crate) fn from_u128(ip: u128, v4: bool) -> IpAddr {
+    if v4 {
+        IpAddr::V4(Ipv4Addr::from(ip as u32))
+    } else {
+        IpAddr::V6(Ipv6Addr::from(ip))
+    }
+}
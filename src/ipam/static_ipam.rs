@@ -0,0 +1,245 @@
+use ipnetwork::IpNetwork;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::net::IpAddr;
+
+use super::config_error;
+use crate::libcni::{
+    plugin::{native_plugin_versions, PluginInfoT},
+    result::{result100, APIResult, ResultCNI},
+    types::{Route, DNS},
+    CNIError,
+};
+use crate::namespace::NetNs;
+use crate::skel::{CmdArgs, NativeSkel, Plugin};
+use crate::verify::read_state;
+
+/// An address of the `static` plugin, always in CIDR notation.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticAddress {
+    #[serde(deserialize_with = "deserialize_cidr")]
+    pub address: IpNetwork,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<IpAddr>,
+}
+
+/// The `ipam` section of a network using static.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StaticConf {
+    #[serde(rename = "type", default)]
+    pub _type: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub addresses: Vec<StaticAddress>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DNS>,
+    /// Keys without a field, kept so the config survives a round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl StaticConf {
+    pub fn new() -> Self {
+        StaticConf {
+            _type: "static".to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_address(mut self, address: IpNetwork, gateway: Option<IpAddr>) -> Self {
+        self.addresses.push(StaticAddress { address, gateway });
+        self
+    }
+
+    pub fn with_route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self
+    }
+
+    pub fn with_dns(mut self, dns: DNS) -> Self {
+        self.dns = Some(dns);
+        self
+    }
+
+    /// Checks that gateways and routes match the family of what they are for.
+    pub fn validate(&self) -> ResultCNI<()> {
+        if self.addresses.is_empty() {
+            return Err(config_error("no addresses given".to_string()));
+        }
+        for addr in &self.addresses {
+            if let Some(gw) = addr.gateway {
+                if gw.is_ipv4() != addr.address.is_ipv4() {
+                    return Err(config_error(format!(
+                        "gateway {} is not of the family of address {}",
+                        gw, addr.address
+                    )));
+                }
+            }
+        }
+        for route in &self.routes {
+            if let (Some(dst), Some(gw)) = (route.dst, route.gw) {
+                if gw.is_ipv4() != dst.is_ipv4() {
+                    return Err(config_error(format!(
+                        "gateway {} is not of the family of route {}",
+                        gw, dst
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct NetConf {
+    ipam: Option<StaticConf>,
+    #[serde(rename = "runtimeConfig", default)]
+    runtime_config: RuntimeConfig,
+}
+
+#[derive(Default, Deserialize)]
+struct RuntimeConfig {
+    #[serde(default)]
+    ips: Vec<String>,
+}
+
+/// Reads the IPAM config, adding the addresses of `IP` in `cni_args` and of
+/// the `ips` capability, and setting the gateways of `GATEWAY` on the
+/// addresses whose subnet holds them.
+pub fn load_conf(stdin: &[u8], cni_args: &str) -> ResultCNI<StaticConf> {
+    let conf: NetConf = serde_json::from_slice(stdin).map_err(|e| {
+        Box::new(CNIError::VarDecode(format!(
+            "failed to decode IPAM config: {}",
+            e
+        )))
+    })?;
+    let mut ipam = conf
+        .ipam
+        .ok_or_else(|| config_error("IPAM config missing 'ipam' key".to_string()))?;
+
+    let args: Vec<(&str, &str)> = cni_args
+        .split(';')
+        .filter_map(|kv| kv.split_once('='))
+        .collect();
+    let values = |key: &str| -> Vec<String> {
+        args.iter()
+            .filter(|(k, _)| *k == key)
+            .flat_map(|(_, v)| v.split(','))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect()
+    };
+
+    for address in values("IP").iter().chain(&conf.runtime_config.ips) {
+        ipam.addresses.push(StaticAddress {
+            address: parse_cidr(address)?,
+            gateway: None,
+        });
+    }
+    for gateway in values("GATEWAY") {
+        let gw: IpAddr = gateway
+            .parse()
+            .map_err(|_| config_error(format!("invalid gateway {:?}", gateway)))?;
+        for addr in ipam.addresses.iter_mut().filter(|a| a.address.contains(gw)) {
+            addr.gateway = Some(gw);
+        }
+    }
+
+    ipam.validate()?;
+    Ok(ipam)
+}
+
+fn parse_cidr(address: &str) -> ResultCNI<IpNetwork> {
+    match address.parse() {
+        Ok(net) if address.contains('/') => Ok(net),
+        _ => Err(config_error(format!(
+            "invalid address {:?}, expected CIDR notation",
+            address
+        ))),
+    }
+}
+
+fn deserialize_cidr<'de, D: serde::Deserializer<'de>>(d: D) -> Result<IpNetwork, D::Error> {
+    let address = String::deserialize(d)?;
+    parse_cidr(&address).map_err(serde::de::Error::custom)
+}
+
+/// IPAM plugin handing out the addresses it is given, like the reference
+/// `static` plugin. Nothing is stored, DEL has nothing to do.
+pub struct StaticIpam;
+
+impl StaticIpam {
+    pub fn plugin_info() -> PluginInfoT {
+        PluginInfoT {
+            cni_version: String::new(),
+            supported_versions: native_plugin_versions(),
+        }
+    }
+
+    /// static as a native plugin, for `PluginRegistry`.
+    pub fn native() -> NativeSkel<StaticIpam> {
+        NativeSkel::new(StaticIpam, Self::plugin_info())
+    }
+}
+
+impl Plugin for StaticIpam {
+    fn add(&self, args: &CmdArgs) -> ResultCNI<Box<dyn APIResult>> {
+        let conf = load_conf(&args.stdin_data, &args.args)?;
+        let ips = conf
+            .addresses
+            .iter()
+            .map(|addr| result100::IPConfig {
+                interface: None,
+                address: Some(addr.address),
+                gateway: addr.gateway,
+            })
+            .collect();
+        Ok(Box::new(result100::Result {
+            cni_version: None,
+            interfaces: None,
+            ips: Some(ips),
+            routes: (!conf.routes.is_empty()).then_some(conf.routes),
+            dns: conf.dns,
+        }))
+    }
+
+    fn del(&self, args: &CmdArgs) -> ResultCNI<()> {
+        load_conf(&args.stdin_data, &args.args).map(|_| ())
+    }
+
+    /// Checks that the addresses are on the interface in the namespace.
+    fn check(&self, args: &CmdArgs) -> ResultCNI<()> {
+        let conf = load_conf(&args.stdin_data, &args.args)?;
+        let netns = NetNs::open(&args.netns).map_err(|e| Box::new(CNIError::ExecuteError(e)))?;
+        let (links, _) = netns
+            .run(read_state)
+            .and_then(|state| state)
+            .map_err(|e| Box::new(CNIError::ExecuteError(e)))?;
+
+        let link = links
+            .iter()
+            .find(|l| l.name == args.ifname)
+            .ok_or_else(|| {
+                Box::new(CNIError::ExecuteError(format!(
+                    "interface {} not found in {}",
+                    args.ifname, args.netns
+                )))
+            })?;
+        for addr in &conf.addresses {
+            debug!("Checking {} on {}", addr.address, link.name);
+            let found = link
+                .addresses
+                .iter()
+                .any(|a| a.ip() == addr.address.ip() && a.prefix() == addr.address.prefix());
+            if !found {
+                return Err(Box::new(CNIError::ExecuteError(format!(
+                    "address {} not found on {}",
+                    addr.address, args.ifname
+                ))));
+            }
+        }
+        Ok(())
+    }
+}
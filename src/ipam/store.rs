@@ -0,0 +1,168 @@
+use log::{debug, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::net::IpAddr;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::libcni::{result::ResultCNI, CNIError};
+
+pub const DEFAULT_DATA_DIR: &str = "/var/lib/cni/networks";
+const LAST_RESERVED_IP: &str = "last_reserved_ip.";
+const LOCK_FILE: &str = "lock";
+const LINE_BREAK: &str = "\r\n";
+
+/// Allocations of one network in the layout of the reference disk store:
+/// one file per address holding the container ID and interface name, the
+/// last address reserved per range set, and a `lock` file.
+pub struct Store {
+    dir: PathBuf,
+    lock: File,
+}
+
+/// An address held by an attachment.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub ip: IpAddr,
+    pub container_id: String,
+    pub ifname: String,
+}
+
+impl Allocation {
+    /// Whether the address belongs to the attachment. Allocations of older
+    /// stores have no ifname and belong to every ifname of their container.
+    pub fn is_held_by(&self, id: &str, ifname: &str) -> bool {
+        self.container_id == id && (self.ifname.is_empty() || self.ifname == ifname)
+    }
+}
+
+impl Store {
+    /// Opens the store of `network` under `data_dir`, creating it if needed.
+    pub fn open(data_dir: &str, network: &str) -> ResultCNI<Self> {
+        if network.is_empty() || network.contains('/') || network == "." || network == ".." {
+            return Err(Box::new(CNIError::Config(format!(
+                "invalid network name {:?}",
+                network
+            ))));
+        }
+        let data_dir = if data_dir.is_empty() {
+            DEFAULT_DATA_DIR
+        } else {
+            data_dir
+        };
+        let dir = Path::new(data_dir).join(network);
+        fs::create_dir_all(&dir).map_err(io_error)?;
+        let lock = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(dir.join(LOCK_FILE))
+            .map_err(io_error)?;
+        Ok(Store { dir, lock })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Runs `f` holding the lock of the store, against other processes too.
+    pub fn locked<T>(&self, f: impl FnOnce(&Self) -> ResultCNI<T>) -> ResultCNI<T> {
+        if unsafe { libc::flock(self.lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(io_error(std::io::Error::last_os_error()));
+        }
+        let result = f(self);
+        unsafe { libc::flock(self.lock.as_raw_fd(), libc::LOCK_UN) };
+        result
+    }
+
+    /// Records `ip` for the attachment. Returns false if it is taken.
+    pub fn reserve(&self, id: &str, ifname: &str, ip: IpAddr, range_id: &str) -> ResultCNI<bool> {
+        let path = self.dir.join(ip.to_string());
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(io_error(e)),
+        };
+        if let Err(e) = file.write_all(format!("{}{}{}", id, LINE_BREAK, ifname).as_bytes()) {
+            let _ = fs::remove_file(&path);
+            return Err(io_error(e));
+        }
+
+        debug!("Reserved {} for {}/{}", ip, id, ifname);
+        fs::write(
+            self.dir.join(format!("{}{}", LAST_RESERVED_IP, range_id)),
+            ip.to_string(),
+        )
+        .map_err(io_error)?;
+        Ok(true)
+    }
+
+    pub fn last_reserved_ip(&self, range_id: &str) -> Option<IpAddr> {
+        fs::read_to_string(self.dir.join(format!("{}{}", LAST_RESERVED_IP, range_id)))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    }
+
+    pub fn release(&self, ip: IpAddr) -> ResultCNI<()> {
+        match fs::remove_file(self.dir.join(ip.to_string())) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Releases every address of the attachment.
+    pub fn release_by_id(&self, id: &str, ifname: &str) -> ResultCNI<()> {
+        for ip in self.find_by_id(id, ifname)? {
+            debug!("Releasing {} of {}/{}", ip, id, ifname);
+            self.release(ip)?;
+        }
+        Ok(())
+    }
+
+    pub fn find_by_id(&self, id: &str, ifname: &str) -> ResultCNI<Vec<IpAddr>> {
+        Ok(self
+            .allocations()?
+            .into_iter()
+            .filter(|a| a.is_held_by(id, ifname))
+            .map(|a| a.ip)
+            .collect())
+    }
+
+    pub fn allocations(&self) -> ResultCNI<Vec<Allocation>> {
+        let mut allocations = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            let ip = match entry.file_name().to_str().and_then(|n| n.parse().ok()) {
+                Some(ip) => ip,
+                // The lock and last reserved files
+                None => continue,
+            };
+            let data = match fs::read_to_string(entry.path()) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("Failed to read allocation {}: {}", ip, e);
+                    continue;
+                }
+            };
+            // Older stores only hold the container ID
+            let (container_id, ifname) = match data.trim().split_once(LINE_BREAK) {
+                Some((id, ifname)) => (id.trim(), ifname.trim()),
+                None => (data.trim(), ""),
+            };
+            allocations.push(Allocation {
+                ip,
+                container_id: container_id.to_string(),
+                ifname: ifname.to_string(),
+            });
+        }
+        allocations.sort_by_key(|a| super::range::to_u128(a.ip));
+        Ok(allocations)
+    }
+}
+
+fn io_error(e: std::io::Error) -> Box<CNIError> {
+    Box::new(CNIError::Io(Box::new(e)))
+}
@@ -1,6 +1,10 @@
 pub mod cni;
+pub mod ipam;
 pub mod libcni;
+pub mod loopback;
 pub mod namespace;
+pub mod skel;
+pub mod verify;
 
 pub fn is_debug_logging() -> bool {
     log::log_enabled!(log::Level::Debug)
@@ -8,7 +12,6 @@ pub fn is_debug_logging() -> bool {
 
 #[cfg(test)]
 pub mod test {
-    use super::*;
     use crate::cni;
     use env_logger::Env;
     use log::{error, info};
@@ -2,12 +2,18 @@
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 
+use super::conf::{ConfigFile, ConfigUpgrade, NetworkConfigListBuilder};
 use super::CNIError;
 
 use super::exec::{Exec, ExecArgs, RawExec};
+use super::plugin::{version_at_least, PluginInfoT};
+use super::plugin_cache::{PluginCacheStats, PluginInfoCache};
+use super::registry::{PluginRegistry, PluginTarget};
+use super::retry::RetryPolicy;
+use super::validate::validate_runtime_conf;
 use crate::libcni::result::result100;
 use crate::libcni::result::{APIResult, ResultCNI};
-use crate::libcni::types::NetworkConfig;
+use crate::libcni::types::{NetworkConfig, PluginDirs};
 use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
@@ -30,6 +36,12 @@ pub trait CNI {
         rt: RuntimeConf,
     ) -> ResultCNI<Box<dyn APIResult>>;
 
+    /// Asks the plugins of `net` whether they are ready to service requests.
+    /// Implementations without STATUS support report the network as ready.
+    fn get_status_network_list(&self, _net: NetworkConfigList) -> ResultCNI<()> {
+        Ok(())
+    }
+
     fn add_network(
         &self,
         name: String,
@@ -79,10 +91,20 @@ pub struct NetworkConfigList {
     pub cni_version: String,
     pub disable_check: bool,
     pub plugins: Vec<NetworkConfig>,
+    pub plugin_dirs: PluginDirs,
+    /// Interface name requested by the config, overriding the runtime's naming policy.
+    pub ifname: Option<String>,
     pub bytes: Vec<u8>,
 }
 
 impl NetworkConfigList {
+    pub fn builder(
+        name: impl Into<String>,
+        cni_version: impl Into<String>,
+    ) -> NetworkConfigListBuilder {
+        NetworkConfigListBuilder::new(name, cni_version)
+    }
+
     pub fn validate(&self) -> Result<(), String> {
         if self.name.is_empty() {
             return Err("Network name cannot be empty".to_string());
@@ -95,6 +117,46 @@ impl NetworkConfigList {
         }
         Ok(())
     }
+
+    /// The conflist JSON of the fields, with sorted keys, as
+    /// `ConfigFile::config_from_bytes` reads it back.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut conf = serde_json::Map::new();
+        conf.insert("cniVersion".to_string(), self.cni_version.clone().into());
+        conf.insert("name".to_string(), self.name.clone().into());
+        if self.disable_check {
+            conf.insert("disableCheck".to_string(), true.into());
+        }
+        match &self.plugin_dirs {
+            PluginDirs::Inherit => {}
+            PluginDirs::Extend(dirs) => {
+                conf.insert("pluginDirs".to_string(), dirs.clone().into());
+            }
+            PluginDirs::Override(dirs) => {
+                conf.insert("pluginDirs".to_string(), dirs.clone().into());
+                conf.insert("pluginDirsOverride".to_string(), true.into());
+            }
+        }
+        if let Some(ifname) = &self.ifname {
+            conf.insert("interfaceName".to_string(), ifname.clone().into());
+        }
+
+        let mut plugins = Vec::new();
+        for plugin in &self.plugins {
+            let value: serde_json::Value = serde_json::from_slice(&plugin.bytes).map_err(|e| {
+                format!("Invalid config of plugin {:?}: {}", plugin.network._type, e)
+            })?;
+            if !value.is_object() {
+                return Err(format!(
+                    "Config of plugin {:?} is not an object",
+                    plugin.network._type
+                ));
+            }
+            plugins.push(value);
+        }
+        conf.insert("plugins".to_string(), plugins.into());
+        serde_json::to_vec(&conf).map_err(|e| e.to_string())
+    }
 }
 
 #[derive(Clone, Default, Serialize, Deserialize)]
@@ -117,6 +179,15 @@ impl RuntimeConf {
 
         format!("{}-{}", id_part, self.if_name)
     }
+
+    /// `args` in the `K1=V1;K2=V2` form of the CNI_ARGS variable.
+    pub fn cni_args(&self) -> String {
+        self.args
+            .iter()
+            .map(|arg| format!("{}={}", arg[0], arg[1]))
+            .collect::<Vec<_>>()
+            .join(";")
+    }
 }
 
 #[derive(Default)]
@@ -124,9 +195,39 @@ pub struct CNIConfig {
     pub path: Vec<String>,
     pub exec: RawExec,
     pub cache_dir: String,
+    pub plugin_cache: PluginInfoCache,
+    pub retry: RetryPolicy,
+    pub registry: PluginRegistry,
 }
 
 impl CNIConfig {
+    pub fn plugin_cache_stats(&self) -> PluginCacheStats {
+        self.plugin_cache.stats()
+    }
+
+    /// Upgrades `net` to the `target` version if all its plugins support it.
+    pub fn upgrade_network_list(
+        &self,
+        net: &NetworkConfigList,
+        target: &str,
+    ) -> ResultCNI<ConfigUpgrade> {
+        let paths = net.plugin_dirs.resolve(&self.path);
+        ConfigFile::upgrade(net, target, |plugin| {
+            self.validate_network_in(&paths, plugin.clone())
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| Box::new(CNIError::Config(e)))
+    }
+
+    /// Turns a single plugin config into a conflist of the `target` version.
+    pub fn upgrade_network(&self, net: &NetworkConfig, target: &str) -> ResultCNI<ConfigUpgrade> {
+        ConfigFile::upgrade_conf(net, target, |plugin| {
+            self.validate_network_in(&self.path, plugin.clone())
+                .map_err(|e| e.to_string())
+        })
+        .map_err(|e| Box::new(CNIError::Config(e)))
+    }
+
     fn get_cache_dir(&self, netname: &str) -> std::path::PathBuf {
         let cache_dir = if self.cache_dir.is_empty() {
             "/var/lib/cni/cache".to_string()
@@ -143,7 +244,7 @@ impl CNIConfig {
         path
     }
 
-    fn cache_network_config(
+    pub(crate) fn cache_network_config(
         &self,
         network_name: &str,
         rt: &RuntimeConf,
@@ -164,7 +265,7 @@ impl CNIConfig {
         Ok(())
     }
 
-    fn cache_network_result(
+    pub(crate) fn cache_network_result(
         &self,
         network_name: &str,
         rt: &RuntimeConf,
@@ -189,7 +290,7 @@ impl CNIConfig {
     }
 
     #[allow(clippy::type_complexity)]
-    fn read_cached_network(
+    pub(crate) fn read_cached_network(
         &self,
         netname: &str,
         rt: &RuntimeConf,
@@ -209,33 +310,86 @@ impl CNIConfig {
         let config_bytes = fs::read(&config_path).map_err(|e| e.to_string())?;
 
         // Parse result
-        let _: serde_json::Value = serde_json::from_slice(&result_bytes)
+        let mut result: result100::Result = serde_json::from_slice(&result_bytes)
             .map_err(|e| format!("Failed to parse result cache: {}", e))?;
-
-        // Create result object
-        let result = result100::Result {
-            cni_version: Some(
+        if result.cni_version.is_none() {
+            result.cni_version = Some(
                 rt.args
                     .iter()
                     .find(|arg| arg[0] == "cniVersion")
                     .map(|arg| arg[1].clone())
                     .unwrap_or_else(|| "0.3.1".to_string()),
-            ),
-            ..Default::default()
-        };
+            );
+        }
 
         let result = Box::new(result) as Box<dyn APIResult>;
 
         Ok((result, config_bytes, rt.clone()))
     }
 
-    fn build_new_config(
+    pub(crate) fn remove_cached_network(&self, netname: &str, rt: &RuntimeConf) {
+        let cache_dir = self.get_cache_dir(netname);
+        let key = rt.get_cache_key();
+        let result_path = cache_dir.join(format!("{}.result", key));
+        let config_path = cache_dir.join(format!("{}.config", key));
+
+        if result_path.exists() {
+            debug!("Removing cached result: {}", result_path.display());
+            if let Err(e) = fs::remove_file(&result_path) {
+                warn!("Failed to remove cached result: {}", e);
+            }
+        }
+
+        if config_path.exists() {
+            debug!("Removing cached config: {}", config_path.display());
+            if let Err(e) = fs::remove_file(&config_path) {
+                warn!("Failed to remove cached config: {}", e);
+            }
+        }
+    }
+
+    /// Looks `plugin` up in the registry and `paths`, in the registry's order.
+    pub(crate) fn resolve_plugin(&self, plugin: &str, paths: &[String]) -> ResultCNI<PluginTarget> {
+        self.registry.resolve(plugin, || {
+            self.exec.find_in_path(plugin.to_string(), paths.to_vec())
+        })
+    }
+
+    fn exec_plugin(
+        &self,
+        plugin: &PluginTarget,
+        stdin_data: &[u8],
+        environ: Vec<String>,
+    ) -> ResultCNI<Vec<u8>> {
+        match plugin {
+            PluginTarget::Native(name, native) => {
+                PluginTarget::exec_native(name, native.as_ref(), stdin_data, &environ)
+            }
+            PluginTarget::External(path) => {
+                self.exec.exec_plugins(path.clone(), stdin_data, environ)
+            }
+        }
+    }
+
+    pub(crate) fn exec_args(command: &str, rt: &RuntimeConf, paths: &[String]) -> ExecArgs {
+        ExecArgs {
+            command: command.to_string(),
+            containerd_id: rt.container_id.clone(),
+            netns: rt.net_ns.clone(),
+            plugin_args: rt.args.clone(),
+            plugin_args_str: rt.cni_args(),
+            ifname: rt.if_name.clone(),
+            path: paths.join(":"),
+        }
+    }
+
+    pub(crate) fn build_new_config(
         &self,
         name: String,
         cni_version: String,
         orig: &NetworkConfig,
         prev_result: Option<Box<dyn APIResult>>,
-        _rt: &RuntimeConf,
+        rt: &RuntimeConf,
     ) -> Result<NetworkConfig, String> {
         debug!("Building new network config for {}", name);
 
@@ -262,6 +416,24 @@ impl CNIConfig {
             }
         }
 
+        // Only capabilities the plugin declares are passed on in runtimeConfig
+        let mut runtime_config = json::JsonValue::new_object();
+        for (key, value) in &rt.capability_args {
+            if !orig.network.capabilities.get(key).copied().unwrap_or(false) {
+                continue;
+            }
+            // Values are JSON documents, e.g. a list of port mappings
+            let value = json::parse(value).unwrap_or_else(|_| value.as_str().into());
+            if let Err(e) = runtime_config.insert(key, value) {
+                return Err(format!("Failed to insert runtimeConfig {}: {}", key, e));
+            }
+        }
+        if !runtime_config.is_empty() {
+            if let Err(e) = json_object.insert("runtimeConfig", runtime_config) {
+                return Err(format!("Failed to insert runtimeConfig: {}", e));
+            }
+        }
+
         let new_bytes = json_object.dump().as_bytes().to_vec();
         debug!("Built new config: {}", String::from_utf8_lossy(&new_bytes));
 
@@ -271,6 +443,226 @@ impl CNIConfig {
 
         Ok(new_conf)
     }
+
+    fn add_network_in(
+        &self,
+        paths: &[String],
+        name: String,
+        cni_version: String,
+        net: NetworkConfig,
+        prev_result: Option<Box<dyn APIResult>>,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        debug!("Adding network {} with plugin {}", name, net.network._type);
+
+        // Find plugin path
+        let plugin = self.resolve_plugin(&net.network._type, paths)?;
+
+        // Setup environment
+        let environ = Self::exec_args("ADD", &rt, paths);
+
+        // Build new config with name, version and prevResult
+        let new_conf = match self.build_new_config(
+            name.clone(),
+            cni_version.clone(),
+            &net,
+            prev_result,
+            &rt,
+        ) {
+            Ok(conf) => conf,
+            Err(e) => return Err(Box::new(CNIError::Config(e))),
+        };
+
+        // Cache network config
+        if let Err(e) = self.cache_network_config(&name, &rt, &new_conf.bytes) {
+            warn!("Failed to cache network config: {}", e);
+        }
+
+        // Execute plugin
+        let env = environ.to_env();
+        let result_bytes = self.retry.run(&format!("ADD {}", name), || {
+            self.exec_plugin(&plugin, &new_conf.bytes, env.clone())
+        })?;
+
+        let result = parse_add_result(&result_bytes, cni_version);
+
+        debug!("Successfully added network {}", name);
+        Ok(Box::new(result))
+    }
+
+    fn check_network_in(
+        &self,
+        paths: &[String],
+        name: String,
+        cni_version: String,
+        prev_result: Option<Box<dyn APIResult>>,
+        net: NetworkConfig,
+        rt: RuntimeConf,
+    ) -> ResultCNI<()> {
+        debug!(
+            "Checking network {} with plugin {}",
+            name, net.network._type
+        );
+
+        // Find plugin in the registry or path
+        let plugin = self.resolve_plugin(&net.network._type, paths)?;
+
+        // Set up environment
+        let environ = Self::exec_args("CHECK", &rt, paths);
+
+        // Build new config with name, version and prevResult
+        let new_conf =
+            match self.build_new_config(name.clone(), cni_version, &net, prev_result, &rt) {
+                Ok(conf) => conf,
+                Err(e) => return Err(Box::new(CNIError::Config(e))),
+            };
+
+        // Execute plugin
+        let env = environ.to_env();
+        self.retry.run(&format!("CHECK {}", name), || {
+            self.exec_plugin(&plugin, &new_conf.bytes, env.clone())
+        })?;
+
+        debug!("Network check passed for {}", name);
+        Ok(())
+    }
+
+    fn delete_network_in(
+        &self,
+        paths: &[String],
+        name: String,
+        cni_version: String,
+        net: NetworkConfig,
+        rt: RuntimeConf,
+    ) -> ResultCNI<()> {
+        debug!(
+            "Deleting network {} with plugin {}",
+            name, net.network._type
+        );
+
+        // Find plugin in the registry or path
+        let plugin = self.resolve_plugin(&net.network._type, paths)?;
+
+        // Set up environment
+        let environ = Self::exec_args("DEL", &rt, paths);
+
+        // Build new config with name and version
+        let new_conf = match self.build_new_config(name.clone(), cni_version, &net, None, &rt) {
+            Ok(conf) => conf,
+            Err(e) => return Err(Box::new(CNIError::Config(e))),
+        };
+
+        // Execute plugin
+        let env = environ.to_env();
+        self.retry.run(&format!("DEL {}", name), || {
+            self.exec_plugin(&plugin, &new_conf.bytes, env.clone())
+        })?;
+
+        debug!("Successfully deleted network {}", name);
+        Ok(())
+    }
+
+    pub(crate) fn validate_network_in(
+        &self,
+        paths: &[String],
+        net: NetworkConfig,
+    ) -> ResultCNI<Vec<String>> {
+        debug!("Validating network: {}", net.network.name);
+
+        // Check basic requirements
+        if net.network._type.is_empty() {
+            return Err(Box::new(CNIError::Config(
+                "Plugin type cannot be empty".to_string(),
+            )));
+        }
+
+        // Set up environment for VERSION command
+        let environ = ExecArgs {
+            command: "VERSION".to_string(),
+            path: paths.join(":"),
+            ..Default::default()
+        };
+
+        // Find plugin in the registry or path
+        let info = match self.resolve_plugin(&net.network._type, paths)? {
+            // Native plugins have no binary to key the cache on
+            PluginTarget::Native(name, plugin) => {
+                let output =
+                    PluginTarget::exec_native(&name, plugin.as_ref(), &[], &environ.to_env());
+                parse_plugin_info(&net.network._type, output)
+            }
+            PluginTarget::External(plugin_path) => {
+                self.plugin_cache.get_or_probe(&plugin_path, || {
+                    // Execute plugin with VERSION command
+                    let output = self
+                        .exec
+                        .exec_plugins(plugin_path.clone(), &[], environ.to_env());
+                    Ok(parse_plugin_info(&net.network._type, output))
+                })?
+            }
+        };
+
+        Ok(supported_versions(&net.network._type, info))
+    }
+}
+
+pub(crate) fn parse_add_result(result_bytes: &[u8], cni_version: String) -> result100::Result {
+    // Directly deserialize the result JSON into the result structure
+    let mut result: result100::Result = match serde_json::from_slice(result_bytes) {
+        Ok(r) => r,
+        Err(e) => {
+            // If direct deserialization fails, create a default result with minimal information
+            debug!(
+                "Failed to directly deserialize result: {}, creating minimal result",
+                e
+            );
+            result100::Result {
+                cni_version: Some(cni_version.clone()),
+                ..Default::default()
+            }
+        }
+    };
+
+    // Ensure CNI version is set
+    if result.cni_version.is_none() {
+        result.cni_version = Some(cni_version);
+    }
+    result
+}
+
+pub(crate) fn parse_plugin_info(
+    plugin_type: &str,
+    output: ResultCNI<Vec<u8>>,
+) -> Option<PluginInfoT> {
+    match output {
+        Ok(version_bytes) => match serde_json::from_slice::<PluginInfoT>(&version_bytes) {
+            Ok(info) => Some(info),
+            Err(e) => {
+                warn!(
+                    "Failed to parse version info from plugin {}: {}",
+                    plugin_type, e
+                );
+                None
+            }
+        },
+        Err(e) => {
+            warn!(
+                "Failed to get version info from plugin {}: {}",
+                plugin_type, e
+            );
+            None
+        }
+    }
+}
+
+pub(crate) fn supported_versions(plugin_type: &str, info: Option<PluginInfoT>) -> Vec<String> {
+    let versions = info.map(|i| i.supported_versions).unwrap_or_default();
+    if versions.is_empty() {
+        warn!("Plugin {} did not return supported versions", plugin_type);
+    } else {
+        debug!("Plugin {} supports versions: {:?}", plugin_type, versions);
+    }
+    versions
 }
 
 impl CNI for CNIConfig {
@@ -280,10 +672,13 @@ impl CNI for CNIConfig {
         rt: RuntimeConf,
     ) -> ResultCNI<Box<dyn APIResult>> {
         info!("Adding network list: {}", net.name);
+        validate_runtime_conf(&rt, "ADD")?;
 
         // Validate the plugin chain
         self.validate_network_list(net.clone())?;
 
+        let paths = net.plugin_dirs.resolve(&self.path);
+
         let mut prev_result: Option<Box<dyn APIResult>> = None;
 
         // Apply each plugin in the chain
@@ -296,7 +691,8 @@ impl CNI for CNIConfig {
             );
 
             // Add network with current plugin
-            let result = self.add_network(
+            let result = self.add_network_in(
+                &paths,
                 net.name.clone(),
                 net.cni_version.clone(),
                 plugin.clone(),
@@ -323,6 +719,7 @@ impl CNI for CNIConfig {
 
     fn check_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
         debug!("Checking network list: {}", net.name);
+        validate_runtime_conf(&rt, "CHECK")?;
 
         // Skip check if disabled
         if net.disable_check {
@@ -343,6 +740,8 @@ impl CNI for CNIConfig {
             }
         };
 
+        let paths = net.plugin_dirs.resolve(&self.path);
+
         // Check each plugin in the chain
         for (i, plugin) in net.plugins.iter().enumerate() {
             debug!(
@@ -353,7 +752,8 @@ impl CNI for CNIConfig {
             );
 
             // Check network with current plugin
-            self.check_network(
+            self.check_network_in(
+                &paths,
                 net.name.clone(),
                 net.cni_version.clone(),
                 Some(prev_result.clone_box()),
@@ -368,6 +768,9 @@ impl CNI for CNIConfig {
 
     fn delete_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
         debug!("Deleting network list: {}", net.name);
+        validate_runtime_conf(&rt, "DEL")?;
+
+        let paths = net.plugin_dirs.resolve(&self.path);
 
         // Delete in reverse order
         for (i, plugin) in net.plugins.iter().enumerate().rev() {
@@ -379,7 +782,8 @@ impl CNI for CNIConfig {
             );
 
             // Delete network with current plugin
-            if let Err(e) = self.delete_network(
+            if let Err(e) = self.delete_network_in(
+                &paths,
                 net.name.clone(),
                 net.cni_version.clone(),
                 plugin.clone(),
@@ -391,24 +795,7 @@ impl CNI for CNIConfig {
         }
 
         // Clean up cached data
-        let cache_dir = self.get_cache_dir(&net.name);
-        let key = rt.get_cache_key();
-        let result_path = cache_dir.join(format!("{}.result", key));
-        let config_path = cache_dir.join(format!("{}.config", key));
-
-        if result_path.exists() {
-            debug!("Removing cached result: {}", result_path.display());
-            if let Err(e) = fs::remove_file(&result_path) {
-                warn!("Failed to remove cached result: {}", e);
-            }
-        }
-
-        if config_path.exists() {
-            debug!("Removing cached config: {}", config_path.display());
-            if let Err(e) = fs::remove_file(&config_path) {
-                warn!("Failed to remove cached config: {}", e);
-            }
-        }
+        self.remove_cached_network(&net.name, &rt);
 
         debug!("Successfully deleted network list: {}", net.name);
         Ok(())
@@ -434,6 +821,49 @@ impl CNI for CNIConfig {
         }
     }
 
+    fn get_status_network_list(&self, net: NetworkConfigList) -> ResultCNI<()> {
+        debug!("Getting status of network list: {}", net.name);
+
+        // STATUS was introduced in spec 1.1.0
+        if !version_at_least(&net.cni_version, "1.1.0") {
+            debug!(
+                "Network {} uses cniVersion {}, skipping STATUS",
+                net.name, net.cni_version
+            );
+            return Ok(());
+        }
+
+        let paths = net.plugin_dirs.resolve(&self.path);
+        for plugin in &net.plugins {
+            let target = self.resolve_plugin(&plugin.network._type, &paths)?;
+
+            let environ = ExecArgs {
+                command: "STATUS".to_string(),
+                path: paths.join(":"),
+                ..Default::default()
+            };
+
+            let new_conf = match self.build_new_config(
+                net.name.clone(),
+                net.cni_version.clone(),
+                plugin,
+                None,
+                &RuntimeConf::default(),
+            ) {
+                Ok(conf) => conf,
+                Err(e) => return Err(Box::new(CNIError::Config(e))),
+            };
+
+            let env = environ.to_env();
+            self.retry.run(&format!("STATUS {}", net.name), || {
+                self.exec_plugin(&target, &new_conf.bytes, env.clone())
+            })?;
+        }
+
+        debug!("Network {} is ready", net.name);
+        Ok(())
+    }
+
     fn add_network(
         &self,
         name: String,
@@ -442,74 +872,8 @@ impl CNI for CNIConfig {
         prev_result: Option<Box<dyn APIResult>>,
         rt: RuntimeConf,
     ) -> ResultCNI<Box<dyn APIResult>> {
-        debug!("Adding network {} with plugin {}", name, net.network._type);
-
-        // Find plugin path
-        let plugin_path = self
-            .exec
-            .find_in_path(net.network._type.clone(), self.path.clone())?;
-
-        // Setup environment
-        let environ = ExecArgs {
-            command: "ADD".to_string(),
-            containerd_id: rt.container_id.clone(),
-            netns: rt.net_ns.clone(),
-            plugin_args: rt.args.clone(),
-            plugin_args_str: rt
-                .args
-                .iter()
-                .map(|arg| format!("{}={}", arg[0], arg[1]))
-                .collect::<Vec<_>>()
-                .join(";"),
-            ifname: rt.if_name.clone(),
-            path: self.path[0].clone(),
-        };
-
-        // Build new config with name, version and prevResult
-        let new_conf = match self.build_new_config(
-            name.clone(),
-            cni_version.clone(),
-            &net,
-            prev_result,
-            &rt,
-        ) {
-            Ok(conf) => conf,
-            Err(e) => return Err(Box::new(CNIError::Config(e))),
-        };
-
-        // Cache network config
-        if let Err(e) = self.cache_network_config(&name, &rt, &new_conf.bytes) {
-            warn!("Failed to cache network config: {}", e);
-        }
-
-        // Execute plugin
-        let result_bytes =
-            self.exec
-                .exec_plugins(plugin_path, &new_conf.bytes, environ.to_env())?;
-
-        // Directly deserialize the result JSON into the result structure
-        let mut result: result100::Result = match serde_json::from_slice(&result_bytes) {
-            Ok(r) => r,
-            Err(e) => {
-                // If direct deserialization fails, create a default result with minimal information
-                debug!(
-                    "Failed to directly deserialize result: {}, creating minimal result",
-                    e
-                );
-                result100::Result {
-                    cni_version: Some(cni_version.clone()),
-                    ..Default::default()
-                }
-            }
-        };
-
-        // Ensure CNI version is set
-        if result.cni_version.is_none() {
-            result.cni_version = Some(cni_version);
-        }
-
-        debug!("Successfully added network {}", name);
-        Ok(Box::new(result))
+        validate_runtime_conf(&rt, "ADD")?;
+        self.add_network_in(&self.path, name, cni_version, net, prev_result, rt)
     }
 
     fn check_network(
@@ -520,45 +884,8 @@ impl CNI for CNIConfig {
         net: NetworkConfig,
         rt: RuntimeConf,
     ) -> ResultCNI<()> {
-        debug!(
-            "Checking network {} with plugin {}",
-            name, net.network._type
-        );
-
-        // Find plugin in path
-        let plugin_path = self
-            .exec
-            .find_in_path(net.network._type.clone(), self.path.clone())?;
-
-        // Set up environment
-        let environ = ExecArgs {
-            command: "CHECK".to_string(),
-            containerd_id: rt.container_id.clone(),
-            netns: rt.net_ns.clone(),
-            plugin_args: rt.args.clone(),
-            plugin_args_str: rt
-                .args
-                .iter()
-                .map(|arg| format!("{}={}", arg[0], arg[1]))
-                .collect::<Vec<_>>()
-                .join(";"),
-            ifname: rt.if_name.clone(),
-            path: self.path[0].clone(),
-        };
-
-        // Build new config with name, version and prevResult
-        let new_conf =
-            match self.build_new_config(name.clone(), cni_version, &net, prev_result, &rt) {
-                Ok(conf) => conf,
-                Err(e) => return Err(Box::new(CNIError::Config(e))),
-            };
-
-        // Execute plugin
-        self.exec
-            .exec_plugins(plugin_path, &new_conf.bytes, environ.to_env())?;
-
-        debug!("Network check passed for {}", name);
-        Ok(())
+        validate_runtime_conf(&rt, "CHECK")?;
+        self.check_network_in(&self.path, name, cni_version, prev_result, net, rt)
     }
 
     fn delete_network(
@@ -568,44 +895,8 @@ impl CNI for CNIConfig {
         net: NetworkConfig,
         rt: RuntimeConf,
     ) -> ResultCNI<()> {
-        debug!(
-            "Deleting network {} with plugin {}",
-            name, net.network._type
-        );
-
-        // Find plugin in path
-        let plugin_path = self
-            .exec
-            .find_in_path(net.network._type.clone(), self.path.clone())?;
-
-        // Set up environment
-        let environ = ExecArgs {
-            command: "DEL".to_string(),
-            containerd_id: rt.container_id.clone(),
-            netns: rt.net_ns.clone(),
-            plugin_args: rt.args.clone(),
-            plugin_args_str: rt
-                .args
-                .iter()
-                .map(|arg| format!("{}={}", arg[0], arg[1]))
-                .collect::<Vec<_>>()
-                .join(";"),
-            ifname: rt.if_name.clone(),
-            path: self.path[0].clone(),
-        };
-
-        // Build new config with name and version
-        let new_conf = match self.build_new_config(name.clone(), cni_version, &net, None, &rt) {
-            Ok(conf) => conf,
-            Err(e) => return Err(Box::new(CNIError::Config(e))),
-        };
-
-        // Execute plugin
-        self.exec
-            .exec_plugins(plugin_path, &new_conf.bytes, environ.to_env())?;
-
-        debug!("Successfully deleted network {}", name);
-        Ok(())
+        validate_runtime_conf(&rt, "DEL")?;
+        self.delete_network_in(&self.path, name, cni_version, net, rt)
     }
 
     fn get_network_cached_result(
@@ -657,9 +948,10 @@ impl CNI for CNIConfig {
         }
 
         // Validate each plugin
+        let paths = net.plugin_dirs.resolve(&self.path);
         let mut plugin_types = Vec::new();
         for plugin in &net.plugins {
-            let types = self.validate_network(plugin.clone())?;
+            let types = self.validate_network_in(&paths, plugin.clone())?;
             plugin_types.extend(types);
         }
 
@@ -668,74 +960,6 @@ impl CNI for CNIConfig {
     }
 
     fn validate_network(&self, net: NetworkConfig) -> ResultCNI<Vec<String>> {
-        debug!("Validating network: {}", net.network.name);
-
-        // Check basic requirements
-        if net.network._type.is_empty() {
-            return Err(Box::new(CNIError::Config(
-                "Plugin type cannot be empty".to_string(),
-            )));
-        }
-
-        // Find plugin in path
-        let plugin_path = self
-            .exec
-            .find_in_path(net.network._type.clone(), self.path.clone())?;
-
-        // Set up environment for VERSION command
-        let environ = ExecArgs {
-            command: "VERSION".to_string(),
-            containerd_id: "".to_string(),
-            netns: "".to_string(),
-            plugin_args: Vec::new(),
-            plugin_args_str: "".to_string(),
-            ifname: "".to_string(),
-            path: self.path[0].clone(),
-        };
-
-        // Execute plugin with VERSION command
-        match self.exec.exec_plugins(plugin_path, &[], environ.to_env()) {
-            Ok(version_bytes) => {
-                // Parse version info
-                match serde_json::from_slice::<serde_json::Value>(&version_bytes) {
-                    Ok(version_info) => {
-                        if let Some(supported_versions) = version_info.get("supportedVersions") {
-                            if let Some(versions_array) = supported_versions.as_array() {
-                                let versions: Vec<String> = versions_array
-                                    .iter()
-                                    .filter_map(|v| v.as_str().map(|s| s.to_string()))
-                                    .collect();
-
-                                debug!(
-                                    "Plugin {} supports versions: {:?}",
-                                    net.network._type, versions
-                                );
-                                return Ok(versions);
-                            }
-                        }
-
-                        warn!(
-                            "Plugin {} did not return supported versions",
-                            net.network._type
-                        );
-                        Ok(vec![])
-                    }
-                    Err(e) => {
-                        warn!(
-                            "Failed to parse version info from plugin {}: {}",
-                            net.network._type, e
-                        );
-                        Ok(vec![])
-                    }
-                }
-            }
-            Err(e) => {
-                warn!(
-                    "Failed to get version info from plugin {}: {}",
-                    net.network._type, e
-                );
-                Ok(vec![])
-            }
-        }
+        self.validate_network_in(&self.path, net)
     }
 }
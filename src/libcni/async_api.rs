@@ -0,0 +1,391 @@
+use async_trait::async_trait;
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+use super::api::{
+    parse_add_result, parse_plugin_info, supported_versions, CNIConfig, NetworkConfigList,
+    RuntimeConf,
+};
+use super::async_exec::{AsyncExec, TokioExec};
+use super::exec::ExecArgs;
+use super::plugin::version_at_least;
+use super::plugin_cache::PluginCacheStats;
+use super::registry::PluginTarget;
+use super::validate::validate_runtime_conf;
+use super::CNIError;
+use crate::libcni::result::result100;
+use crate::libcni::result::{APIResult, ResultCNI};
+use crate::libcni::types::NetworkConfig;
+
+/// Async counterpart of the list operations of `CNI`.
+#[async_trait]
+pub trait AsyncCNI {
+    async fn add_network_list(
+        &self,
+        net: NetworkConfigList,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>>;
+
+    async fn check_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()>;
+
+    async fn delete_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()>;
+
+    async fn get_status_network_list(&self, net: NetworkConfigList) -> ResultCNI<()>;
+
+    async fn validate_network_list(&self, net: NetworkConfigList) -> ResultCNI<Vec<String>>;
+}
+
+/// Runs plugins through `TokioExec`. Plugin paths, native plugins, the cache
+/// directory, the VERSION cache and the retry policy are taken from
+/// `config`. Plugins are looked up and run by `exec`, `config.exec` is
+/// never used.
+#[derive(Default)]
+pub struct AsyncCNIConfig {
+    pub config: CNIConfig,
+    pub exec: TokioExec,
+}
+
+impl AsyncCNIConfig {
+    pub fn new(config: CNIConfig, exec: TokioExec) -> Self {
+        AsyncCNIConfig { config, exec }
+    }
+
+    pub fn plugin_cache_stats(&self) -> PluginCacheStats {
+        self.config.plugin_cache_stats()
+    }
+
+    /// Attaches several networks concurrently. Results are returned in the
+    /// order of `nets`. Dropping the returned future aborts the attachments
+    /// still running and kills their plugins.
+    pub async fn add_network_lists(
+        self: &Arc<Self>,
+        nets: Vec<(NetworkConfigList, RuntimeConf)>,
+    ) -> Vec<ResultCNI<Box<dyn APIResult>>> {
+        let mut tasks = JoinSet::new();
+        for (i, (net, rt)) in nets.into_iter().enumerate() {
+            let this = Arc::clone(self);
+            tasks.spawn(async move { (i, this.add_network_list(net, rt).await) });
+        }
+
+        let mut results: Vec<Option<ResultCNI<Box<dyn APIResult>>>> =
+            (0..tasks.len()).map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((i, result)) => results[i] = Some(result),
+                Err(e) => error!("Network attachment task failed: {}", e),
+            }
+        }
+
+        results
+            .into_iter()
+            .map(|r| {
+                r.unwrap_or_else(|| {
+                    Err(Box::new(CNIError::ExecuteError(
+                        "network attachment task did not complete".to_string(),
+                    )))
+                })
+            })
+            .collect()
+    }
+
+    fn resolve_plugin(&self, plugin: &str, paths: &[String]) -> ResultCNI<PluginTarget> {
+        self.config.registry.resolve(plugin, || {
+            self.exec.find_in_path(plugin.to_string(), paths.to_vec())
+        })
+    }
+
+    async fn exec_plugin(
+        &self,
+        plugin: &PluginTarget,
+        stdin_data: &[u8],
+        environ: Vec<String>,
+    ) -> ResultCNI<Vec<u8>> {
+        match plugin {
+            PluginTarget::Native(name, native) => {
+                PluginTarget::exec_native(name, native.as_ref(), stdin_data, &environ)
+            }
+            PluginTarget::External(path) => {
+                self.exec
+                    .exec_plugins(path.clone(), stdin_data, environ)
+                    .await
+            }
+        }
+    }
+
+    async fn exec_with_retry(
+        &self,
+        what: String,
+        plugin: &PluginTarget,
+        stdin_data: &[u8],
+        environ: ExecArgs,
+    ) -> ResultCNI<Vec<u8>> {
+        let env = environ.to_env();
+        self.config
+            .retry
+            .run_async(&what, || self.exec_plugin(plugin, stdin_data, env.clone()))
+            .await
+    }
+
+    async fn add_network_in(
+        &self,
+        paths: &[String],
+        name: String,
+        cni_version: String,
+        net: NetworkConfig,
+        prev_result: Option<Box<dyn APIResult>>,
+        rt: &RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        debug!("Adding network {} with plugin {}", name, net.network._type);
+
+        let plugin = self.resolve_plugin(&net.network._type, paths)?;
+
+        let new_conf = self
+            .config
+            .build_new_config(name.clone(), cni_version.clone(), &net, prev_result, rt)
+            .map_err(|e| Box::new(CNIError::Config(e)))?;
+
+        if let Err(e) = self.config.cache_network_config(&name, rt, &new_conf.bytes) {
+            warn!("Failed to cache network config: {}", e);
+        }
+
+        let environ = CNIConfig::exec_args("ADD", rt, paths);
+        let result_bytes = self
+            .exec_with_retry(format!("ADD {}", name), &plugin, &new_conf.bytes, environ)
+            .await?;
+
+        debug!("Successfully added network {}", name);
+        Ok(Box::new(parse_add_result(&result_bytes, cni_version)))
+    }
+
+    async fn validate_network_in(
+        &self,
+        paths: &[String],
+        net: &NetworkConfig,
+    ) -> ResultCNI<Vec<String>> {
+        debug!("Validating network: {}", net.network.name);
+
+        if net.network._type.is_empty() {
+            return Err(Box::new(CNIError::Config(
+                "Plugin type cannot be empty".to_string(),
+            )));
+        }
+
+        let environ = ExecArgs {
+            command: "VERSION".to_string(),
+            path: paths.join(":"),
+            ..Default::default()
+        };
+        let info = match self.resolve_plugin(&net.network._type, paths)? {
+            PluginTarget::Native(name, plugin) => {
+                let output =
+                    PluginTarget::exec_native(&name, plugin.as_ref(), &[], &environ.to_env());
+                parse_plugin_info(&net.network._type, output)
+            }
+            PluginTarget::External(plugin_path) => {
+                match self.config.plugin_cache.lookup(&plugin_path) {
+                    Ok(info) => Some(info),
+                    Err(stamp) => {
+                        let output = self
+                            .exec
+                            .exec_plugins(plugin_path.clone(), &[], environ.to_env())
+                            .await;
+                        let info = parse_plugin_info(&net.network._type, output);
+                        self.config.plugin_cache.store(&plugin_path, stamp, &info);
+                        info
+                    }
+                }
+            }
+        };
+
+        Ok(supported_versions(&net.network._type, info))
+    }
+}
+
+#[async_trait]
+impl AsyncCNI for AsyncCNIConfig {
+    async fn add_network_list(
+        &self,
+        net: NetworkConfigList,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        info!("Adding network list: {}", net.name);
+        validate_runtime_conf(&rt, "ADD")?;
+
+        self.validate_network_list(net.clone()).await?;
+
+        let paths = net.plugin_dirs.resolve(&self.config.path);
+        let mut prev_result: Option<Box<dyn APIResult>> = None;
+        for (i, plugin) in net.plugins.iter().enumerate() {
+            debug!(
+                "Executing plugin {}/{}: {}",
+                i + 1,
+                net.plugins.len(),
+                plugin.network._type
+            );
+            let result = self
+                .add_network_in(
+                    &paths,
+                    net.name.clone(),
+                    net.cni_version.clone(),
+                    plugin.clone(),
+                    prev_result,
+                    &rt,
+                )
+                .await?;
+            prev_result = Some(result);
+        }
+
+        if let Some(result) = &prev_result {
+            if let Err(e) = self
+                .config
+                .cache_network_result(&net.name, &rt, result.as_ref())
+            {
+                warn!("Failed to cache network result: {}", e);
+            }
+        }
+
+        debug!("Successfully added network list: {}", net.name);
+        Ok(prev_result.unwrap_or_else(|| Box::<result100::Result>::default()))
+    }
+
+    async fn check_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
+        debug!("Checking network list: {}", net.name);
+        validate_runtime_conf(&rt, "CHECK")?;
+
+        if net.disable_check {
+            debug!("Network check is disabled for {}", net.name);
+            return Ok(());
+        }
+
+        let prev_result = match self.config.read_cached_network(&net.name, &rt) {
+            Ok((result, _, _)) => result,
+            Err(e) => {
+                warn!("No cached result found for network {}: {}", net.name, e);
+                Box::<result100::Result>::default()
+            }
+        };
+
+        let paths = net.plugin_dirs.resolve(&self.config.path);
+        for plugin in &net.plugins {
+            let target = self.resolve_plugin(&plugin.network._type, &paths)?;
+            let new_conf = self
+                .config
+                .build_new_config(
+                    net.name.clone(),
+                    net.cni_version.clone(),
+                    plugin,
+                    Some(prev_result.clone_box()),
+                    &rt,
+                )
+                .map_err(|e| Box::new(CNIError::Config(e)))?;
+
+            let environ = CNIConfig::exec_args("CHECK", &rt, &paths);
+            self.exec_with_retry(
+                format!("CHECK {}", net.name),
+                &target,
+                &new_conf.bytes,
+                environ,
+            )
+            .await?;
+        }
+
+        debug!("Network list check passed: {}", net.name);
+        Ok(())
+    }
+
+    async fn delete_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
+        debug!("Deleting network list: {}", net.name);
+        validate_runtime_conf(&rt, "DEL")?;
+
+        let paths = net.plugin_dirs.resolve(&self.config.path);
+        for plugin in net.plugins.iter().rev() {
+            let deleted = async {
+                let target = self.resolve_plugin(&plugin.network._type, &paths)?;
+                let new_conf = self
+                    .config
+                    .build_new_config(net.name.clone(), net.cni_version.clone(), plugin, None, &rt)
+                    .map_err(|e| Box::new(CNIError::Config(e)))?;
+
+                let environ = CNIConfig::exec_args("DEL", &rt, &paths);
+                self.exec_with_retry(
+                    format!("DEL {}", net.name),
+                    &target,
+                    &new_conf.bytes,
+                    environ,
+                )
+                .await
+            };
+            if let Err(e) = deleted.await {
+                error!("Error deleting plugin {}: {}", plugin.network._type, e);
+                // Continue with next plugin even if one fails
+            }
+        }
+
+        self.config.remove_cached_network(&net.name, &rt);
+
+        debug!("Successfully deleted network list: {}", net.name);
+        Ok(())
+    }
+
+    async fn get_status_network_list(&self, net: NetworkConfigList) -> ResultCNI<()> {
+        debug!("Getting status of network list: {}", net.name);
+
+        // STATUS was introduced in spec 1.1.0
+        if !version_at_least(&net.cni_version, "1.1.0") {
+            debug!(
+                "Network {} uses cniVersion {}, skipping STATUS",
+                net.name, net.cni_version
+            );
+            return Ok(());
+        }
+
+        let paths = net.plugin_dirs.resolve(&self.config.path);
+        for plugin in &net.plugins {
+            let target = self.resolve_plugin(&plugin.network._type, &paths)?;
+            let new_conf = self
+                .config
+                .build_new_config(
+                    net.name.clone(),
+                    net.cni_version.clone(),
+                    plugin,
+                    None,
+                    &RuntimeConf::default(),
+                )
+                .map_err(|e| Box::new(CNIError::Config(e)))?;
+
+            let environ = ExecArgs {
+                command: "STATUS".to_string(),
+                path: paths.join(":"),
+                ..Default::default()
+            };
+            self.exec_with_retry(
+                format!("STATUS {}", net.name),
+                &target,
+                &new_conf.bytes,
+                environ,
+            )
+            .await?;
+        }
+
+        debug!("Network {} is ready", net.name);
+        Ok(())
+    }
+
+    async fn validate_network_list(&self, net: NetworkConfigList) -> ResultCNI<Vec<String>> {
+        debug!("Validating network list: {}", net.name);
+
+        if let Err(e) = net.validate() {
+            return Err(Box::new(CNIError::Config(e)));
+        }
+
+        let paths = net.plugin_dirs.resolve(&self.config.path);
+        let mut plugin_types = Vec::new();
+        for plugin in &net.plugins {
+            plugin_types.extend(self.validate_network_in(&paths, plugin).await?);
+        }
+
+        debug!("Network list validation passed for {}", net.name);
+        Ok(plugin_types)
+    }
+}
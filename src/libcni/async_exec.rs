@@ -0,0 +1,169 @@
+use async_trait::async_trait;
+use log::{debug, trace, warn};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+use super::exec::{check_plugin_output, find_plugin, open_plugin, parse_environ, plugin_command};
+use super::limits::{read_capped_async, ExecLimits};
+use super::security::PluginSecurity;
+use super::{CNIError, ResultCNI};
+
+#[async_trait]
+pub trait AsyncExec {
+    async fn exec_plugins(
+        &self,
+        plugin_path: String,
+        stdin_data: &[u8],
+        environ: Vec<String>,
+    ) -> ResultCNI<Vec<u8>>;
+
+    fn find_in_path(&self, plugin: String, paths: Vec<String>) -> ResultCNI<String>;
+}
+
+/// Runs plugins with tokio's process API. The plugin is killed when the
+/// returned future is dropped or `timeout` expires.
+#[derive(Default)]
+pub struct TokioExec {
+    pub security: Option<PluginSecurity>,
+    pub limits: ExecLimits,
+    pub timeout: Option<Duration>,
+}
+
+impl TokioExec {
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_limits(mut self, limits: ExecLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_security(mut self, security: PluginSecurity) -> Self {
+        self.security = Some(security);
+        self
+    }
+
+    async fn run(
+        &self,
+        plugin_path: &str,
+        stdin_data: &[u8],
+        environ: Vec<String>,
+    ) -> ResultCNI<Vec<u8>> {
+        let binary = open_plugin(self.security.as_ref(), plugin_path)?;
+        let mut std_cmd = plugin_command(plugin_path, binary.as_ref());
+        std_cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .envs(parse_environ(&environ));
+        self.limits
+            .apply(&mut std_cmd, binary.as_ref().map(|f| f.as_raw_fd()))?;
+
+        let mut cmd = tokio::process::Command::from(std_cmd);
+        cmd.kill_on_drop(true);
+        let mut child = cmd.spawn().map_err(|e| {
+            let err_msg = format!("Failed to start CNI plugin {}: {}", plugin_path, e);
+            Box::new(CNIError::ExecuteError(err_msg))
+        })?;
+
+        let max_output = self.limits.max_output;
+        let stdin = child.stdin.take();
+        let stdout = child.stdout.take();
+        let stderr = child.stderr.take();
+
+        let write_stdin = async move {
+            if let Some(mut stdin) = stdin {
+                match stdin.write_all(stdin_data).await {
+                    // The plugin does not have to read its config, e.g. for VERSION
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                    r => r?,
+                }
+                // Close stdin to signal end of input
+                drop(stdin);
+            }
+            Ok::<(), io::Error>(())
+        };
+        let (written, stdout, stderr) = tokio::join!(
+            write_stdin,
+            read_capped_async(stdout, max_output),
+            read_capped_async(stderr, max_output)
+        );
+
+        if let Err(e) = written {
+            let err_msg = format!("Failed to write to plugin stdin: {}", e);
+            return Err(Box::new(CNIError::ExecuteError(err_msg)));
+        }
+        let (stdout, stdout_truncated) = stdout.map_err(|e| {
+            let err_msg = format!("Failed to get plugin output: {}", e);
+            Box::new(CNIError::ExecuteError(err_msg))
+        })?;
+
+        let status = match child.wait().await {
+            Ok(status) => status,
+            Err(e) => {
+                let err_msg = format!("Failed to get plugin output: {}", e);
+                return Err(Box::new(CNIError::ExecuteError(err_msg)));
+            }
+        };
+
+        if let Ok((stderr, _)) = stderr {
+            if !stderr.is_empty() {
+                warn!("CNI plugin stderr: {}", String::from_utf8_lossy(&stderr));
+            }
+        }
+
+        if stdout_truncated {
+            let err_msg = format!(
+                "CNI plugin {} output exceeded {} bytes",
+                plugin_path,
+                max_output.unwrap_or_default()
+            );
+            return Err(Box::new(CNIError::ExecuteError(err_msg)));
+        }
+
+        let stdout = check_plugin_output(stdout)?;
+        if !status.success() {
+            let err_msg = format!("CNI plugin {} failed: {}", plugin_path, status);
+            return Err(Box::new(CNIError::ExecuteError(err_msg)));
+        }
+        Ok(stdout)
+    }
+}
+
+#[async_trait]
+impl AsyncExec for TokioExec {
+    async fn exec_plugins(
+        &self,
+        plugin_path: String,
+        stdin_data: &[u8],
+        environ: Vec<String>,
+    ) -> ResultCNI<Vec<u8>> {
+        debug!("Executing CNI plugin: {}", plugin_path);
+        trace!("CNI stdin data: {}", String::from_utf8_lossy(stdin_data));
+
+        match self.timeout {
+            Some(timeout) => {
+                match tokio::time::timeout(timeout, self.run(&plugin_path, stdin_data, environ))
+                    .await
+                {
+                    Ok(result) => result,
+                    Err(_) => {
+                        let err_msg =
+                            format!("CNI plugin {} timed out after {:?}", plugin_path, timeout);
+                        Err(Box::new(CNIError::ExecuteError(err_msg)))
+                    }
+                }
+            }
+            None => self.run(&plugin_path, stdin_data, environ).await,
+        }
+    }
+
+    fn find_in_path(&self, plugin: String, paths: Vec<String>) -> ResultCNI<String> {
+        find_plugin(self.security.as_ref(), plugin, paths)
+    }
+}
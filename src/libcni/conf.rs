@@ -1,16 +1,202 @@
 // Copyright (c) 2024 https://github.com/divinerapier/cni-rs
 use log::{debug, error, trace, warn};
 use std::{
-    fs::{self, File},
-    io::{BufReader, Read},
+    fs::{self, File, OpenOptions},
+    io::{BufReader, Read, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
     path::Path,
 };
 
 use super::{
     api::NetworkConfigList,
-    types::{NetConf, NetworkConfig},
+    plugin::{parse_version, version_at_least, SPEC_VERSIONS},
+    types::{NetConf, NetworkConfig, PluginDirs},
 };
 
+/// Network names must match `[a-zA-Z0-9][a-zA-Z0-9_.-]*`.
+pub fn validate_network_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            first.is_ascii_alphanumeric()
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(format!("invalid network name {:?}", name));
+    }
+    Ok(())
+}
+
+/// Reads `pluginDirs` and `pluginDirsOverride` of a conflist. Errors come
+/// with the key they are about.
+pub(crate) fn parse_plugin_dirs(
+    conf: &serde_json::Value,
+) -> Result<PluginDirs, (&'static str, String)> {
+    let override_dirs = match conf.get("pluginDirsOverride") {
+        Some(o) => o.as_bool().ok_or((
+            "pluginDirsOverride",
+            "'pluginDirsOverride' must be a bool".to_string(),
+        ))?,
+        None => false,
+    };
+    let dirs = match conf.get("pluginDirs") {
+        Some(dirs) => dirs
+            .as_array()
+            .ok_or(("pluginDirs", "'pluginDirs' must be an array".to_string()))?
+            .iter()
+            .map(|d| d.as_str().map(|s| s.to_string()))
+            .collect::<Option<Vec<String>>>()
+            .ok_or((
+                "pluginDirs",
+                "'pluginDirs' must be an array of strings".to_string(),
+            ))?,
+        None if override_dirs => {
+            return Err((
+                "pluginDirsOverride",
+                "'pluginDirsOverride' needs 'pluginDirs'".to_string(),
+            ))
+        }
+        None => return Ok(PluginDirs::Inherit),
+    };
+    Ok(if override_dirs {
+        PluginDirs::Override(dirs)
+    } else {
+        PluginDirs::Extend(dirs)
+    })
+}
+
+/// Assembles a `NetworkConfigList` whose `bytes` are generated from it.
+#[derive(Clone, Debug, Default)]
+pub struct NetworkConfigListBuilder {
+    name: String,
+    cni_version: String,
+    disable_check: bool,
+    plugins: Vec<NetworkConfig>,
+    plugin_dirs: PluginDirs,
+    ifname: Option<String>,
+}
+
+impl NetworkConfigListBuilder {
+    pub fn new(name: impl Into<String>, cni_version: impl Into<String>) -> Self {
+        NetworkConfigListBuilder {
+            name: name.into(),
+            cni_version: cni_version.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_disable_check(mut self, disable_check: bool) -> Self {
+        self.disable_check = disable_check;
+        self
+    }
+
+    /// Appends a plugin to the chain, see `libcni::models` for typed configs.
+    pub fn with_plugin(mut self, plugin: NetworkConfig) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub fn with_plugin_dirs(mut self, plugin_dirs: PluginDirs) -> Self {
+        self.plugin_dirs = plugin_dirs;
+        self
+    }
+
+    pub fn with_ifname(mut self, ifname: impl Into<String>) -> Self {
+        self.ifname = Some(ifname.into());
+        self
+    }
+
+    /// Checks the name, the version and the plugins, and generates the bytes
+    /// of the list and of each plugin.
+    pub fn build(self) -> Result<NetworkConfigList, String> {
+        validate_network_name(&self.name)?;
+        if !SPEC_VERSIONS.contains(&self.cni_version.as_str()) {
+            return Err(format!("unknown CNI version {:?}", self.cni_version));
+        }
+        if !version_at_least(&self.cni_version, "0.3.0") {
+            return Err(format!(
+                "CNI version {} has no network config lists",
+                self.cni_version
+            ));
+        }
+        if self.plugins.is_empty() {
+            return Err("At least one plugin is required".to_string());
+        }
+
+        let mut plugins = Vec::new();
+        for (i, plugin) in self.plugins.into_iter().enumerate() {
+            let value: serde_json::Value = serde_json::from_slice(&plugin.bytes)
+                .map_err(|e| format!("Invalid config of plugin {}: {}", i, e))?;
+            let conf: NetConf = serde_json::from_value(value.clone())
+                .map_err(|e| format!("Invalid config of plugin {}: {}", i, e))?;
+            if conf._type.is_empty() {
+                return Err(format!("plugin {} has no type", i));
+            }
+            // The runtime sets both on each plugin of the list
+            if !conf.name.is_empty() && conf.name != self.name {
+                return Err(format!(
+                    "plugin {} is named {:?}, not {:?}",
+                    i, conf.name, self.name
+                ));
+            }
+            if !conf.cni_version.is_empty() && conf.cni_version != self.cni_version {
+                return Err(format!(
+                    "plugin {} has CNI version {}, not {}",
+                    i, conf.cni_version, self.cni_version
+                ));
+            }
+            plugins.push(NetworkConfig {
+                network: conf,
+                bytes: value.to_string().into_bytes(),
+            });
+        }
+
+        let mut list = NetworkConfigList {
+            name: self.name,
+            cni_version: self.cni_version,
+            disable_check: self.disable_check,
+            plugins,
+            plugin_dirs: self.plugin_dirs,
+            ifname: self.ifname,
+            bytes: Vec::new(),
+        };
+        list.bytes = list.to_bytes()?;
+        debug!("Built network config list {}", list.name);
+        Ok(list)
+    }
+}
+
+/// Extensions of the files loaded from a config dir.
+pub const CONFIG_EXTENSIONS: [&str; 3] = ["conf", "conflist", "json"];
+
+/// A network found in a config dir, see `ConfigFile::list_networks`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InstalledNetwork {
+    pub name: String,
+    pub cni_version: String,
+    pub path: String,
+    /// The number the file name starts with, if any.
+    pub priority: Option<u32>,
+}
+
+/// A field rewritten by an upgrade, at its JSON path in the new conflist.
+/// `old` is none for added fields and `new` for removed ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigChange {
+    pub path: String,
+    pub old: Option<serde_json::Value>,
+    pub new: Option<serde_json::Value>,
+}
+
+/// The conflist made by `ConfigFile::upgrade` and what was changed.
+#[derive(Clone)]
+pub struct ConfigUpgrade {
+    pub list: NetworkConfigList,
+    pub changes: Vec<ConfigChange>,
+}
+
 pub struct ConfigFile {}
 
 impl ConfigFile {
@@ -39,6 +225,8 @@ impl ConfigFile {
                     }
                 }
 
+                // Files are loaded in the order of their names
+                conf_files.sort();
                 debug!("Found {} config files", conf_files.len());
                 Ok(conf_files)
             }
@@ -78,6 +266,18 @@ impl ConfigFile {
                     }
                 }
 
+                // Runtime-specific extension: extra plugin dirs for this network
+                let plugin_dirs = parse_plugin_dirs(&ncmaps).map_err(|(_, e)| e)?;
+
+                // Runtime-specific extension: fixed interface name for this network
+                let ifname = match ncmaps.get("interfaceName") {
+                    Some(ifname) => match ifname.as_str() {
+                        Some(ifname) => Some(ifname.to_string()),
+                        None => return Err("'interfaceName' is not a string".to_string()),
+                    },
+                    None => None,
+                };
+
                 let mut ncflist = NetworkConfigList::default();
                 let mut all_plugins = Vec::new();
 
@@ -113,6 +313,8 @@ impl ConfigFile {
                 ncflist.bytes = datas.to_vec();
                 ncflist.disable_check = disable_check;
                 ncflist.plugins = all_plugins;
+                ncflist.plugin_dirs = plugin_dirs;
+                ncflist.ifname = ifname;
                 debug!("Successfully parsed NetworkConfigList: {}", ncflist.name);
                 Ok(ncflist)
             }
@@ -202,6 +404,271 @@ impl ConfigFile {
         }
     }
 
+    /// The networks of `dir` in load order. Unreadable files are skipped.
+    pub fn list_networks(dir: &str) -> Result<Vec<InstalledNetwork>, String> {
+        let extensions = CONFIG_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+        let mut networks = Vec::new();
+        for path in Self::config_files(dir.to_string(), extensions)? {
+            let network = if path.ends_with(".conflist") {
+                Self::read_configlist_file(path.clone()).map(|l| (l.name, l.cni_version))
+            } else {
+                Self::read_config_file(path.clone())
+                    .map(|c| (c.network.name, c.network.cni_version))
+            };
+            let Some((name, cni_version)) = network else {
+                warn!("Skipping unreadable config file {}", path);
+                continue;
+            };
+            let priority = Path::new(&path)
+                .file_name()
+                .and_then(|f| f.to_str())
+                .and_then(|f| f.split('-').next())
+                .and_then(|p| p.parse().ok());
+            networks.push(InstalledNetwork {
+                name,
+                cni_version,
+                path,
+                priority,
+            });
+        }
+        Ok(networks)
+    }
+
+    /// Writes `list` to `<priority>-<name>.conflist` in `dir` atomically and
+    /// returns its path. The file holds `list.to_bytes()`, so it always
+    /// matches the fields of `list`. A network of the same name installed in
+    /// another file, or another network in that file, is not overwritten.
+    pub fn install(dir: &str, priority: u32, list: &NetworkConfigList) -> Result<String, String> {
+        list.validate()?;
+        validate_network_name(&list.name)?;
+        let bytes = list.to_bytes()?;
+        Self::config_from_bytes(&bytes)?;
+
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create directory {}: {}", dir, e))?;
+        let file_name = format!("{:02}-{}.conflist", priority, list.name);
+        let path = Path::new(dir).join(&file_name);
+        let path_str = path.to_string_lossy().to_string();
+        Self::locked(dir, || {
+            let installed = Self::list_networks(dir)?;
+            for network in &installed {
+                if network.name == list.name && network.path != path_str {
+                    return Err(format!(
+                        "network {} is already installed in {}",
+                        list.name, network.path
+                    ));
+                }
+                if network.path == path_str && network.name != list.name {
+                    return Err(format!(
+                        "{} holds network {}, not {}",
+                        path_str, network.name, list.name
+                    ));
+                }
+            }
+            if path.exists() && !installed.iter().any(|n| n.path == path_str) {
+                return Err(format!("{} exists and is not a readable config", path_str));
+            }
+
+            // Written next to the target so the rename stays on one filesystem
+            let tmp = Path::new(dir).join(format!(".{}.{}.tmp", file_name, uuid::Uuid::new_v4()));
+            let write = || -> std::io::Result<()> {
+                let mut file = File::create(&tmp)?;
+                file.write_all(&bytes)?;
+                file.sync_all()?;
+                fs::rename(&tmp, &path)?;
+                File::open(dir)?.sync_all()
+            };
+            if let Err(e) = write() {
+                let _ = fs::remove_file(&tmp);
+                return Err(format!("Failed to write {}: {}", path_str, e));
+            }
+            Ok(())
+        })?;
+        debug!("Installed network {} in {}", list.name, path_str);
+        Ok(path_str)
+    }
+
+    /// Removes every file of `dir` holding the network `name` and returns
+    /// their paths.
+    pub fn remove(dir: &str, name: &str) -> Result<Vec<String>, String> {
+        Self::locked(dir, || {
+            let mut removed = Vec::new();
+            for network in Self::list_networks(dir)? {
+                if network.name != name {
+                    continue;
+                }
+                fs::remove_file(&network.path)
+                    .map_err(|e| format!("Failed to remove {}: {}", network.path, e))?;
+                debug!("Removed network {} from {}", name, network.path);
+                removed.push(network.path);
+            }
+            Ok(removed)
+        })
+    }
+
+    // Runs `f` holding a lock on `dir` itself, so concurrent installs, also
+    // from other processes, see each other's files. Nothing is added to the
+    // directory other tools scan.
+    fn locked<T>(dir: &str, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+        let lock = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY)
+            .open(dir)
+            .map_err(|e| format!("Failed to open {}: {}", dir, e))?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX) } != 0 {
+            return Err(format!(
+                "Failed to lock {}: {}",
+                dir,
+                std::io::Error::last_os_error()
+            ));
+        }
+        let result = f();
+        unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_UN) };
+        result
+    }
+
+    /// Rewrites `list` for the `target` version, which every plugin must
+    /// support according to `supported`, e.g. the versions returned by
+    /// `CNI::validate_network`.
+    pub fn upgrade<F>(
+        list: &NetworkConfigList,
+        target: &str,
+        supported: F,
+    ) -> Result<ConfigUpgrade, String>
+    where
+        F: FnMut(&NetworkConfig) -> Result<Vec<String>, String>,
+    {
+        let bytes = if list.bytes.is_empty() {
+            list.to_bytes()?
+        } else {
+            list.bytes.clone()
+        };
+        let conf = serde_json::from_slice(&bytes).map_err(|e| format!("Invalid JSON: {}", e))?;
+        Self::upgrade_value(conf, Vec::new(), list, target, supported)
+    }
+
+    /// Makes a conflist of a single plugin config, then upgrades it.
+    pub fn upgrade_conf<F>(
+        config: &NetworkConfig,
+        target: &str,
+        supported: F,
+    ) -> Result<ConfigUpgrade, String>
+    where
+        F: FnMut(&NetworkConfig) -> Result<Vec<String>, String>,
+    {
+        let mut plugin: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(&config.bytes)
+                .map_err(|e| format!("Invalid plugin config: {}", e))?;
+        let mut changes = Vec::new();
+        let mut conf = serde_json::Map::new();
+        // Both belong to the list, the runtime sets them on the plugin
+        for key in ["cniVersion", "name"] {
+            if let Some(value) = plugin.remove(key) {
+                changes.push(ConfigChange {
+                    path: format!("$.plugins[0].{}", key),
+                    old: Some(value.clone()),
+                    new: None,
+                });
+                conf.insert(key.to_string(), value);
+            }
+        }
+        let plugins = serde_json::Value::Array(vec![plugin.into()]);
+        changes.push(ConfigChange {
+            path: "$.plugins".to_string(),
+            old: None,
+            new: Some(plugins.clone()),
+        });
+        conf.insert("plugins".to_string(), plugins);
+
+        // Configs without a version are of the first spec
+        let mut versioned = conf.clone();
+        versioned
+            .entry("cniVersion")
+            .or_insert_with(|| SPEC_VERSIONS[0].into());
+        let list =
+            Self::config_from_bytes(&serde_json::to_vec(&versioned).map_err(|e| e.to_string())?)?;
+        Self::upgrade_value(conf.into(), changes, &list, target, supported)
+    }
+
+    fn upgrade_value<F>(
+        mut conf: serde_json::Value,
+        mut changes: Vec<ConfigChange>,
+        list: &NetworkConfigList,
+        target: &str,
+        mut supported: F,
+    ) -> Result<ConfigUpgrade, String>
+    where
+        F: FnMut(&NetworkConfig) -> Result<Vec<String>, String>,
+    {
+        if !SPEC_VERSIONS.contains(&target) {
+            return Err(format!("unknown CNI version {:?}", target));
+        }
+        if !version_at_least(target, "0.3.0") {
+            return Err(format!(
+                "CNI version {} has no network config lists",
+                target
+            ));
+        }
+        if let (Some(from), Some(to)) = (parse_version(&list.cni_version), parse_version(target)) {
+            if from > to {
+                return Err(format!(
+                    "cannot downgrade network {} from {} to {}",
+                    list.name, list.cni_version, target
+                ));
+            }
+        }
+
+        if list.cni_version != target {
+            let mut unsupported = Vec::new();
+            for plugin in &list.plugins {
+                if !supported(plugin)?.iter().any(|v| v == target) {
+                    unsupported.push(plugin.network._type.clone());
+                }
+            }
+            if !unsupported.is_empty() {
+                return Err(format!(
+                    "plugins {} of network {} do not support CNI version {}",
+                    unsupported.join(", "),
+                    list.name,
+                    target
+                ));
+            }
+        }
+
+        let mut set_version = |path: String, value: &mut serde_json::Value| {
+            if value.as_str() != Some(target) {
+                changes.push(ConfigChange {
+                    path,
+                    old: (!value.is_null()).then(|| value.clone()),
+                    new: Some(target.into()),
+                });
+                *value = target.into();
+            }
+        };
+        let obj = conf
+            .as_object_mut()
+            .ok_or("network config is not an object".to_string())?;
+        let version = obj.entry("cniVersion").or_insert(serde_json::Value::Null);
+        set_version("$.cniVersion".to_string(), version);
+        if let Some(plugins) = obj.get_mut("plugins").and_then(|p| p.as_array_mut()) {
+            for (i, plugin) in plugins.iter_mut().enumerate() {
+                if let Some(version) = plugin.get_mut("cniVersion") {
+                    set_version(format!("$.plugins[{}].cniVersion", i), version);
+                }
+            }
+        }
+
+        let bytes = serde_json::to_vec(&conf).map_err(|e| e.to_string())?;
+        let list = Self::config_from_bytes(&bytes)?;
+        debug!(
+            "Upgraded network {} to {} with {} changes",
+            list.name,
+            target,
+            changes.len()
+        );
+        Ok(ConfigUpgrade { list, changes })
+    }
+
     pub fn convert_to_config_list(config: NetworkConfig) -> NetworkConfigList {
         debug!(
             "Converting single config to config list: {}",
@@ -212,6 +679,8 @@ impl ConfigFile {
             cni_version: config.network.cni_version.clone(),
             disable_check: false,
             plugins: vec![config],
+            plugin_dirs: PluginDirs::Inherit,
+            ifname: None,
             bytes: Vec::new(), // This will be empty for converted configs
         }
     }
@@ -1,18 +1,61 @@
 // Copyright (c) 2024 https://github.com/divinerapier/cni-rs
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::io;
 use std::result;
 use thiserror::Error;
 
-// const CODE_UNKNOWN: usize = 0;
-// const CODE_INCOMPATIBLE_CNI_VERSION: usize = 1;
-// const CODE_UNSUPPORTED_FIELD: usize = 2;
-// const CODE_UNKNOWN_CONTAINER: usize = 3;
-// const CODE_INVALID_ENVIRONMENT_VARIABLES: usize = 4;
-// const CODE_IO_FILURE: usize = 5;
-// const CODE_DECODING_FAILURE: usize = 6;
-// const CODE_INVALID_NETWORK_CONFIG: usize = 7;
-// const CODE_TRY_AGAIN_LATER: usize = 11;
-// const CODE_INTERNAL: usize = 999;
+pub const CODE_UNKNOWN: u32 = 0;
+pub const CODE_INCOMPATIBLE_CNI_VERSION: u32 = 1;
+pub const CODE_UNSUPPORTED_FIELD: u32 = 2;
+pub const CODE_UNKNOWN_CONTAINER: u32 = 3;
+pub const CODE_INVALID_ENVIRONMENT_VARIABLES: u32 = 4;
+pub const CODE_IO_FAILURE: u32 = 5;
+pub const CODE_DECODING_FAILURE: u32 = 6;
+pub const CODE_INVALID_NETWORK_CONFIG: u32 = 7;
+pub const CODE_TRY_AGAIN_LATER: u32 = 11;
+pub const CODE_INTERNAL: u32 = 999;
+
+/// Error object a plugin prints on stdout when it fails.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginError {
+    #[serde(
+        rename = "cniVersion",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub cni_version: String,
+    pub code: u32,
+    #[serde(default)]
+    pub msg: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub details: String,
+}
+
+impl PluginError {
+    pub fn new(code: u32, msg: &str) -> Self {
+        PluginError {
+            code,
+            msg: msg.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_details(mut self, details: &str) -> Self {
+        self.details = details.to_string();
+        self
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "plugin error {}: {}", self.code, self.msg)?;
+        if !self.details.is_empty() {
+            write!(f, "; {}", self.details)?;
+        }
+        Ok(())
+    }
+}
 
 #[derive(Debug, Error)]
 pub enum CNIError {
@@ -30,10 +73,12 @@ pub enum CNIError {
     EmptyKey,
     #[error("{0}")]
     TooLong(String),
-    #[error("Invalid checksum")]
+    #[error("Invalid checksum: {0}")]
     InvalidChecksum(String),
-    #[error("Invalid filename")]
+    #[error("Invalid filename: {0}")]
     InvalidFilename(String),
+    #[error("insecure plugin binary {0}: {1}")]
+    InsecurePlugin(String, String),
     // #[error("Invalid prost data: {0}")]
     // Decode(#[source] Box<prost::DecodeError>),
     #[error("Invalid data: {0}")]
@@ -44,6 +89,43 @@ pub enum CNIError {
     DBClosed,
     #[error("{0}")]
     LogRead(String),
+    #[error("{0}")]
+    Plugin(PluginError),
+    #[error("invalid environment variables: {0}")]
+    InvalidEnvironmentVariables(String),
+    #[error("{1} (gave up after {0} attempts)")]
+    RetriesExhausted(u32, Box<CNIError>),
+}
+
+impl CNIError {
+    /// The spec error code reported by the plugin, if any.
+    pub fn code(&self) -> Option<u32> {
+        match self {
+            CNIError::Plugin(e) => Some(e.code),
+            CNIError::RetriesExhausted(_, e) => e.code(),
+            CNIError::InvalidEnvironmentVariables(_) => Some(CODE_INVALID_ENVIRONMENT_VARIABLES),
+            _ => None,
+        }
+    }
+
+    /// The error object a plugin prints for this error.
+    pub fn to_plugin_error(&self) -> PluginError {
+        let code = match self {
+            CNIError::Plugin(e) => return e.clone(),
+            CNIError::RetriesExhausted(_, e) => return e.to_plugin_error(),
+            CNIError::InvalidEnvironmentVariables(_) => CODE_INVALID_ENVIRONMENT_VARIABLES,
+            CNIError::Io(_) => CODE_IO_FAILURE,
+            CNIError::VarDecode(_) => CODE_DECODING_FAILURE,
+            CNIError::Config(_) => CODE_INVALID_NETWORK_CONFIG,
+            CNIError::NotFound(_, _) => CODE_UNKNOWN_CONTAINER,
+            _ => CODE_INTERNAL,
+        };
+        PluginError::new(code, &self.to_string())
+    }
+
+    pub fn is_try_again_later(&self) -> bool {
+        self.code() == Some(CODE_TRY_AGAIN_LATER)
+    }
 }
 
 impl From<io::Error> for CNIError {
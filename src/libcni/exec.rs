@@ -2,10 +2,17 @@
 use log::{debug, error, trace, warn};
 use serde::{Deserialize, Serialize};
 
+use crate::libcni::error::PluginError;
+use crate::libcni::limits::{read_capped, ExecLimits};
 use crate::libcni::result::ResultCNI;
+use crate::libcni::security::PluginSecurity;
 use crate::libcni::CNIError;
+use std::fs::File;
+use std::os::fd::AsRawFd;
+use std::os::unix::process::CommandExt;
 use std::path::Path;
-use std::process::{Command, Stdio};
+use std::process::{Child, Command, Stdio};
+use std::thread;
 use std::{collections::HashMap, io::Write};
 
 #[derive(Default, Serialize, Deserialize, Debug)]
@@ -22,18 +29,22 @@ pub struct ExecArgs {
 impl ExecArgs {
     pub fn to_env(&self) -> Vec<String> {
         debug!("Preparing environment for CNI execution , args :{:?}", self);
-        let mut result_env = Vec::default();
-
-        // Set environment variables
-        std::env::set_var("CNI_COMMAND", self.command.clone());
-        std::env::set_var("CNI_CONTAINERID", self.containerd_id.clone());
-        std::env::set_var("CNI_NETNS", self.netns.clone());
-        std::env::set_var("CNI_ARGS", self.plugin_args_str.clone());
-        std::env::set_var("CNI_IFNAME", self.ifname.clone());
-        std::env::set_var("CNI_PATH", self.path.clone());
-
-        // Collect all environment variables
-        for (k, v) in std::env::vars() {
+        let cni_env = [
+            ("CNI_COMMAND", &self.command),
+            ("CNI_CONTAINERID", &self.containerd_id),
+            ("CNI_NETNS", &self.netns),
+            ("CNI_ARGS", &self.plugin_args_str),
+            ("CNI_IFNAME", &self.ifname),
+            ("CNI_PATH", &self.path),
+        ];
+
+        // Inherit the runtime's environment, but never mutate it: plugins
+        // may be executed from several threads at once.
+        let mut result_env: Vec<String> = std::env::vars()
+            .filter(|(k, _)| !cni_env.iter().any(|(key, _)| key == k))
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        for (k, v) in cni_env {
             result_env.push(format!("{}={}", k, v));
         }
 
@@ -58,8 +69,37 @@ pub trait Exec {
     fn decode(&self, data: &[u8]) -> ResultCNI<()>;
 }
 
+// Kills and reaps the plugin unless it was waited for, so no error path
+// leaves a zombie behind.
+struct ReapOnDrop(Child);
+
+impl Drop for ReapOnDrop {
+    fn drop(&mut self) {
+        // Both are no-ops once the child was reaped
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
 #[derive(Default)]
-pub struct RawExec {}
+pub struct RawExec {
+    pub security: Option<PluginSecurity>,
+    pub limits: ExecLimits,
+}
+
+impl RawExec {
+    pub fn hardened(security: PluginSecurity) -> Self {
+        RawExec {
+            security: Some(security),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_limits(mut self, limits: ExecLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
 
 impl Exec for RawExec {
     fn exec_plugins(
@@ -72,17 +112,7 @@ impl Exec for RawExec {
         trace!("CNI stdin data: {}", String::from_utf8_lossy(stdin_data));
 
         // Parse environment variables
-        let envs: HashMap<String, String> = environ
-            .iter()
-            .filter_map(|env_var| {
-                let parts: Vec<&str> = env_var.splitn(2, '=').collect();
-                if parts.len() == 2 {
-                    Some((parts[0].to_string(), parts[1].to_string()))
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let envs = parse_environ(&environ);
         // debug!("CNI environment variables: {:?}", envs);
         // Check if plugin exists
         if !Path::new(&plugin_path).exists() {
@@ -90,34 +120,69 @@ impl Exec for RawExec {
             return Err(Box::new(CNIError::ExecuteError(err_msg)));
         }
 
-        // Start the plugin process
-        let mut plugin_cmd = match Command::new(&plugin_path)
-            .stdin(Stdio::piped())
+        // Start the plugin process, hardened from the descriptor it was verified on
+        let binary = open_plugin(self.security.as_ref(), &plugin_path)?;
+        let mut cmd = plugin_command(&plugin_path, binary.as_ref());
+        cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
-            .envs(envs)
-            .spawn()
-        {
-            Ok(cmd) => cmd,
+            .envs(envs);
+        self.limits
+            .apply(&mut cmd, binary.as_ref().map(|f| f.as_raw_fd()))?;
+        let mut plugin_cmd = match cmd.spawn() {
+            Ok(cmd) => ReapOnDrop(cmd),
             Err(e) => {
                 let err_msg = format!("Failed to start CNI plugin {}: {}", plugin_path, e);
                 return Err(Box::new(CNIError::ExecuteError(err_msg)));
             }
         };
         debug!("cni stdin is: {:?}", String::from_utf8_lossy(stdin_data));
-        // Write stdin data
-        if let Some(mut stdin) = plugin_cmd.stdin.take() {
-            if let Err(e) = stdin.write_all(stdin_data) {
+
+        let max_output = self.limits.max_output;
+        let (stdout, stdout_truncated, stderr) = thread::scope(|s| {
+            // Write stdin data, closing it afterwards to signal end of input
+            let stdin_writer = plugin_cmd.0.stdin.take().map(|mut stdin| {
+                s.spawn(move || match stdin.write_all(stdin_data) {
+                    // The plugin does not have to read its config, e.g. for VERSION
+                    Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+                    r => r,
+                })
+            });
+            let stderr_reader = plugin_cmd
+                .0
+                .stderr
+                .take()
+                .map(|stderr| s.spawn(move || read_capped(stderr, max_output)));
+
+            let stdout = match plugin_cmd.0.stdout.take() {
+                Some(stdout) => read_capped(stdout, max_output),
+                None => Ok((Vec::new(), false)),
+            };
+            // Otherwise the threads may wait on the plugin forever
+            if stdout.is_err() {
+                let _ = plugin_cmd.0.kill();
+            }
+
+            if let Some(Ok(Err(e))) = stdin_writer.map(|h| h.join()) {
                 let err_msg = format!("Failed to write to plugin stdin: {}", e);
                 return Err(Box::new(CNIError::ExecuteError(err_msg)));
             }
-            // Close stdin to signal end of input
-            drop(stdin);
-        }
+            let stderr = match stderr_reader.map(|h| h.join()) {
+                Some(Ok(Ok((stderr, _)))) => stderr,
+                _ => Vec::new(),
+            };
+            match stdout {
+                Ok((stdout, truncated)) => Ok((stdout, truncated, stderr)),
+                Err(e) => {
+                    let err_msg = format!("Failed to get plugin output: {}", e);
+                    Err(Box::new(CNIError::ExecuteError(err_msg)))
+                }
+            }
+        })?;
 
-        // Wait for command to complete and get output
-        let output = match plugin_cmd.wait_with_output() {
-            Ok(output) => output,
+        // Wait for command to complete
+        let status = match plugin_cmd.0.wait() {
+            Ok(status) => status,
             Err(e) => {
                 let err_msg = format!("Failed to get plugin output: {}", e);
                 return Err(Box::new(CNIError::ExecuteError(err_msg)));
@@ -125,47 +190,30 @@ impl Exec for RawExec {
         };
 
         // Check for errors in stderr
-        if !output.stderr.is_empty() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
+        if !stderr.is_empty() {
+            let stderr = String::from_utf8_lossy(&stderr);
             warn!("CNI plugin stderr: {}", stderr);
         }
 
-        // Check for error in stdout (CNI returns errors in JSON format)
-        if let Ok(json_value) = serde_json::from_slice::<serde_json::Value>(&output.stdout) {
-            if let Some(error_code) = json_value.get("code") {
-                if error_code.as_u64().is_some() {
-                    let msg = String::from_utf8_lossy(&output.stdout).to_string();
-                    return Err(Box::new(CNIError::ExecuteError(msg)));
-                }
-            }
+        if stdout_truncated {
+            let err_msg = format!(
+                "CNI plugin {} output exceeded {} bytes",
+                plugin_path,
+                max_output.unwrap_or_default()
+            );
+            return Err(Box::new(CNIError::ExecuteError(err_msg)));
         }
 
-        debug!("CNI plugin execution successful");
-        Ok(output.stdout)
+        let stdout = check_plugin_output(stdout)?;
+        if !status.success() {
+            let err_msg = format!("CNI plugin {} failed: {}", plugin_path, status);
+            return Err(Box::new(CNIError::ExecuteError(err_msg)));
+        }
+        Ok(stdout)
     }
 
     fn find_in_path(&self, plugin: String, paths: Vec<String>) -> ResultCNI<String> {
-        trace!("Finding CNI plugin {} in paths", plugin);
-
-        if paths.is_empty() {
-            let err_msg = format!("No plugin paths provided for {}", plugin);
-            error!("{}", err_msg);
-            return Err(Box::new(CNIError::Config(err_msg)));
-        }
-
-        for path in &paths {
-            let full_path = format!("{}/{}", path, plugin);
-            let plugin_path = Path::new(&full_path);
-
-            if plugin_path.exists() {
-                debug!("Found CNI plugin at: {}", full_path);
-                return Ok(full_path);
-            }
-        }
-
-        let err_msg = format!("CNI plugin {} not found in paths {:?}", plugin, paths);
-        error!("{}", err_msg);
-        Err(Box::new(CNIError::NotFound(plugin, paths.join(":"))))
+        find_plugin(self.security.as_ref(), plugin, paths)
     }
 
     fn decode(&self, data: &[u8]) -> ResultCNI<()> {
@@ -187,3 +235,102 @@ impl Exec for RawExec {
         }
     }
 }
+
+pub(crate) fn parse_environ(environ: &[String]) -> HashMap<String, String> {
+    environ
+        .iter()
+        .filter_map(|env_var| {
+            let parts: Vec<&str> = env_var.splitn(2, '=').collect();
+            if parts.len() == 2 {
+                Some((parts[0].to_string(), parts[1].to_string()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Turns an error object printed by the plugin into an error.
+pub(crate) fn check_plugin_output(stdout: Vec<u8>) -> ResultCNI<Vec<u8>> {
+    // Check for error in stdout (CNI returns errors in JSON format)
+    if let Ok(json_value) = serde_json::from_slice::<serde_json::Value>(&stdout) {
+        if let Some(error_code) = json_value.get("code") {
+            if error_code.as_u64().is_some() {
+                return match serde_json::from_value::<PluginError>(json_value) {
+                    Ok(plugin_err) => Err(Box::new(CNIError::Plugin(plugin_err))),
+                    Err(_) => {
+                        let msg = String::from_utf8_lossy(&stdout).to_string();
+                        Err(Box::new(CNIError::ExecuteError(msg)))
+                    }
+                };
+            }
+        }
+    }
+
+    debug!("CNI plugin execution successful");
+    Ok(stdout)
+}
+
+pub(crate) fn find_plugin(
+    security: Option<&PluginSecurity>,
+    plugin: String,
+    paths: Vec<String>,
+) -> ResultCNI<String> {
+    trace!("Finding CNI plugin {} in paths", plugin);
+
+    if paths.is_empty() {
+        let err_msg = format!("No plugin paths provided for {}", plugin);
+        error!("{}", err_msg);
+        return Err(Box::new(CNIError::Config(err_msg)));
+    }
+
+    if security.is_some() {
+        PluginSecurity::check_plugin_name(&plugin)?;
+    }
+
+    for path in &paths {
+        let full_path = format!("{}/{}", path, plugin);
+        let plugin_path = Path::new(&full_path);
+
+        if plugin_path.exists() {
+            debug!("Found CNI plugin at: {}", full_path);
+            if let Some(security) = security {
+                security.verify_binary(&plugin, &full_path)?;
+            }
+            return Ok(full_path);
+        }
+    }
+
+    let err_msg = format!("CNI plugin {} not found in paths {:?}", plugin, paths);
+    error!("{}", err_msg);
+    Err(Box::new(CNIError::NotFound(plugin, paths.join(":"))))
+}
+
+/// Opens and verifies the binary of a hardened exec, which is then run from
+/// the returned descriptor so it cannot be swapped after the checks.
+pub(crate) fn open_plugin(
+    security: Option<&PluginSecurity>,
+    plugin_path: &str,
+) -> ResultCNI<Option<File>> {
+    let Some(security) = security else {
+        return Ok(None);
+    };
+    let plugin = Path::new(plugin_path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    security.open_verified(&plugin, plugin_path).map(Some)
+}
+
+/// A command running `binary` if given, `plugin_path` otherwise. The plugin
+/// sees `plugin_path` as its argv[0] either way.
+pub(crate) fn plugin_command(plugin_path: &str, binary: Option<&File>) -> Command {
+    match binary {
+        Some(binary) => {
+            let mut cmd = Command::new(format!("/proc/self/fd/{}", binary.as_raw_fd()));
+            cmd.arg0(plugin_path);
+            cmd
+        }
+        None => Command::new(plugin_path),
+    }
+}
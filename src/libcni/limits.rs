@@ -0,0 +1,260 @@
+use log::{debug, warn};
+use std::ffi::CString;
+use std::io::{self, Read};
+use std::os::fd::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+use super::{CNIError, ResultCNI};
+
+// Not exported by every libc version
+const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+const FALLBACK_MAX_FD: libc::c_int = 65536;
+
+/// Constraints applied to every plugin process started by `RawExec`.
+/// The default leaves the plugin unconstrained, as before.
+#[derive(Clone, Debug, Default)]
+pub struct ExecLimits {
+    /// Do not let the plugin inherit descriptors other than stdio.
+    pub close_fds: bool,
+    /// RLIMIT_AS in bytes.
+    pub address_space: Option<u64>,
+    /// RLIMIT_CPU in seconds.
+    pub cpu_seconds: Option<u64>,
+    /// RLIMIT_NOFILE.
+    pub open_files: Option<u64>,
+    /// Run the plugin as the leader of a new process group.
+    pub new_process_group: bool,
+    /// cgroup directory the plugin is moved into before exec.
+    pub cgroup: Option<String>,
+    /// Maximum number of bytes kept from each of stdout and stderr.
+    pub max_output: Option<usize>,
+}
+
+impl ExecLimits {
+    pub fn with_close_fds(mut self) -> Self {
+        self.close_fds = true;
+        self
+    }
+
+    pub fn with_address_space(mut self, bytes: u64) -> Self {
+        self.address_space = Some(bytes);
+        self
+    }
+
+    pub fn with_cpu_seconds(mut self, seconds: u64) -> Self {
+        self.cpu_seconds = Some(seconds);
+        self
+    }
+
+    pub fn with_open_files(mut self, files: u64) -> Self {
+        self.open_files = Some(files);
+        self
+    }
+
+    pub fn with_new_process_group(mut self) -> Self {
+        self.new_process_group = true;
+        self
+    }
+
+    pub fn with_cgroup(mut self, cgroup: &str) -> Self {
+        self.cgroup = Some(cgroup.to_string());
+        self
+    }
+
+    pub fn with_max_output(mut self, bytes: usize) -> Self {
+        self.max_output = Some(bytes);
+        self
+    }
+
+    /// Installs the limits on `cmd`. `exec_fd` is a descriptor the child
+    /// must keep open across exec, the plugin binary run through
+    /// `/proc/self/fd`.
+    pub(crate) fn apply(&self, cmd: &mut Command, exec_fd: Option<RawFd>) -> ResultCNI<()> {
+        if self.new_process_group {
+            cmd.process_group(0);
+        }
+
+        let mut rlimits = Vec::new();
+        if let Some(v) = self.address_space {
+            rlimits.push((libc::RLIMIT_AS, v));
+        }
+        if let Some(v) = self.cpu_seconds {
+            rlimits.push((libc::RLIMIT_CPU, v));
+        }
+        if let Some(v) = self.open_files {
+            rlimits.push((libc::RLIMIT_NOFILE, v));
+        }
+
+        // Everything the child needs is allocated here, the pre_exec hook
+        // itself may only make async-signal-safe calls.
+        let cgroup_procs = match &self.cgroup {
+            Some(dir) => Some(
+                CString::new(format!("{}/cgroup.procs", dir.trim_end_matches('/'))).map_err(
+                    |_| Box::new(CNIError::Config(format!("invalid cgroup path {}", dir))),
+                )?,
+            ),
+            None => None,
+        };
+        let close_fds = self.close_fds;
+
+        if !close_fds && rlimits.is_empty() && cgroup_procs.is_none() && exec_fd.is_none() {
+            return Ok(());
+        }
+
+        debug!("Applying plugin exec limits: {:?}", self);
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(path) = &cgroup_procs {
+                    join_cgroup(path)?;
+                }
+                for (resource, value) in &rlimits {
+                    let limit = libc::rlimit {
+                        rlim_cur: *value as libc::rlim_t,
+                        rlim_max: *value as libc::rlim_t,
+                    };
+                    if libc::setrlimit(*resource, &limit) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                if close_fds {
+                    cloexec_from(3);
+                }
+                // Interpreted plugins re-open their script by this path
+                if let Some(fd) = exec_fd {
+                    if libc::fcntl(fd, libc::F_SETFD, 0) != 0 {
+                        return Err(io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+        Ok(())
+    }
+}
+
+// Runs in the forked child.
+unsafe fn join_cgroup(procs: &CString) -> io::Result<()> {
+    let fd = libc::open(procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // "0" moves the writing process
+    let ret = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+    let err = io::Error::last_os_error();
+    libc::close(fd);
+    if ret != 1 {
+        return Err(err);
+    }
+    Ok(())
+}
+
+// Runs in the forked child. Marking descriptors close-on-exec rather than
+// closing them keeps the pipe std uses to report exec failures working.
+unsafe fn cloexec_from(first: libc::c_int) {
+    if libc::syscall(
+        libc::SYS_close_range,
+        first as libc::c_uint,
+        libc::c_uint::MAX,
+        CLOSE_RANGE_CLOEXEC,
+    ) == 0
+    {
+        return;
+    }
+
+    let mut limit: libc::rlimit = std::mem::zeroed();
+    let max_fd = if libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) == 0 {
+        (limit.rlim_cur as libc::c_int).clamp(first, FALLBACK_MAX_FD)
+    } else {
+        FALLBACK_MAX_FD
+    };
+    for fd in first..max_fd {
+        let flags = libc::fcntl(fd, libc::F_GETFD);
+        if flags >= 0 {
+            libc::fcntl(fd, libc::F_SETFD, flags | libc::FD_CLOEXEC);
+        }
+    }
+}
+
+/// Reads `reader` to the end, keeping at most `max` bytes. The rest is
+/// drained so the writer never blocks. Returns the kept bytes and whether
+/// anything was dropped.
+pub(crate) fn read_capped<R: Read>(
+    mut reader: R,
+    max: Option<usize>,
+) -> io::Result<(Vec<u8>, bool)> {
+    let mut out = CappedOutput::new(max);
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => out.extend(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(out.finish())
+}
+
+/// `read_capped` for async readers; a missing reader reads as empty.
+#[cfg(feature = "async")]
+pub(crate) async fn read_capped_async<R>(
+    reader: Option<R>,
+    max: Option<usize>,
+) -> io::Result<(Vec<u8>, bool)>
+where
+    R: tokio::io::AsyncRead + Unpin,
+{
+    use tokio::io::AsyncReadExt;
+
+    let mut out = CappedOutput::new(max);
+    let Some(mut reader) = reader else {
+        return Ok(out.finish());
+    };
+    let mut buf = [0u8; 8192];
+    loop {
+        match reader.read(&mut buf).await? {
+            0 => break,
+            n => out.extend(&buf[..n]),
+        }
+    }
+    Ok(out.finish())
+}
+
+// Output kept up to a maximum, remembering whether any was dropped
+struct CappedOutput {
+    out: Vec<u8>,
+    max: Option<usize>,
+    truncated: bool,
+}
+
+impl CappedOutput {
+    fn new(max: Option<usize>) -> Self {
+        CappedOutput {
+            out: Vec::new(),
+            max,
+            truncated: false,
+        }
+    }
+
+    fn extend(&mut self, data: &[u8]) {
+        let room = self
+            .max
+            .map_or(data.len(), |max| max.saturating_sub(self.out.len()));
+        let room = room.min(data.len());
+        self.out.extend_from_slice(&data[..room]);
+        if room < data.len() {
+            if !self.truncated {
+                warn!(
+                    "Plugin output exceeded {} bytes, truncating",
+                    self.max.unwrap_or(0)
+                );
+            }
+            self.truncated = true;
+        }
+    }
+
+    fn finish(self) -> (Vec<u8>, bool) {
+        (self.out, self.truncated)
+    }
+}
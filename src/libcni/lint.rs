@@ -0,0 +1,377 @@
+use log::debug;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+use super::api::CNIConfig;
+use super::conf::{parse_plugin_dirs, validate_network_name, ConfigFile, CONFIG_EXTENSIONS};
+use super::plugin::{version_at_least, SPEC_VERSIONS};
+use super::types::{NetConf, NetworkConfig, PluginDirs};
+use crate::ipam::{host_local::HostLocalConf, range::RangeSet};
+
+/// Capabilities of the CNI conventions, which runtimes commonly supply.
+pub const CONVENTION_CAPABILITIES: [&str; 10] = [
+    "portMappings",
+    "ipRanges",
+    "bandwidth",
+    "dns",
+    "ips",
+    "mac",
+    "infinibandGUID",
+    "deviceID",
+    "aliases",
+    "cgroupPath",
+];
+
+// Keys the runtime adds to the config it passes to plugins
+const RUNTIME_KEYS: [&str; 2] = ["prevResult", "runtimeConfig"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LintKind {
+    InvalidConfig,
+    InvalidName,
+    DuplicateName,
+    UnknownVersion,
+    UnsupportedVersion,
+    MissingPlugin,
+    UnsuppliedCapability,
+    RuntimeKey,
+    OverlappingRanges,
+}
+
+/// A problem of a config file, at a JSON path such as `$.plugins[0].type`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub file: String,
+    pub path: String,
+    pub kind: LintKind,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.file, self.path, self.message)
+    }
+}
+
+/// Checks config files beyond what loading them does. Plugins are looked up
+/// and asked for their versions the way `CNIConfig` runs them.
+pub struct Linter<'a> {
+    cni: &'a CNIConfig,
+    capabilities: Vec<String>,
+}
+
+// Host-local ranges of a network, to check against the other networks
+struct NetworkRanges {
+    file: String,
+    path: String,
+    network: String,
+    ranges: Vec<RangeSet>,
+}
+
+// What the plugins of a config are checked against
+struct ConfContext<'c> {
+    file: &'c str,
+    network: &'c str,
+    version: Option<&'c str>,
+    paths: Vec<String>,
+}
+
+#[derive(Default)]
+struct Report {
+    findings: Vec<Finding>,
+    // Network name to the file defining it first
+    names: HashMap<String, String>,
+    ranges: Vec<NetworkRanges>,
+}
+
+impl Report {
+    fn add(&mut self, file: &str, path: impl Into<String>, kind: LintKind, message: String) {
+        self.findings.push(Finding {
+            file: file.to_string(),
+            path: path.into(),
+            kind,
+            message,
+        });
+    }
+}
+
+impl<'a> Linter<'a> {
+    pub fn new(cni: &'a CNIConfig) -> Self {
+        Linter {
+            cni,
+            capabilities: CONVENTION_CAPABILITIES
+                .iter()
+                .map(|c| c.to_string())
+                .collect(),
+        }
+    }
+
+    /// The capabilities the runtime supplies, the conventional ones by default.
+    pub fn with_capabilities<I, S>(mut self, capabilities: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.capabilities = capabilities.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Lints the config files of `dir` in load order.
+    pub fn lint_dir(&self, dir: &str) -> Result<Vec<Finding>, String> {
+        let extensions = CONFIG_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+        let files = ConfigFile::config_files(dir.to_string(), extensions)?;
+        Ok(self.lint_files(&files))
+    }
+
+    pub fn lint_files(&self, files: &[String]) -> Vec<Finding> {
+        let mut report = Report::default();
+        for file in files {
+            debug!("Linting {}", file);
+            let conf = fs::read(file)
+                .map_err(|e| format!("failed to read file: {}", e))
+                .and_then(|bytes| {
+                    serde_json::from_slice::<Value>(&bytes)
+                        .map_err(|e| format!("invalid JSON: {}", e))
+                });
+            match conf {
+                Ok(conf) if conf.is_object() => self.lint_conf(file, &conf, &mut report),
+                Ok(_) => report.add(
+                    file,
+                    "$",
+                    LintKind::InvalidConfig,
+                    "config is not an object".to_string(),
+                ),
+                Err(e) => report.add(file, "$", LintKind::InvalidConfig, e),
+            }
+        }
+
+        for (i, b) in report.ranges.iter().enumerate() {
+            let overlapping = report.ranges[..i].iter().find(|a| {
+                a.file != b.file
+                    && a.ranges
+                        .iter()
+                        .any(|x| b.ranges.iter().any(|y| x.overlaps(y)))
+            });
+            if let Some(a) = overlapping {
+                report.findings.push(Finding {
+                    file: b.file.clone(),
+                    path: b.path.clone(),
+                    kind: LintKind::OverlappingRanges,
+                    message: format!(
+                        "ranges of network {} overlap with network {} in {}",
+                        b.network, a.network, a.file
+                    ),
+                });
+            }
+        }
+        report.findings
+    }
+
+    fn lint_conf(&self, file: &str, conf: &Value, report: &mut Report) {
+        let name = conf
+            .get("name")
+            .and_then(|n| n.as_str())
+            .unwrap_or_default();
+        if let Err(e) = validate_network_name(name) {
+            report.add(file, "$.name", LintKind::InvalidName, e);
+        } else if let Some(first) = report.names.get(name) {
+            report.add(
+                file,
+                "$.name",
+                LintKind::DuplicateName,
+                format!("network {} is also defined in {}", name, first),
+            );
+        } else {
+            report.names.insert(name.to_string(), file.to_string());
+        }
+
+        let is_list = conf.get("plugins").is_some();
+        let version = match conf.get("cniVersion").and_then(|v| v.as_str()) {
+            Some(v) if !SPEC_VERSIONS.contains(&v) => {
+                report.add(
+                    file,
+                    "$.cniVersion",
+                    LintKind::UnknownVersion,
+                    format!("unknown CNI version {:?}", v),
+                );
+                None
+            }
+            Some(v) if is_list && !version_at_least(v, "0.3.0") => {
+                report.add(
+                    file,
+                    "$.cniVersion",
+                    LintKind::UnknownVersion,
+                    format!("CNI version {} has no network config lists", v),
+                );
+                None
+            }
+            Some(v) => Some(v),
+            None => {
+                report.add(
+                    file,
+                    "$.cniVersion",
+                    LintKind::UnknownVersion,
+                    "'cniVersion' is missing".to_string(),
+                );
+                None
+            }
+        };
+
+        // A single plugin config is its own plugin
+        let plugins: Vec<(String, &Value)> = if is_list {
+            lint_runtime_keys(file, "$", conf, report);
+            match conf["plugins"].as_array() {
+                Some(plugins) => plugins
+                    .iter()
+                    .enumerate()
+                    .map(|(i, p)| (format!("$.plugins[{}]", i), p))
+                    .collect(),
+                None => {
+                    report.add(
+                        file,
+                        "$.plugins",
+                        LintKind::InvalidConfig,
+                        "'plugins' must be an array".to_string(),
+                    );
+                    Vec::new()
+                }
+            }
+        } else {
+            vec![("$".to_string(), conf)]
+        };
+
+        let plugin_dirs = parse_plugin_dirs(conf).unwrap_or_else(|(key, e)| {
+            report.add(file, child("$", key), LintKind::InvalidConfig, e);
+            PluginDirs::Inherit
+        });
+        let context = ConfContext {
+            file,
+            network: name,
+            version,
+            paths: plugin_dirs.resolve(&self.cni.path),
+        };
+        for (path, plugin) in plugins {
+            self.lint_plugin(&context, &path, plugin, report);
+        }
+    }
+
+    fn lint_plugin(&self, conf: &ConfContext, path: &str, plugin: &Value, report: &mut Report) {
+        let file = conf.file;
+        lint_runtime_keys(file, path, plugin, report);
+
+        let capabilities = plugin.get("capabilities").and_then(|c| c.as_object());
+        for (capability, enabled) in capabilities.into_iter().flatten() {
+            if enabled.as_bool() == Some(true) && !self.capabilities.contains(capability) {
+                report.add(
+                    file,
+                    child(&child(path, "capabilities"), capability),
+                    LintKind::UnsuppliedCapability,
+                    format!("capability {} is not supplied by the runtime", capability),
+                );
+            }
+        }
+
+        if let Some(ipam) = plugin.get("ipam") {
+            if ipam.get("type").and_then(|t| t.as_str()) == Some("host-local") {
+                let host_local = serde_json::from_value::<HostLocalConf>(ipam.clone())
+                    .map_err(|e| e.to_string())
+                    .and_then(|mut host_local| {
+                        host_local.canonicalize().map_err(|e| e.to_string())?;
+                        Ok(host_local)
+                    });
+                match host_local {
+                    Ok(host_local) => report.ranges.push(NetworkRanges {
+                        file: file.to_string(),
+                        path: child(path, "ipam"),
+                        network: conf.network.to_string(),
+                        ranges: host_local.ranges,
+                    }),
+                    Err(e) => report.add(
+                        file,
+                        child(path, "ipam"),
+                        LintKind::InvalidConfig,
+                        format!("invalid host-local config: {}", e),
+                    ),
+                }
+            }
+        }
+
+        let plugin_type = match plugin.get("type").and_then(|t| t.as_str()) {
+            Some(t) if !t.is_empty() => t,
+            _ => {
+                report.add(
+                    file,
+                    child(path, "type"),
+                    LintKind::InvalidConfig,
+                    "plugin type is missing".to_string(),
+                );
+                return;
+            }
+        };
+        let paths = &conf.paths;
+        if self.cni.resolve_plugin(plugin_type, paths).is_err() {
+            report.add(
+                file,
+                child(path, "type"),
+                LintKind::MissingPlugin,
+                format!("plugin {} not found in {:?}", plugin_type, paths),
+            );
+            return;
+        }
+        let Some(version) = conf.version else {
+            return;
+        };
+        let net = NetworkConfig {
+            network: NetConf {
+                _type: plugin_type.to_string(),
+                ..Default::default()
+            },
+            bytes: plugin.to_string().into_bytes(),
+        };
+        match self.cni.validate_network_in(paths, net) {
+            Ok(supported) if supported.iter().any(|v| v == version) => {}
+            Ok(supported) => report.add(
+                file,
+                "$.cniVersion",
+                LintKind::UnsupportedVersion,
+                format!(
+                    "plugin {} does not support CNI version {}, only {:?}",
+                    plugin_type, version, supported
+                ),
+            ),
+            Err(e) => report.add(
+                file,
+                child(path, "type"),
+                LintKind::UnsupportedVersion,
+                format!(
+                    "failed to get the versions of plugin {}: {}",
+                    plugin_type, e
+                ),
+            ),
+        }
+    }
+}
+
+fn lint_runtime_keys(file: &str, path: &str, conf: &Value, report: &mut Report) {
+    for key in RUNTIME_KEYS {
+        if conf.get(key).is_some() {
+            report.add(
+                file,
+                child(path, key),
+                LintKind::RuntimeKey,
+                format!("{} is set by the runtime, not in static config", key),
+            );
+        }
+    }
+}
+
+// The JSON path of `key` in the object at `path`
+fn child(path: &str, key: &str) -> String {
+    if !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        format!("{}.{}", path, key)
+    } else {
+        format!("{}[{:?}]", path, key)
+    }
+}
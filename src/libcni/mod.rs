@@ -1,10 +1,22 @@
 pub mod api;
+#[cfg(feature = "async")]
+pub mod async_api;
+#[cfg(feature = "async")]
+pub mod async_exec;
 pub mod conf;
 pub mod error;
 pub mod exec;
+pub mod limits;
+pub mod lint;
+pub mod models;
 pub mod plugin;
+pub mod plugin_cache;
+pub mod registry;
 pub mod result;
+pub mod retry;
+pub mod security;
 pub mod types;
+pub mod validate;
 
 pub use error::CNIError;
 pub use result::ResultCNI;
@@ -0,0 +1,374 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+use super::types::{NetConf, NetworkConfig};
+use crate::ipam::{host_local::HostLocalConf, static_ipam::StaticConf};
+
+/// Fields every plugin config can carry. In a conflist, `cniVersion` and
+/// `name` are set by the runtime and can stay empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Common {
+    #[serde(
+        rename = "cniVersion",
+        default,
+        skip_serializing_if = "String::is_empty"
+    )]
+    pub cni_version: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub capabilities: BTreeMap<String, bool>,
+}
+
+/// A typed plugin config, turned into a `NetworkConfig` with its `type`.
+pub trait PluginConf: Serialize {
+    const TYPE: &'static str;
+
+    fn to_network_config(&self) -> Result<NetworkConfig, String> {
+        let mut value = serde_json::to_value(self).map_err(|e| e.to_string())?;
+        value
+            .as_object_mut()
+            .ok_or("plugin config is not an object".to_string())?
+            .insert("type".to_string(), Value::String(Self::TYPE.to_string()));
+        let bytes = serde_json::to_vec(&value).map_err(|e| e.to_string())?;
+        let network = serde_json::from_slice::<NetConf>(&bytes)
+            .map_err(|e| format!("Invalid plugin config: {}", e))?;
+        Ok(NetworkConfig { network, bytes })
+    }
+}
+
+/// The `ipam` section of a plugin. Types without a model are kept as JSON
+/// and must have a `type`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "Value")]
+pub enum IpamConf {
+    HostLocal(HostLocalConf),
+    Static(StaticConf),
+    Dhcp(DhcpConf),
+    Other(Value),
+}
+
+impl TryFrom<Value> for IpamConf {
+    type Error = String;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let decode = |e: serde_json::Error| format!("Invalid IPAM config: {}", e);
+        match value.get("type").and_then(|t| t.as_str()) {
+            Some("host-local") => serde_json::from_value(value)
+                .map(IpamConf::HostLocal)
+                .map_err(decode),
+            Some("static") => serde_json::from_value(value)
+                .map(IpamConf::Static)
+                .map_err(decode),
+            Some("dhcp") => serde_json::from_value(value)
+                .map(IpamConf::Dhcp)
+                .map_err(decode),
+            Some(_) => Ok(IpamConf::Other(value)),
+            None => Err("IPAM config missing 'type'".to_string()),
+        }
+    }
+}
+
+impl From<HostLocalConf> for IpamConf {
+    fn from(mut conf: HostLocalConf) -> Self {
+        if conf._type.is_empty() {
+            conf._type = "host-local".to_string();
+        }
+        IpamConf::HostLocal(conf)
+    }
+}
+
+impl From<StaticConf> for IpamConf {
+    fn from(mut conf: StaticConf) -> Self {
+        if conf._type.is_empty() {
+            conf._type = "static".to_string();
+        }
+        IpamConf::Static(conf)
+    }
+}
+
+impl From<DhcpConf> for IpamConf {
+    fn from(mut conf: DhcpConf) -> Self {
+        if conf._type.is_empty() {
+            conf._type = "dhcp".to_string();
+        }
+        IpamConf::Dhcp(conf)
+    }
+}
+
+/// The `ipam` section of a network using dhcp.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpConf {
+    #[serde(rename = "type")]
+    pub _type: String,
+    #[serde(
+        rename = "daemonSocketPath",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub daemon_socket_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub request: Vec<DhcpRequest>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub provide: Vec<DhcpProvide>,
+    /// Keys without a field, kept so the config survives a round trip.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A DHCP option to ask the server for.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpRequest {
+    pub option: String,
+    #[serde(rename = "skipDefault", default)]
+    pub skip_default: bool,
+}
+
+/// A DHCP option to send to the server, given or taken from a CNI arg.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DhcpProvide {
+    pub option: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(rename = "fromArg", default, skip_serializing_if = "Option::is_none")]
+    pub from_arg: Option<String>,
+}
+
+impl Default for DhcpConf {
+    fn default() -> Self {
+        DhcpConf {
+            _type: "dhcp".to_string(),
+            daemon_socket_path: None,
+            request: Vec::new(),
+            provide: Vec::new(),
+            extra: Map::new(),
+        }
+    }
+}
+
+impl DhcpConf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_daemon_socket_path(mut self, path: impl Into<String>) -> Self {
+        self.daemon_socket_path = Some(path.into());
+        self
+    }
+
+    pub fn with_request(mut self, option: impl Into<String>, skip_default: bool) -> Self {
+        self.request.push(DhcpRequest {
+            option: option.into(),
+            skip_default,
+        });
+        self
+    }
+
+    pub fn with_provide(mut self, option: impl Into<String>, value: impl Into<String>) -> Self {
+        self.provide.push(DhcpProvide {
+            option: option.into(),
+            value: Some(value.into()),
+            from_arg: None,
+        });
+        self
+    }
+
+    pub fn with_provide_from_arg(
+        mut self,
+        option: impl Into<String>,
+        arg: impl Into<String>,
+    ) -> Self {
+        self.provide.push(DhcpProvide {
+            option: option.into(),
+            value: None,
+            from_arg: Some(arg.into()),
+        });
+        self
+    }
+}
+
+// A plugin config struct with optional fields, a `with_*` builder for each
+// and the setters of `Common`. Keys without a field are kept in `extra`.
+macro_rules! plugin_conf {
+    (
+        $(#[$meta:meta])*
+        $name:ident = $type:literal {
+            $( $(#[$fmeta:meta])* $field:ident: $ty:ty => $setter:ident, )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        pub struct $name {
+            #[serde(flatten)]
+            pub common: Common,
+            $(
+                $(#[$fmeta])*
+                #[serde(default, skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$ty>,
+            )*
+            #[serde(flatten)]
+            pub extra: Map<String, Value>,
+        }
+
+        impl $name {
+            pub fn new() -> Self {
+                Self::default()
+            }
+
+            pub fn with_cni_version(mut self, version: impl Into<String>) -> Self {
+                self.common.cni_version = version.into();
+                self
+            }
+
+            pub fn with_name(mut self, name: impl Into<String>) -> Self {
+                self.common.name = name.into();
+                self
+            }
+
+            pub fn with_capability(mut self, capability: impl Into<String>, enabled: bool) -> Self {
+                self.common.capabilities.insert(capability.into(), enabled);
+                self
+            }
+
+            $(
+                pub fn $setter(mut self, value: impl Into<$ty>) -> Self {
+                    self.$field = Some(value.into());
+                    self
+                }
+            )*
+        }
+
+        impl PluginConf for $name {
+            const TYPE: &'static str = $type;
+        }
+    };
+}
+
+plugin_conf! {
+    /// Config of the `bridge` plugin.
+    BridgeConf = "bridge" {
+        bridge: String => with_bridge,
+        is_gateway: bool => with_is_gateway,
+        is_default_gateway: bool => with_is_default_gateway,
+        force_address: bool => with_force_address,
+        ip_masq: bool => with_ip_masq,
+        mtu: u32 => with_mtu,
+        hairpin_mode: bool => with_hairpin_mode,
+        promisc_mode: bool => with_promisc_mode,
+        vlan: u16 => with_vlan,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `ptp` plugin.
+    PtpConf = "ptp" {
+        ip_masq: bool => with_ip_masq,
+        mtu: u32 => with_mtu,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `macvlan` plugin. `mode` is one of bridge, private,
+    /// vepa or passthru.
+    MacvlanConf = "macvlan" {
+        master: String => with_master,
+        mode: String => with_mode,
+        mtu: u32 => with_mtu,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `ipvlan` plugin. `mode` is one of l2, l3 or l3s.
+    IpvlanConf = "ipvlan" {
+        master: String => with_master,
+        mode: String => with_mode,
+        mtu: u32 => with_mtu,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `host-device` plugin, the device is picked by one of
+    /// its fields.
+    HostDeviceConf = "host-device" {
+        device: String => with_device,
+        hwaddr: String => with_hwaddr,
+        kernelpath: String => with_kernelpath,
+        #[serde(rename = "pciBusID")]
+        pci_bus_id: String => with_pci_bus_id,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `vlan` plugin.
+    VlanConf = "vlan" {
+        master: String => with_master,
+        vlan_id: u16 => with_vlan_id,
+        mtu: u32 => with_mtu,
+        ipam: IpamConf => with_ipam,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `tuning` plugin.
+    TuningConf = "tuning" {
+        sysctl: BTreeMap<String, String> => with_sysctls,
+        mac: String => with_mac,
+        promisc: bool => with_promisc,
+        mtu: u32 => with_mtu,
+        allmulti: bool => with_allmulti,
+    }
+}
+
+impl TuningConf {
+    pub fn with_sysctl(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.sysctl
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+}
+
+plugin_conf! {
+    /// Config of the `portmap` plugin, which takes its mappings from the
+    /// `portMappings` capability.
+    PortmapConf = "portmap" {
+        snat: bool => with_snat,
+        mark_masq_bit: u32 => with_mark_masq_bit,
+        external_set_mark_chain: String => with_external_set_mark_chain,
+        conditions_v4: Vec<String> => with_conditions_v4,
+        conditions_v6: Vec<String> => with_conditions_v6,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `bandwidth` plugin, rates in bits and bursts in bytes.
+    BandwidthConf = "bandwidth" {
+        ingress_rate: u64 => with_ingress_rate,
+        ingress_burst: u64 => with_ingress_burst,
+        egress_rate: u64 => with_egress_rate,
+        egress_burst: u64 => with_egress_burst,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `firewall` plugin. `backend` is iptables or firewalld.
+    FirewallConf = "firewall" {
+        backend: String => with_backend,
+        iptables_admin_chain_name: String => with_iptables_admin_chain_name,
+        firewalld_zone: String => with_firewalld_zone,
+    }
+}
+
+plugin_conf! {
+    /// Config of the `sbr` plugin.
+    SbrConf = "sbr" {
+        table: u32 => with_table,
+    }
+}
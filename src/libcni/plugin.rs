@@ -4,12 +4,12 @@ pub trait PluginInfo {
     fn encode<W: std::io::Write>(&self, w: W) -> super::ResultCNI<()>;
 }
 
-#[derive(serde::Serialize, serde::Deserialize)]
-struct PluginInfoT {
-    #[serde(rename = "cniVersion")]
-    cni_version: String,
-    #[serde(rename = "supportedVersions")]
-    supported_versions: Vec<String>,
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct PluginInfoT {
+    #[serde(rename = "cniVersion", default)]
+    pub cni_version: String,
+    #[serde(rename = "supportedVersions", default)]
+    pub supported_versions: Vec<String>,
 }
 
 impl PluginInfo for PluginInfoT {
@@ -17,7 +17,45 @@ impl PluginInfo for PluginInfoT {
         self.supported_versions.clone()
     }
 
-    fn encode<W: std::io::Write>(&self, _w: W) -> super::ResultCNI<()> {
-        todo!()
+    fn encode<W: std::io::Write>(&self, w: W) -> super::ResultCNI<()> {
+        serde_json::to_writer(w, self)
+            .map_err(|e| Box::new(super::CNIError::VarDecode(e.to_string())))
+    }
+}
+
+/// Versions of the CNI spec, oldest first.
+pub const SPEC_VERSIONS: [&str; 8] = [
+    "0.1.0", "0.2.0", "0.3.0", "0.3.1", "0.4.0", "1.0.0", "1.1.0", "1.2.0",
+];
+
+/// Spec versions the plugins of this crate support, the ones with network
+/// config lists: 0.3.0 and later.
+pub fn native_plugin_versions() -> Vec<String> {
+    SPEC_VERSIONS
+        .iter()
+        .filter(|v| version_at_least(v, "0.3.0"))
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Parses a `major.minor.patch` CNI version; a missing patch counts as 0.
+pub fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    let patch = match parts.next() {
+        Some(p) => p.parse().ok()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+pub fn version_at_least(version: &str, min: &str) -> bool {
+    match (parse_version(version), parse_version(min)) {
+        (Some(v), Some(m)) => v >= m,
+        _ => false,
     }
 }
@@ -0,0 +1,128 @@
+use log::{debug, trace, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use super::plugin::PluginInfoT;
+use super::ResultCNI;
+
+/// Identity of a plugin binary on disk. A cached entry is only reused while
+/// the binary still has the same inode and modification time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct BinaryStamp {
+    dev: u64,
+    ino: u64,
+    mtime: i64,
+    mtime_nsec: i64,
+    size: u64,
+}
+
+impl BinaryStamp {
+    fn of(plugin_path: &str) -> Option<Self> {
+        match fs::metadata(plugin_path) {
+            Ok(meta) => Some(BinaryStamp {
+                dev: meta.dev(),
+                ino: meta.ino(),
+                mtime: meta.mtime(),
+                mtime_nsec: meta.mtime_nsec(),
+                size: meta.size(),
+            }),
+            Err(e) => {
+                warn!("Failed to stat plugin binary {}: {}", plugin_path, e);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PluginCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Cache of `VERSION` responses keyed by the resolved plugin binary path.
+#[derive(Default)]
+pub struct PluginInfoCache {
+    entries: Mutex<HashMap<String, (BinaryStamp, PluginInfoT)>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl PluginInfoCache {
+    /// Returns the cached info for `plugin_path`, or runs `probe` and caches
+    /// what it returns. A `None` from `probe` is passed through uncached so the
+    /// next lookup probes the plugin again.
+    pub fn get_or_probe<F>(&self, plugin_path: &str, probe: F) -> ResultCNI<Option<PluginInfoT>>
+    where
+        F: FnOnce() -> ResultCNI<Option<PluginInfoT>>,
+    {
+        let stamp = match self.lookup(plugin_path) {
+            Ok(info) => return Ok(Some(info)),
+            Err(stamp) => stamp,
+        };
+        let info = probe()?;
+        self.store(plugin_path, stamp, &info);
+        Ok(info)
+    }
+
+    /// Cache lookup for callers that probe on their own. On a miss the
+    /// binary's current identity is returned, to be handed to `store`.
+    pub(crate) fn lookup(&self, plugin_path: &str) -> Result<PluginInfoT, Option<BinaryStamp>> {
+        // Stat before probing so a binary replaced during the probe is not
+        // cached under its new identity.
+        let stamp = BinaryStamp::of(plugin_path);
+
+        if let Some(stamp) = stamp {
+            let entries = self.entries.lock().unwrap();
+            if let Some((cached_stamp, info)) = entries.get(plugin_path) {
+                if *cached_stamp == stamp {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    trace!("Plugin info cache hit for {}", plugin_path);
+                    return Ok(info.clone());
+                }
+                debug!("Plugin binary {} changed, invalidating cache", plugin_path);
+            }
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        trace!("Plugin info cache miss for {}", plugin_path);
+        Err(stamp)
+    }
+
+    pub(crate) fn store(
+        &self,
+        plugin_path: &str,
+        stamp: Option<BinaryStamp>,
+        info: &Option<PluginInfoT>,
+    ) {
+        let mut entries = self.entries.lock().unwrap();
+        match (info, stamp) {
+            (Some(info), Some(stamp)) => {
+                entries.insert(plugin_path.to_string(), (stamp, info.clone()));
+            }
+            _ => {
+                entries.remove(plugin_path);
+            }
+        }
+    }
+
+    pub fn invalidate(&self, plugin_path: &str) {
+        self.entries.lock().unwrap().remove(plugin_path);
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    pub fn stats(&self) -> PluginCacheStats {
+        PluginCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
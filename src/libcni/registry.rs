@@ -0,0 +1,143 @@
+use log::debug;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::exec::{check_plugin_output, parse_environ};
+use super::result::ResultCNI;
+
+/// A plugin implemented in Rust and called in-process. It gets what an
+/// external binary would: the network config as `stdin` and the CNI_*
+/// variables in `env`. It returns what the binary would print on stdout,
+/// an error object included.
+pub trait NativePlugin: Send + Sync {
+    fn exec(&self, stdin: &[u8], env: &HashMap<String, String>) -> ResultCNI<Vec<u8>>;
+}
+
+impl<F> NativePlugin for F
+where
+    F: Fn(&[u8], &HashMap<String, String>) -> ResultCNI<Vec<u8>> + Send + Sync,
+{
+    fn exec(&self, stdin: &[u8], env: &HashMap<String, String>) -> ResultCNI<Vec<u8>> {
+        self(stdin, env)
+    }
+}
+
+/// Where `CNIConfig` looks for a plugin type first.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LookupOrder {
+    /// Registered plugins shadow binaries of the same name.
+    #[default]
+    RegistryFirst,
+    /// Registered plugins are only used when no binary is found.
+    DiskFirst,
+}
+
+/// Native plugins by plugin `type`.
+#[derive(Default, Clone)]
+pub struct PluginRegistry {
+    plugins: HashMap<String, Arc<dyn NativePlugin>>,
+    order: LookupOrder,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_order(mut self, order: LookupOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn with_plugin(mut self, name: &str, plugin: impl NativePlugin + 'static) -> Self {
+        self.register(name, plugin);
+        self
+    }
+
+    /// Registers `plugin` under `name`, replacing any previous one.
+    pub fn register(&mut self, name: &str, plugin: impl NativePlugin + 'static) {
+        debug!("Registering native plugin {}", name);
+        self.plugins.insert(name.to_string(), Arc::new(plugin));
+    }
+
+    pub fn unregister(&mut self, name: &str) -> bool {
+        self.plugins.remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn NativePlugin>> {
+        self.plugins.get(name).cloned()
+    }
+
+    pub fn order(&self) -> LookupOrder {
+        self.order
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    /// Resolves `plugin` in the configured order, `find` searching the disk.
+    pub(crate) fn resolve<F>(&self, plugin: &str, find: F) -> ResultCNI<PluginTarget>
+    where
+        F: FnOnce() -> ResultCNI<String>,
+    {
+        let native = || {
+            self.get(plugin).map(|p| {
+                debug!("Using native plugin {}", plugin);
+                PluginTarget::Native(plugin.to_string(), p)
+            })
+        };
+        match self.order {
+            LookupOrder::RegistryFirst => match native() {
+                Some(target) => Ok(target),
+                None => find().map(PluginTarget::External),
+            },
+            LookupOrder::DiskFirst => match find() {
+                Ok(path) => Ok(PluginTarget::External(path)),
+                Err(e) => native().ok_or(e),
+            },
+        }
+    }
+}
+
+impl fmt::Debug for PluginRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut names: Vec<&String> = self.plugins.keys().collect();
+        names.sort();
+        f.debug_struct("PluginRegistry")
+            .field("plugins", &names)
+            .field("order", &self.order)
+            .finish()
+    }
+}
+
+/// A resolved plugin: registered, or the path of a binary.
+#[derive(Clone)]
+pub enum PluginTarget {
+    Native(String, Arc<dyn NativePlugin>),
+    External(String),
+}
+
+impl PluginTarget {
+    /// Runs a native plugin. External ones are left to the caller's `Exec`.
+    pub(crate) fn exec_native(
+        name: &str,
+        plugin: &dyn NativePlugin,
+        stdin_data: &[u8],
+        environ: &[String],
+    ) -> ResultCNI<Vec<u8>> {
+        debug!("Calling native plugin: {}", name);
+        let env = parse_environ(environ);
+        check_plugin_output(plugin.exec(stdin_data, &env)?)
+    }
+}
+
+impl fmt::Display for PluginTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PluginTarget::Native(name, _) => write!(f, "native:{}", name),
+            PluginTarget::External(path) => write!(f, "{}", path),
+        }
+    }
+}
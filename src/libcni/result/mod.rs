@@ -1,12 +1,13 @@
 // Copyright (c) 2024 https://github.com/divinerapier/cni-rs
 use json::JsonValue;
 
+pub mod result040;
 pub mod result100;
 
 pub type ResultCNI<T> = std::result::Result<T, Box<super::error::CNIError>>;
 
 #[typetag::serde(tag = "type")]
-pub trait APIResult {
+pub trait APIResult: Send + Sync {
     fn version(&self) -> String;
     fn get_as_version(&self, version: String) -> ResultCNI<Box<dyn APIResult>>;
     fn print(&self) -> ResultCNI<()>;
@@ -14,3 +15,26 @@ pub trait APIResult {
     fn get_json(&self) -> JsonValue;
     fn clone_box(&self) -> Box<dyn APIResult>;
 }
+
+/// `result` in the format of the `version` spec.
+pub(crate) fn convert(
+    mut result: result100::Result,
+    version: String,
+) -> ResultCNI<Box<dyn APIResult>> {
+    match version.as_str() {
+        "1.0.0" | "1.1.0" | "1.2.0" => {
+            result.cni_version = Some(version);
+            Ok(Box::new(result))
+        }
+        "0.3.0" | "0.3.1" | "0.4.0" => {
+            result.cni_version = Some(version);
+            Ok(Box::new(result040::Result::from(&result)))
+        }
+        _ => Err(Box::new(super::CNIError::Plugin(
+            super::error::PluginError::new(
+                super::error::CODE_INCOMPATIBLE_CNI_VERSION,
+                &format!("cannot convert result to version {:?}", version),
+            ),
+        ))),
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::stdout;
+
+use json::JsonValue;
+use serde::{Deserialize, Serialize};
+use serde_json::to_string;
+
+use crate::libcni::CNIError;
+
+use super::result100::{self, Interface};
+use super::APIResult;
+
+/// Result of the 0.3.0, 0.3.1 and 0.4.0 specs, where each IP carries its
+/// version.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct IPConfig {
+    pub version: String,
+    #[serde(rename = "interface")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interface: Option<usize>,
+    #[serde(rename = "address")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<ipnetwork::IpNetwork>,
+    #[serde(rename = "gateway")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gateway: Option<std::net::IpAddr>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
+pub struct Result {
+    #[serde(rename = "cniVersion")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cni_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interfaces: Option<Vec<Interface>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ips: Option<Vec<IPConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub routes: Option<Vec<super::super::types::Route>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<super::super::types::DNS>,
+}
+
+impl From<&result100::Result> for Result {
+    fn from(result: &result100::Result) -> Self {
+        let ips = result.ips.as_ref().map(|ips| {
+            ips.iter()
+                .map(|ip| {
+                    let v6 = match (ip.address, ip.gateway) {
+                        (Some(address), _) => address.is_ipv6(),
+                        (None, Some(gateway)) => gateway.is_ipv6(),
+                        _ => false,
+                    };
+                    IPConfig {
+                        version: if v6 { "6" } else { "4" }.to_string(),
+                        interface: ip.interface,
+                        address: ip.address,
+                        gateway: ip.gateway,
+                    }
+                })
+                .collect()
+        });
+        Result {
+            cni_version: result.cni_version.clone(),
+            interfaces: result.interfaces.clone(),
+            ips,
+            routes: result.routes.clone(),
+            dns: result.dns.clone(),
+        }
+    }
+}
+
+impl From<&Result> for result100::Result {
+    fn from(result: &Result) -> Self {
+        let ips = result.ips.as_ref().map(|ips| {
+            ips.iter()
+                .map(|ip| result100::IPConfig {
+                    interface: ip.interface,
+                    address: ip.address,
+                    gateway: ip.gateway,
+                })
+                .collect()
+        });
+        result100::Result {
+            cni_version: result.cni_version.clone(),
+            interfaces: result.interfaces.clone(),
+            ips,
+            routes: result.routes.clone(),
+            dns: result.dns.clone(),
+        }
+    }
+}
+
+#[typetag::serde(name = "Result040")]
+impl APIResult for Result {
+    fn version(&self) -> String {
+        self.cni_version.clone().unwrap_or_default()
+    }
+
+    fn get_as_version(&self, version: String) -> super::ResultCNI<Box<dyn APIResult>> {
+        super::convert(result100::Result::from(self), version)
+    }
+
+    fn print(&self) -> super::ResultCNI<()> {
+        self.print_to(Box::new(stdout()))
+    }
+
+    fn print_to(&self, mut w: Box<dyn std::io::Write>) -> super::ResultCNI<()> {
+        let json_data = to_string(&self).map_err(|e| CNIError::VarDecode(e.to_string()))?;
+        w.write_all(json_data.as_bytes())
+            .map_err(|e| CNIError::Io(Box::new(e)))?;
+        Ok(())
+    }
+
+    fn get_json(&self) -> JsonValue {
+        let js_string = to_string(&self).unwrap();
+        json::parse(&js_string).unwrap()
+    }
+
+    fn clone_box(&self) -> Box<dyn APIResult> {
+        Box::new(self.clone())
+    }
+}
@@ -59,8 +59,8 @@ impl APIResult for Result {
         String::default()
     }
 
-    fn get_as_version(&self, _version: String) -> super::ResultCNI<Box<dyn APIResult>> {
-        Ok(Box::<Result>::default())
+    fn get_as_version(&self, version: String) -> super::ResultCNI<Box<dyn APIResult>> {
+        super::convert(self.clone(), version)
     }
 
     fn print(&self) -> super::ResultCNI<()> {
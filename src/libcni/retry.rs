@@ -0,0 +1,149 @@
+use log::{debug, warn};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::Duration;
+
+use super::{CNIError, ResultCNI};
+
+/// How plugin invocations failing with `CODE_TRY_AGAIN_LATER` are retried.
+/// The default makes a single attempt, i.e. never retries.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry.
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after every retry.
+    pub multiplier: f64,
+    /// Fraction of the delay, between 0 and 1, that is randomized.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay before retry number `retry` (starting at 1), without jitter.
+    /// A multiplier below 1 counts as 1.
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(retry.saturating_sub(1) as i32);
+        let secs = self.initial_backoff.as_secs_f64() * factor;
+        Duration::from_secs_f64(secs.min(self.max_backoff.as_secs_f64()))
+    }
+
+    fn jittered(&self, delay: Duration) -> Duration {
+        // The fields are public, so they are clamped here and not only in
+        // `with_jitter`; a factor below zero would make `mul_f64` panic.
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter.is_nan() || jitter <= 0.0 {
+            return delay;
+        }
+        // A randomly seeded hasher is enough entropy for spreading retries
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        delay.mul_f64((1.0 - jitter + 2.0 * jitter * random).max(0.0))
+    }
+
+    /// Runs `op`, retrying it while it fails with `CODE_TRY_AGAIN_LATER`.
+    pub(crate) fn run<T, F>(&self, what: &str, mut op: F) -> ResultCNI<T>
+    where
+        F: FnMut() -> ResultCNI<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            debug!("{} attempt {}/{}", what, attempt, self.attempts());
+            match op() {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    let delay = self.next_delay(what, attempt, e)?;
+                    thread::sleep(delay);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Async flavour of `run`, sleeping on the tokio timer.
+    #[cfg(feature = "async")]
+    pub(crate) async fn run_async<T, F, Fut>(&self, what: &str, mut op: F) -> ResultCNI<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = ResultCNI<T>>,
+    {
+        let mut attempt = 1;
+        loop {
+            debug!("{} attempt {}/{}", what, attempt, self.attempts());
+            match op().await {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    let delay = self.next_delay(what, attempt, e)?;
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn attempts(&self) -> u32 {
+        self.max_attempts.max(1)
+    }
+
+    // Decides whether failed attempt number `attempt` is retried, and after
+    // how long. Returns the error to report when giving up.
+    fn next_delay(&self, what: &str, attempt: u32, e: Box<CNIError>) -> ResultCNI<Duration> {
+        let max_attempts = self.attempts();
+        if e.is_try_again_later() && attempt < max_attempts {
+            let delay = self.jittered(self.backoff(attempt));
+            warn!(
+                "{} attempt {}/{} failed: {}, retrying in {:?}",
+                what, attempt, max_attempts, e, delay
+            );
+            return Ok(delay);
+        }
+        if attempt > 1 || e.is_try_again_later() {
+            warn!(
+                "{} attempt {}/{} failed: {}, giving up",
+                what, attempt, max_attempts, e
+            );
+            return Err(Box::new(CNIError::RetriesExhausted(attempt, e)));
+        }
+        Err(e)
+    }
+}
@@ -0,0 +1,185 @@
+use log::{debug, error, trace};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+
+use super::{CNIError, ResultCNI};
+
+/// Hardened plugin lookup. When set on a `RawExec`, every plugin binary must be
+/// a root-owned, executable regular file writable by root only, and plugin types
+/// may not contain path separators. With an allowlist loaded, only pinned
+/// binaries with a matching SHA-256 digest are executed.
+#[derive(Clone, Debug, Default)]
+pub struct PluginSecurity {
+    // hex digest keyed by plugin type or absolute binary path
+    digests: HashMap<String, String>,
+}
+
+impl PluginSecurity {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads pinned digests from a file in `sha256sum` format, one
+    /// `<hex digest>  <plugin type or absolute path>` entry per line.
+    pub fn with_allowlist_file(mut self, path: &str) -> ResultCNI<Self> {
+        debug!("Loading plugin allowlist from {}", path);
+        let content = fs::read_to_string(path).map_err(|e| Box::new(CNIError::from(e)))?;
+
+        for (lineno, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(digest), Some(plugin), None) => {
+                    // sha256sum marks binary mode with a leading '*'
+                    let plugin = plugin.trim_start_matches('*');
+                    self = self.with_digest(plugin, digest)?;
+                }
+                _ => {
+                    return Err(Box::new(CNIError::Config(format!(
+                        "invalid allowlist entry at {}:{}",
+                        path,
+                        lineno + 1
+                    ))))
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    pub fn with_digest(mut self, plugin: &str, digest: &str) -> ResultCNI<Self> {
+        let digest = digest.to_ascii_lowercase();
+        if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Box::new(CNIError::Config(format!(
+                "invalid SHA-256 digest for plugin {}",
+                plugin
+            ))));
+        }
+        self.digests.insert(plugin.to_string(), digest);
+        Ok(self)
+    }
+
+    pub fn is_pinning(&self) -> bool {
+        !self.digests.is_empty()
+    }
+
+    pub fn check_plugin_name(plugin: &str) -> ResultCNI<()> {
+        if plugin.is_empty() || plugin.contains('/') || plugin == "." || plugin == ".." {
+            error!("Refusing plugin type {:?}", plugin);
+            return Err(Box::new(CNIError::InvalidFilename(plugin.to_string())));
+        }
+        Ok(())
+    }
+
+    pub fn verify_binary(&self, plugin: &str, plugin_path: &str) -> ResultCNI<()> {
+        self.open_verified(plugin, plugin_path).map(|_| ())
+    }
+
+    /// Opens the plugin binary and verifies the opened file, so executing it
+    /// through `/proc/self/fd` runs exactly what was checked. The binary may
+    /// not be a symlink and its directory must be root-owned and writable by
+    /// root only, as must every ancestor unless it is sticky, like `/tmp`.
+    pub fn open_verified(&self, plugin: &str, plugin_path: &str) -> ResultCNI<File> {
+        trace!("Verifying plugin binary {}", plugin_path);
+        let insecure = |reason: &str| {
+            Box::new(CNIError::InsecurePlugin(
+                plugin_path.to_string(),
+                reason.to_string(),
+            ))
+        };
+
+        let path = Path::new(plugin_path);
+        let link = fs::symlink_metadata(path).map_err(|e| Box::new(CNIError::from(e)))?;
+        if link.file_type().is_symlink() {
+            return Err(insecure("is a symlink"));
+        }
+        let (dir, file_name) = match (path.parent(), path.file_name()) {
+            (Some(dir), Some(file_name)) => (dir, file_name),
+            _ => return Err(insecure("not in a plugin directory")),
+        };
+        let dir = fs::canonicalize(dir).map_err(|e| Box::new(CNIError::from(e)))?;
+        for (i, ancestor) in dir.ancestors().enumerate() {
+            let meta = fs::metadata(ancestor).map_err(|e| Box::new(CNIError::from(e)))?;
+            let mode = meta.permissions().mode();
+            if meta.uid() != 0 {
+                return Err(insecure(&format!(
+                    "directory {} owned by uid {}",
+                    ancestor.display(),
+                    meta.uid()
+                )));
+            }
+            // Entries of a sticky directory can only be replaced by their owner
+            if mode & 0o022 != 0 && (i == 0 || mode & 0o1000 == 0) {
+                return Err(insecure(&format!(
+                    "directory {} is group or world-writable",
+                    ancestor.display()
+                )));
+            }
+        }
+
+        // Every component was checked above, the file itself is not followed
+        let file = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC)
+            .open(dir.join(file_name))
+            .map_err(|e| Box::new(CNIError::from(e)))?;
+        let meta = file.metadata().map_err(|e| Box::new(CNIError::from(e)))?;
+        if !meta.is_file() {
+            return Err(insecure("not a regular file"));
+        }
+        let mode = meta.permissions().mode();
+        if mode & 0o111 == 0 {
+            return Err(insecure("not executable"));
+        }
+        if mode & 0o022 != 0 {
+            return Err(insecure("group or world-writable"));
+        }
+        if meta.uid() != 0 {
+            return Err(insecure(&format!("owned by uid {}", meta.uid())));
+        }
+
+        if self.is_pinning() {
+            let expected = self
+                .digests
+                .get(plugin_path)
+                .or_else(|| self.digests.get(plugin))
+                .ok_or_else(|| insecure("not in plugin allowlist"))?;
+            let actual = sha256_file(&file)?;
+            if &actual != expected {
+                error!(
+                    "Digest mismatch for {}: expected {}, got {}",
+                    plugin_path, expected, actual
+                );
+                return Err(Box::new(CNIError::InvalidChecksum(plugin_path.to_string())));
+            }
+        }
+
+        debug!("Plugin binary {} passed security checks", plugin_path);
+        Ok(file)
+    }
+}
+
+fn sha256_file(mut file: &File) -> ResultCNI<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| Box::new(CNIError::from(e)))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
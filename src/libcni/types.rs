@@ -2,6 +2,12 @@
 
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use super::api::NetworkConfigList;
+use super::registry::PluginRegistry;
+use super::retry::RetryPolicy;
 
 #[derive(Default, Clone)]
 pub struct Config {
@@ -9,6 +15,64 @@ pub struct Config {
     pub plugin_conf_dir: String,
     pub plugin_max_conf_num: i64,
     pub prefix: String,
+    pub cache_dir: String,
+    pub retry: RetryPolicy,
+    pub setup_mode: SetupMode,
+    pub ifname_policy: IfnamePolicy,
+    pub registry: PluginRegistry,
+    /// Verify the namespace against the plugin results after ADD and CHECK,
+    /// failing on any difference.
+    pub verify_results: bool,
+}
+
+/// Names the interface of a network from its index and config.
+pub type IfnameFn = dyn Fn(usize, &NetworkConfigList) -> String + Send + Sync;
+
+/// How `Libcni` names the interface of each loaded network. An
+/// `interfaceName` in the network config always takes precedence.
+#[derive(Clone, Default)]
+pub enum IfnamePolicy {
+    /// `prefix` followed by the index of the network: vethcni0, vethcni1...
+    #[default]
+    Prefix,
+    /// eth0 for the first network, net1, net2... for the others.
+    Kubernetes,
+    /// Called with the index of the network and its config.
+    Custom(Arc<IfnameFn>),
+}
+
+impl IfnamePolicy {
+    pub fn ifname(&self, prefix: &str, index: usize, net: &NetworkConfigList) -> String {
+        if let Some(ifname) = &net.ifname {
+            return ifname.clone();
+        }
+        match self {
+            IfnamePolicy::Prefix => format!("{}{}", prefix, index),
+            IfnamePolicy::Kubernetes if index == 0 => "eth0".to_string(),
+            IfnamePolicy::Kubernetes => format!("net{}", index),
+            IfnamePolicy::Custom(name) => name(index, net),
+        }
+    }
+}
+
+impl fmt::Debug for IfnamePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IfnamePolicy::Prefix => write!(f, "Prefix"),
+            IfnamePolicy::Kubernetes => write!(f, "Kubernetes"),
+            IfnamePolicy::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// How `Libcni::setup` attaches the loaded networks.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetupMode {
+    /// One network after another, in load order.
+    #[default]
+    Serial,
+    /// Loopback networks first, then every other network chain on its own thread.
+    Parallel,
 }
 #[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct NetworkConfig {
@@ -29,9 +93,40 @@ pub struct NetConf {
     pub _type: String,
     #[serde(default)]
     pub capabilities: HashMap<String, bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ipam: Option<IPAM>,
+}
+
+/// Plugin search path of a single network, relative to the runtime's plugin dirs.
+#[derive(Default, Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum PluginDirs {
+    #[default]
+    Inherit,
+    /// Searched before the runtime's plugin dirs.
+    Extend(Vec<String>),
+    /// Replaces the runtime's plugin dirs.
+    Override(Vec<String>),
 }
 
-#[derive(Serialize, Deserialize)]
+impl PluginDirs {
+    pub fn resolve(&self, defaults: &[String]) -> Vec<String> {
+        match self {
+            PluginDirs::Inherit => defaults.to_vec(),
+            PluginDirs::Extend(dirs) => {
+                let mut paths = dirs.clone();
+                for dir in defaults {
+                    if !paths.contains(dir) {
+                        paths.push(dir.clone());
+                    }
+                }
+                paths
+            }
+            PluginDirs::Override(dirs) => dirs.clone(),
+        }
+    }
+}
+
+#[derive(Default, Clone, Serialize, Deserialize, Debug)]
 pub struct IPAM {
     #[serde(rename = "type")]
     pub _type: String,
@@ -44,7 +139,7 @@ pub struct NetConfList {
     pub plugins: Vec<NetworkConfig>,
 }
 
-#[derive(Clone, Serialize, Deserialize, Default)]
+#[derive(Clone, Serialize, Deserialize, Default, Debug, PartialEq, Eq)]
 pub struct DNS {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub nameservers: Option<Vec<String>>,
@@ -56,7 +151,7 @@ pub struct DNS {
     pub options: Option<Vec<String>>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub struct Route {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename = "dst")]
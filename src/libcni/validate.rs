@@ -0,0 +1,92 @@
+use std::ffi::CString;
+use std::path::Path;
+
+use super::api::RuntimeConf;
+use super::{CNIError, ResultCNI};
+
+// IFNAMSIZ minus the trailing NUL
+const MAX_IFNAME_LEN: usize = 15;
+// Not exported by every libc version
+const NSFS_MAGIC: libc::c_long = 0x6e73_6673;
+
+fn invalid(msg: String) -> Box<CNIError> {
+    Box::new(CNIError::InvalidEnvironmentVariables(msg))
+}
+
+/// Checks `ifname` against the rules the kernel applies to interface names.
+pub fn validate_ifname(ifname: &str) -> ResultCNI<()> {
+    let reason = if ifname.is_empty() {
+        "is empty"
+    } else if ifname.len() > MAX_IFNAME_LEN {
+        "is longer than 15 bytes"
+    } else if ifname == "." || ifname == ".." {
+        "is not a valid name"
+    } else if ifname.contains(['/', ':', '\0']) {
+        "contains '/', ':' or NUL"
+    } else if ifname.chars().any(char::is_whitespace) {
+        "contains whitespace"
+    } else {
+        return Ok(());
+    };
+    Err(invalid(format!("interface name {:?} {}", ifname, reason)))
+}
+
+/// Container IDs must match `[a-zA-Z0-9][a-zA-Z0-9_.-]*`.
+pub fn validate_container_id(id: &str) -> ResultCNI<()> {
+    let mut chars = id.chars();
+    let valid = match chars.next() {
+        Some(first) => {
+            first.is_ascii_alphanumeric()
+                && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(invalid(format!("invalid container id {:?}", id)));
+    }
+    Ok(())
+}
+
+/// `netns` must be an absolute path to a network namespace, i.e. an nsfs
+/// mount such as `/proc/<pid>/ns/net` or a bind mount of it.
+pub fn validate_netns(netns: &str) -> ResultCNI<()> {
+    if !Path::new(netns).is_absolute() {
+        return Err(invalid(format!(
+            "netns path {:?} is not an absolute path",
+            netns
+        )));
+    }
+    let path =
+        CString::new(netns).map_err(|_| invalid(format!("netns path {:?} contains NUL", netns)))?;
+
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        let e = std::io::Error::last_os_error();
+        return Err(invalid(format!("netns path {}: {}", netns, e)));
+    }
+    if stat.f_type as libc::c_long != NSFS_MAGIC {
+        return Err(invalid(format!(
+            "netns path {} is not a network namespace",
+            netns
+        )));
+    }
+    Ok(())
+}
+
+/// Checks `rt` before it is handed to plugins. For DEL the namespace may
+/// already be gone, so only a non-empty `net_ns` is checked, and only for
+/// being absolute.
+pub fn validate_runtime_conf(rt: &RuntimeConf, command: &str) -> ResultCNI<()> {
+    validate_container_id(&rt.container_id)?;
+    validate_ifname(&rt.if_name)?;
+    if command == "DEL" {
+        if !rt.net_ns.is_empty() && !Path::new(&rt.net_ns).is_absolute() {
+            return Err(invalid(format!(
+                "netns path {:?} is not an absolute path",
+                rt.net_ns
+            )));
+        }
+        return Ok(());
+    }
+    validate_netns(&rt.net_ns)
+}
@@ -0,0 +1,253 @@
+use log::{debug, warn};
+use std::io;
+
+use crate::libcni::{
+    api::{NetworkConfigList, RuntimeConf, CNI},
+    plugin::native_plugin_versions,
+    result::{result100, APIResult, ResultCNI},
+    types::NetworkConfig,
+    validate::validate_runtime_conf,
+    CNIError,
+};
+use crate::namespace::NetNs;
+
+const LOOPBACK: &str = "lo";
+
+/// Brings `lo` up in-process, doing what the reference `loopback` plugin
+/// does without exec'ing it. Nothing is cached, the result is always the
+/// same.
+#[derive(Default)]
+pub struct NativeLoopback;
+
+impl NativeLoopback {
+    fn result(cni_version: &str, rt: &RuntimeConf) -> result100::Result {
+        result100::Result {
+            cni_version: Some(cni_version.to_string()),
+            interfaces: Some(vec![result100::Interface {
+                name: Some(LOOPBACK.to_string()),
+                mac: Some("00:00:00:00:00:00".to_string()),
+                sandbox: Some(rt.net_ns.clone()),
+            }]),
+            ips: Some(vec![
+                result100::IPConfig {
+                    interface: Some(0),
+                    address: "127.0.0.1/8".parse().ok(),
+                    gateway: None,
+                },
+                result100::IPConfig {
+                    interface: Some(0),
+                    address: "::1/128".parse().ok(),
+                    gateway: None,
+                },
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn check_version(cni_version: &str) -> ResultCNI<()> {
+        if !native_plugin_versions().iter().any(|v| v == cni_version) {
+            return Err(Box::new(CNIError::Config(format!(
+                "loopback does not support cniVersion {}",
+                cni_version
+            ))));
+        }
+        Ok(())
+    }
+
+    fn set_up(rt: &RuntimeConf, up: bool) -> ResultCNI<()> {
+        let netns = NetNs::open(&rt.net_ns).map_err(|e| Box::new(CNIError::ExecuteError(e)))?;
+        netns
+            .run(|| set_link_up(LOOPBACK, up))
+            .map_err(|e| Box::new(CNIError::ExecuteError(e)))?
+            .map_err(|e| {
+                Box::new(CNIError::ExecuteError(format!(
+                    "failed to set {} {}: {}",
+                    LOOPBACK,
+                    if up { "up" } else { "down" },
+                    e
+                )))
+            })
+    }
+
+    fn add(&self, cni_version: &str, rt: RuntimeConf) -> ResultCNI<Box<dyn APIResult>> {
+        validate_runtime_conf(&rt, "ADD")?;
+        Self::check_version(cni_version)?;
+        debug!("Bringing up {} in {}", LOOPBACK, rt.net_ns);
+        Self::set_up(&rt, true)?;
+        Ok(Box::new(Self::result(cni_version, &rt)))
+    }
+
+    fn check(&self, cni_version: &str, rt: RuntimeConf) -> ResultCNI<()> {
+        validate_runtime_conf(&rt, "CHECK")?;
+        Self::check_version(cni_version)?;
+        let netns = NetNs::open(&rt.net_ns).map_err(|e| Box::new(CNIError::ExecuteError(e)))?;
+        let up = netns
+            .run(|| link_is_up(LOOPBACK))
+            .map_err(|e| Box::new(CNIError::ExecuteError(e)))?
+            .map_err(|e| Box::new(CNIError::Io(Box::new(e))))?;
+        if !up {
+            return Err(Box::new(CNIError::ExecuteError(format!(
+                "{} is down in {}",
+                LOOPBACK, rt.net_ns
+            ))));
+        }
+        Ok(())
+    }
+
+    fn delete(&self, rt: RuntimeConf) -> ResultCNI<()> {
+        validate_runtime_conf(&rt, "DEL")?;
+        if rt.net_ns.is_empty() {
+            return Ok(());
+        }
+        // Like the reference plugin, a namespace that is already gone is fine
+        if let Err(e) = Self::set_up(&rt, false) {
+            warn!("Failed to bring down {} in {}: {}", LOOPBACK, rt.net_ns, e);
+        }
+        Ok(())
+    }
+}
+
+impl CNI for NativeLoopback {
+    fn add_network_list(
+        &self,
+        net: NetworkConfigList,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        self.add(&net.cni_version, rt)
+    }
+
+    fn check_network_list(&self, net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
+        if net.disable_check {
+            return Ok(());
+        }
+        self.check(&net.cni_version, rt)
+    }
+
+    fn delete_network_list(&self, _net: NetworkConfigList, rt: RuntimeConf) -> ResultCNI<()> {
+        self.delete(rt)
+    }
+
+    fn get_network_list_cached_result(
+        &self,
+        net: NetworkConfigList,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        Ok(Box::new(Self::result(&net.cni_version, &rt)))
+    }
+
+    fn get_status_network_list(&self, _net: NetworkConfigList) -> ResultCNI<()> {
+        Ok(())
+    }
+
+    fn add_network(
+        &self,
+        _name: String,
+        cni_version: String,
+        _net: NetworkConfig,
+        _prev_result: Option<Box<dyn APIResult>>,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        self.add(&cni_version, rt)
+    }
+
+    fn check_network(
+        &self,
+        _name: String,
+        cni_version: String,
+        _prev_result: Option<Box<dyn APIResult>>,
+        _net: NetworkConfig,
+        rt: RuntimeConf,
+    ) -> ResultCNI<()> {
+        self.check(&cni_version, rt)
+    }
+
+    fn delete_network(
+        &self,
+        _name: String,
+        _cni_version: String,
+        _net: NetworkConfig,
+        rt: RuntimeConf,
+    ) -> ResultCNI<()> {
+        self.delete(rt)
+    }
+
+    fn get_network_cached_result(
+        &self,
+        net: NetworkConfig,
+        rt: RuntimeConf,
+    ) -> ResultCNI<Box<dyn APIResult>> {
+        Ok(Box::new(Self::result(&net.network.cni_version, &rt)))
+    }
+
+    fn get_network_cached_config(
+        &self,
+        net: NetworkConfig,
+        _rt: RuntimeConf,
+    ) -> ResultCNI<(Vec<u8>, RuntimeConf)> {
+        Err(Box::new(CNIError::NotFound(
+            net.network.name,
+            "the native loopback does not cache its config".to_string(),
+        )))
+    }
+
+    fn validate_network_list(&self, net: NetworkConfigList) -> ResultCNI<Vec<String>> {
+        Self::check_version(&net.cni_version)?;
+        Ok(native_plugin_versions())
+    }
+
+    fn validate_network(&self, net: NetworkConfig) -> ResultCNI<Vec<String>> {
+        Self::check_version(&net.network.cni_version)?;
+        Ok(native_plugin_versions())
+    }
+}
+
+// The ioctls act on the namespace of the calling thread.
+fn with_ifreq<T>(
+    name: &str,
+    f: impl FnOnce(libc::c_int, &mut libc::ifreq) -> io::Result<T>,
+) -> io::Result<T> {
+    let mut req: libc::ifreq = unsafe { std::mem::zeroed() };
+    if name.len() >= req.ifr_name.len() {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+    for (dst, src) in req.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+
+    let sock = unsafe { libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0) };
+    if sock < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let result = f(sock, &mut req);
+    unsafe { libc::close(sock) };
+    result
+}
+
+fn link_flags(sock: libc::c_int, req: &mut libc::ifreq) -> io::Result<libc::c_short> {
+    if unsafe { libc::ioctl(sock, libc::SIOCGIFFLAGS as _, req as *mut libc::ifreq) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { req.ifr_ifru.ifru_flags })
+}
+
+fn link_is_up(name: &str) -> io::Result<bool> {
+    with_ifreq(name, |sock, req| {
+        Ok(link_flags(sock, req)? & libc::IFF_UP as libc::c_short != 0)
+    })
+}
+
+fn set_link_up(name: &str, up: bool) -> io::Result<()> {
+    with_ifreq(name, |sock, req| {
+        let flags = link_flags(sock, req)?;
+        let flags = if up {
+            flags | libc::IFF_UP as libc::c_short
+        } else {
+            flags & !(libc::IFF_UP as libc::c_short)
+        };
+        req.ifr_ifru.ifru_flags = flags;
+        if unsafe { libc::ioctl(sock, libc::SIOCSIFFLAGS as _, req as *mut libc::ifreq) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    })
+}
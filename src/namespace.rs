@@ -1,10 +1,22 @@
-use log::{debug, error, trace};
+use log::{debug, error, trace, warn};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
 use std::{collections::HashMap, sync::Arc};
 
 use crate::libcni::{
     self,
     api::{RuntimeConf, CNI},
+    types::PluginDirs,
+    validate::validate_netns,
 };
+use crate::verify::{verify_result, VerifyReport};
+
+pub const NETNS_RUN_DIR: &str = "/var/run/netns";
 
 pub struct Network {
     pub cni: Arc<Box<dyn CNI + Send + Sync>>,
@@ -13,6 +25,24 @@ pub struct Network {
 }
 
 impl Network {
+    pub fn with_plugin_dirs(mut self, plugin_dirs: PluginDirs) -> Self {
+        debug!(
+            "Setting plugin dirs {:?} for network {}",
+            plugin_dirs, self.config.name
+        );
+        self.config.plugin_dirs = plugin_dirs;
+        self
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        !self.config.plugins.is_empty()
+            && self
+                .config
+                .plugins
+                .iter()
+                .all(|p| p.network._type == "loopback")
+    }
+
     pub fn attach(&self, ns: &Namespace) -> Result<(), String> {
         debug!(
             "Attaching network {} with interface {}",
@@ -21,7 +51,7 @@ impl Network {
 
         match self
             .cni
-            .add_network_list(self.config.clone(), ns.config(self.ifname.clone()))
+            .add_network_list(self.config.clone(), ns.config(self.ifname.clone())?)
         {
             Ok(result) => {
                 debug!(
@@ -43,7 +73,7 @@ impl Network {
 
         match self
             .cni
-            .delete_network_list(self.config.clone(), ns.config(self.ifname.clone()))
+            .delete_network_list(self.config.clone(), ns.config(self.ifname.clone())?)
         {
             Ok(_) => {
                 debug!(
@@ -68,7 +98,7 @@ impl Network {
 
         match self
             .cni
-            .check_network_list(self.config.clone(), ns.config(self.ifname.clone()))
+            .check_network_list(self.config.clone(), ns.config(self.ifname.clone())?)
         {
             Ok(_) => {
                 debug!("Network {} is properly configured", self.config.name);
@@ -82,6 +112,31 @@ impl Network {
         }
     }
 
+    /// Checks the cached ADD result against the namespace, for plugins whose
+    /// CHECK does not look at anything.
+    pub fn verify(&self, ns: &Namespace) -> Result<VerifyReport, String> {
+        debug!(
+            "Verifying network {} with interface {}",
+            self.config.name, self.ifname
+        );
+
+        let result = self
+            .cni
+            .get_network_list_cached_result(self.config.clone(), ns.config(self.ifname.clone())?)
+            .map_err(|e| format!("No result to verify network {}: {}", self.config.name, e))?;
+        let report = match ns.get_netns() {
+            Some(netns) => verify_result(netns, result.as_ref())?,
+            None => verify_result(&NetNs::open(ns.get_path())?, result.as_ref())?,
+        };
+        if !report.is_ok() {
+            error!(
+                "Network {} verification failed: {}",
+                self.config.name, report
+            );
+        }
+        Ok(report)
+    }
+
     pub fn get_stats(&self, ns: &Namespace) -> Result<String, String> {
         debug!(
             "Getting stats for network {} with interface {}",
@@ -90,7 +145,7 @@ impl Network {
 
         match self
             .cni
-            .get_network_list_cached_result(self.config.clone(), ns.config(self.ifname.clone()))
+            .get_network_list_cached_result(self.config.clone(), ns.config(self.ifname.clone())?)
         {
             Ok(result) => {
                 let stats_json = result.get_json().dump();
@@ -109,12 +164,201 @@ impl Network {
     }
 }
 
+pub const K8S_POD_NAMESPACE: &str = "K8S_POD_NAMESPACE";
+pub const K8S_POD_NAME: &str = "K8S_POD_NAME";
+pub const K8S_POD_INFRA_CONTAINER_ID: &str = "K8S_POD_INFRA_CONTAINER_ID";
+pub const K8S_POD_UID: &str = "K8S_POD_UID";
+/// Tells plugins to ignore args they do not know, instead of failing.
+pub const IGNORE_UNKNOWN: &str = "IgnoreUnknown";
+
+/// A network a container joins, by name, with its per-network settings.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkSelection {
+    pub name: String,
+    /// Overrides the interface name the network was loaded with.
+    pub ifname: Option<String>,
+    pub args: HashMap<String, String>,
+    pub capabilities: HashMap<String, String>,
+}
+
+impl NetworkSelection {
+    pub fn new(name: &str) -> Self {
+        NetworkSelection {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_ifname(mut self, ifname: &str) -> Self {
+        self.ifname = Some(ifname.to_string());
+        self
+    }
+
+    pub fn with_args(mut self, args: HashMap<String, String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: HashMap<String, String>) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+}
+
+/// A network namespace bind mounted at `path`. One created by this type is
+/// deleted when dropped, one that was opened is left alone.
+#[derive(Debug)]
+pub struct NetNs {
+    path: PathBuf,
+    owned: bool,
+}
+
+impl NetNs {
+    /// Creates the namespace `name` under `/var/run/netns`, like `ip netns add`.
+    pub fn create(name: &str) -> Result<Self, String> {
+        Self::create_in(NETNS_RUN_DIR, name)
+    }
+
+    pub fn create_in(dir: &str, name: &str) -> Result<Self, String> {
+        if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+            return Err(format!("Invalid netns name {:?}", name));
+        }
+        let path = Path::new(dir).join(name);
+        debug!("Creating network namespace at {}", path.display());
+
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+        // The mount point; refusing to reuse one keeps us from shadowing a live namespace
+        File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+
+        let netns = NetNs { path, owned: true };
+        let target = path_cstring(&netns.path)?;
+        // unshare only moves the calling thread, which exits right after
+        thread::scope(|s| {
+            s.spawn(|| unsafe {
+                if libc::unshare(libc::CLONE_NEWNET) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let source = c"/proc/thread-self/ns/net";
+                if libc::mount(
+                    source.as_ptr(),
+                    target.as_ptr(),
+                    std::ptr::null(),
+                    libc::MS_BIND,
+                    std::ptr::null(),
+                ) != 0
+                {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            })
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("netns thread panicked")))
+        })
+        .map_err(|e| format!("Failed to create netns {}: {}", netns.path.display(), e))?;
+
+        debug!("Created network namespace at {}", netns.path.display());
+        Ok(netns)
+    }
+
+    /// Opens an existing namespace, e.g. `/var/run/netns/<name>`.
+    pub fn open(path: &str) -> Result<Self, String> {
+        validate_netns(path).map_err(|e| e.to_string())?;
+        Ok(NetNs {
+            path: PathBuf::from(path),
+            owned: false,
+        })
+    }
+
+    /// Opens the network namespace process `pid` is in.
+    pub fn from_pid(pid: u32) -> Result<Self, String> {
+        Self::open(&format!("/proc/{}/ns/net", pid))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn path_str(&self) -> String {
+        self.path.to_string_lossy().to_string()
+    }
+
+    /// Runs `f` inside the namespace, on a thread of its own so the caller's
+    /// namespace is never touched.
+    pub fn run<F, T>(&self, f: F) -> Result<T, String>
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        let ns = File::open(&self.path)
+            .map_err(|e| format!("Failed to open netns {}: {}", self.path.display(), e))?;
+        thread::scope(|s| {
+            s.spawn(|| {
+                if unsafe { libc::setns(ns.as_raw_fd(), libc::CLONE_NEWNET) } != 0 {
+                    return Err(format!(
+                        "Failed to enter netns {}: {}",
+                        self.path.display(),
+                        io::Error::last_os_error()
+                    ));
+                }
+                Ok(f())
+            })
+            .join()
+            .unwrap_or_else(|_| Err("netns thread panicked".to_string()))
+        })
+    }
+
+    /// Unmounts and removes the namespace. The kernel frees it once nothing
+    /// else holds it open.
+    pub fn delete(mut self) -> Result<(), String> {
+        self.owned = false;
+        Self::unmount(&self.path)
+    }
+
+    fn unmount(path: &Path) -> Result<(), String> {
+        debug!("Deleting network namespace at {}", path.display());
+        let target = path_cstring(path)?;
+        // Detached, so a process still using the namespace does not make this fail
+        if unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) } != 0 {
+            let e = io::Error::last_os_error();
+            // EINVAL: not a mount point, e.g. a half-created namespace
+            if !matches!(e.raw_os_error(), Some(libc::EINVAL) | Some(libc::ENOENT)) {
+                return Err(format!("Failed to unmount {}: {}", path.display(), e));
+            }
+        }
+        match fs::remove_file(path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+        }
+    }
+}
+
+impl Drop for NetNs {
+    fn drop(&mut self) {
+        if self.owned {
+            if let Err(e) = Self::unmount(&self.path) {
+                warn!("{}", e);
+            }
+        }
+    }
+}
+
+fn path_cstring(path: &Path) -> Result<CString, String> {
+    CString::new(path.as_os_str().as_encoded_bytes())
+        .map_err(|_| format!("Invalid netns path {}", path.display()))
+}
+
 #[derive(Clone, Default)]
 pub struct Namespace {
     id: String,
     path: String,
     capability_args: HashMap<String, String>,
     args: HashMap<String, String>,
+    netns: Option<Arc<NetNs>>,
 }
 
 impl Namespace {
@@ -128,9 +372,21 @@ impl Namespace {
             path,
             capability_args: HashMap::default(),
             args: HashMap::default(),
+            netns: None,
         }
     }
 
+    /// A namespace that keeps `netns` alive until the last clone is dropped.
+    pub fn with_netns(id: String, netns: NetNs) -> Self {
+        let mut ns = Self::new(id, netns.path_str());
+        ns.netns = Some(Arc::new(netns));
+        ns
+    }
+
+    pub fn get_netns(&self) -> Option<&NetNs> {
+        self.netns.as_deref()
+    }
+
     pub fn with_args(mut self, args: HashMap<String, String>) -> Self {
         debug!("Adding {} arguments to namespace", args.len());
         self.args = args;
@@ -143,6 +399,30 @@ impl Namespace {
         self
     }
 
+    /// Passes pod labels as CNI_ARGS, like go-cni's `WithLabels`. Labels
+    /// whose key is not a valid CNI_ARGS key, such as `app.kubernetes.io/name`,
+    /// are skipped. A value containing `;` or `=` is an error. `IgnoreUnknown=1`
+    /// is added so plugins accept the args they do not know.
+    pub fn with_labels(mut self, labels: &HashMap<String, String>) -> Result<Self, String> {
+        debug!("Adding {} labels to namespace", labels.len());
+        for (key, value) in labels {
+            if !is_valid_arg_key(key) {
+                debug!("Skipping label {}, not a valid CNI_ARGS key", key);
+                continue;
+            }
+            if value.contains([';', '=', '\0']) {
+                return Err(format!(
+                    "Label {} has value {:?}, which cannot be passed in CNI_ARGS",
+                    key, value
+                ));
+            }
+            self.args.insert(key.clone(), value.clone());
+        }
+        self.args
+            .insert(IGNORE_UNKNOWN.to_string(), "1".to_string());
+        Ok(self)
+    }
+
     pub fn add_arg(&mut self, key: &str, value: &str) {
         debug!("Adding argument {}={} to namespace", key, value);
         self.args.insert(key.to_string(), value.to_string());
@@ -154,25 +434,38 @@ impl Namespace {
             .insert(key.to_string(), value.to_string());
     }
 
-    pub fn config(&self, ifname: String) -> libcni::api::RuntimeConf {
+    /// The runtime config of this namespace. Args that cannot be joined into
+    /// CNI_ARGS, with a `;`, `=` or NUL in them or an empty key, are an error.
+    pub fn config(&self, ifname: String) -> Result<libcni::api::RuntimeConf, String> {
         debug!(
             "Creating runtime config for namespace with interface {}",
             ifname
         );
-        let args = self
+        for (key, value) in &self.args {
+            if key.is_empty() || key.contains([';', '=', '\0']) || value.contains([';', '=', '\0'])
+            {
+                return Err(format!(
+                    "Arg {:?}={:?} cannot be passed in CNI_ARGS",
+                    key, value
+                ));
+            }
+        }
+        // Sorted so CNI_ARGS is stable, with IgnoreUnknown first
+        let mut args: Vec<[String; 2]> = self
             .args
             .iter()
             .map(|(key, val)| [key.clone(), val.clone()])
             .collect();
+        args.sort_by(|a, b| (a[0] != IGNORE_UNKNOWN, &a[0]).cmp(&(b[0] != IGNORE_UNKNOWN, &b[0])));
 
-        RuntimeConf {
+        Ok(RuntimeConf {
             container_id: self.id.clone(),
             net_ns: self.path.clone(),
             if_name: ifname,
             args,
             capability_args: self.capability_args.clone(),
             cache_dir: String::default(),
-        }
+        })
     }
 
     pub fn get_id(&self) -> &str {
@@ -183,3 +476,7 @@ impl Namespace {
         &self.path
     }
 }
+
+fn is_valid_arg_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
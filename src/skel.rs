@@ -0,0 +1,253 @@
+use log::debug;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::libcni::{
+    error::{
+        PluginError, CODE_DECODING_FAILURE, CODE_INCOMPATIBLE_CNI_VERSION,
+        CODE_INVALID_ENVIRONMENT_VARIABLES, CODE_IO_FAILURE,
+    },
+    plugin::{parse_version, version_at_least, PluginInfo, PluginInfoT},
+    registry::NativePlugin,
+    result::{APIResult, ResultCNI},
+    types::NetConf,
+    validate::{validate_container_id, validate_ifname},
+};
+
+/// What a plugin is called with.
+#[derive(Clone, Debug, Default)]
+pub struct CmdArgs {
+    pub container_id: String,
+    pub netns: String,
+    pub ifname: String,
+    pub args: String,
+    pub path: String,
+    pub stdin_data: Vec<u8>,
+}
+
+impl CmdArgs {
+    /// Decodes the common fields of the network config on stdin.
+    pub fn net_conf(&self) -> ResultCNI<NetConf> {
+        serde_json::from_slice(&self.stdin_data).map_err(|e| {
+            Box::new(crate::libcni::CNIError::VarDecode(format!(
+                "failed to decode network config: {}",
+                e
+            )))
+        })
+    }
+
+    /// `CNI_ARGS` as key/value pairs.
+    pub fn parsed_args(&self) -> Vec<(String, String)> {
+        self.args
+            .split(';')
+            .filter_map(|kv| kv.split_once('='))
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    /// `CNI_PATH` split into its directories.
+    pub fn paths(&self) -> Vec<String> {
+        self.path
+            .split(':')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+            .collect()
+    }
+}
+
+/// Handlers of a plugin. GC and STATUS succeed unless implemented.
+pub trait Plugin {
+    fn add(&self, args: &CmdArgs) -> ResultCNI<Box<dyn APIResult>>;
+
+    fn del(&self, args: &CmdArgs) -> ResultCNI<()>;
+
+    fn check(&self, args: &CmdArgs) -> ResultCNI<()>;
+
+    fn gc(&self, _args: &CmdArgs) -> ResultCNI<()> {
+        Ok(())
+    }
+
+    fn status(&self, _args: &CmdArgs) -> ResultCNI<()> {
+        Ok(())
+    }
+}
+
+/// Serves a `Plugin` in-process, through the same checks and output as
+/// `plugin_main`, so that it can be put in a `PluginRegistry`.
+pub struct NativeSkel<P> {
+    plugin: P,
+    info: PluginInfoT,
+}
+
+impl<P: Plugin> NativeSkel<P> {
+    pub fn new(plugin: P, info: PluginInfoT) -> Self {
+        NativeSkel { plugin, info }
+    }
+}
+
+impl<P: Plugin + Send + Sync> NativePlugin for NativeSkel<P> {
+    fn exec(&self, stdin: &[u8], env: &HashMap<String, String>) -> ResultCNI<Vec<u8>> {
+        let mut out = Vec::new();
+        if let Err(e) = dispatch(&self.plugin, &self.info, env, stdin, &mut out) {
+            out = serde_json::to_vec(&e)
+                .map_err(|e| Box::new(crate::libcni::CNIError::VarDecode(e.to_string())))?;
+        }
+        Ok(out)
+    }
+}
+
+// Variables each command needs, as in the reference skel
+const REQUIRED_VARS: [(&str, &[&str]); 4] = [
+    ("CNI_CONTAINERID", &["ADD", "CHECK", "DEL"]),
+    ("CNI_NETNS", &["ADD", "CHECK"]),
+    ("CNI_IFNAME", &["ADD", "CHECK", "DEL"]),
+    ("CNI_PATH", &["ADD", "CHECK", "DEL", "GC", "STATUS"]),
+];
+
+/// Reads the environment and stdin of the process, runs the handler of
+/// `CNI_COMMAND` and exits. Errors are printed on stdout and exit with 1.
+/// Without `CNI_COMMAND`, `about` is printed on stderr.
+pub fn plugin_main(plugin: &dyn Plugin, info: &PluginInfoT, about: &str) -> ! {
+    let env: HashMap<String, String> = std::env::vars().collect();
+    if env.get("CNI_COMMAND").is_none_or(|c| c.is_empty()) {
+        eprintln!("{}", about);
+        eprintln!(
+            "CNI protocol versions supported: {}",
+            info.supported_versions.join(", ")
+        );
+        std::process::exit(0);
+    }
+
+    let mut stdout = io::stdout().lock();
+    let code = match dispatch(plugin, info, &env, io::stdin().lock(), &mut stdout) {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = serde_json::to_writer(&mut stdout, &e);
+            1
+        }
+    };
+    let _ = stdout.flush();
+    std::process::exit(code)
+}
+
+/// Runs the handler of `CNI_COMMAND` in `env` and prints its result, if any,
+/// on `stdout`. The error is returned for the caller to print.
+pub fn dispatch<R: Read, W: Write>(
+    plugin: &dyn Plugin,
+    info: &PluginInfoT,
+    env: &HashMap<String, String>,
+    mut stdin: R,
+    stdout: W,
+) -> Result<(), PluginError> {
+    let var = |name: &str| env.get(name).cloned().unwrap_or_default();
+    let command = var("CNI_COMMAND");
+    debug!("Plugin called with CNI_COMMAND={}", command);
+
+    if command == "VERSION" {
+        return version_info(info)
+            .encode(stdout)
+            .map_err(|e| e.to_plugin_error());
+    }
+    if !["ADD", "CHECK", "DEL", "GC", "STATUS"].contains(&command.as_str()) {
+        return Err(PluginError::new(
+            CODE_INVALID_ENVIRONMENT_VARIABLES,
+            &format!("unknown CNI_COMMAND: {}", command),
+        ));
+    }
+
+    let missing: Vec<&str> = REQUIRED_VARS
+        .iter()
+        .filter(|(name, commands)| commands.contains(&command.as_str()) && var(name).is_empty())
+        .map(|(name, _)| *name)
+        .collect();
+    if !missing.is_empty() {
+        return Err(PluginError::new(
+            CODE_INVALID_ENVIRONMENT_VARIABLES,
+            &format!("required env variables [{}] missing", missing.join(",")),
+        ));
+    }
+
+    let mut stdin_data = Vec::new();
+    stdin.read_to_end(&mut stdin_data).map_err(|e| {
+        PluginError::new(CODE_IO_FAILURE, &format!("error reading from stdin: {}", e))
+    })?;
+    let args = CmdArgs {
+        container_id: var("CNI_CONTAINERID"),
+        netns: var("CNI_NETNS"),
+        ifname: var("CNI_IFNAME"),
+        args: var("CNI_ARGS"),
+        path: var("CNI_PATH"),
+        stdin_data,
+    };
+
+    let conf = args.net_conf().map_err(|e| e.to_plugin_error())?;
+    let with_version = |mut e: PluginError| {
+        e.cni_version = conf.cni_version.clone();
+        e
+    };
+    check_version(info, &command, &conf.cni_version).map_err(with_version)?;
+    if ["ADD", "CHECK", "DEL"].contains(&command.as_str()) {
+        validate_container_id(&args.container_id)
+            .and_then(|_| validate_ifname(&args.ifname))
+            .map_err(|e| with_version(e.to_plugin_error()))?;
+    }
+
+    let handled = match command.as_str() {
+        "ADD" => plugin
+            .add(&args)
+            .and_then(|result| result.get_as_version(conf.cni_version.clone()))
+            .and_then(|result| print(stdout, result.get_json().dump().as_bytes())),
+        "CHECK" => plugin.check(&args),
+        "DEL" => plugin.del(&args),
+        "GC" => plugin.gc(&args),
+        _ => plugin.status(&args),
+    };
+    handled.map_err(|e| with_version(e.to_plugin_error()))
+}
+
+fn print<W: Write>(mut stdout: W, data: &[u8]) -> ResultCNI<()> {
+    stdout
+        .write_all(data)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| Box::new(e.into()))
+}
+
+// The reported cniVersion defaults to the newest supported one
+fn version_info(info: &PluginInfoT) -> PluginInfoT {
+    let mut info = info.clone();
+    if info.cni_version.is_empty() {
+        info.cni_version = info
+            .supported_versions
+            .iter()
+            .max_by_key(|v| parse_version(v))
+            .cloned()
+            .unwrap_or_default();
+    }
+    info
+}
+
+fn check_version(info: &PluginInfoT, command: &str, version: &str) -> Result<(), PluginError> {
+    if version.is_empty() {
+        return Err(PluginError::new(
+            CODE_DECODING_FAILURE,
+            "network config has no cniVersion",
+        ));
+    }
+    if !info.supported_versions.iter().any(|v| v == version) {
+        return Err(
+            PluginError::new(CODE_INCOMPATIBLE_CNI_VERSION, "incompatible CNI versions")
+                .with_details(&format!(
+                    "config is {:?}, plugin supports {:?}",
+                    version, info.supported_versions
+                )),
+        );
+    }
+    // GC and STATUS were introduced in spec 1.1.0
+    if (command == "GC" || command == "STATUS") && !version_at_least(version, "1.1.0") {
+        return Err(PluginError::new(
+            CODE_INCOMPATIBLE_CNI_VERSION,
+            &format!("config version {} does not allow {}", version, command),
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,409 @@
+use ipnetwork::IpNetwork;
+use log::{debug, trace};
+use std::ffi::CStr;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::libcni::result::{result100, APIResult};
+use crate::namespace::NetNs;
+
+/// An interface as found inside the namespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LinkState {
+    pub name: String,
+    pub mac: Option<String>,
+    pub addresses: Vec<IpNetwork>,
+}
+
+/// A route as found inside the namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteState {
+    pub dst: IpNetwork,
+    pub gw: Option<IpAddr>,
+    pub dev: String,
+}
+
+/// A difference between a plugin result and the namespace.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    MissingInterface {
+        name: String,
+    },
+    MacMismatch {
+        interface: String,
+        expected: String,
+        actual: Option<String>,
+    },
+    MissingAddress {
+        interface: Option<String>,
+        expected: IpNetwork,
+        actual: Vec<IpNetwork>,
+    },
+    MissingRoute {
+        dst: IpNetwork,
+        gw: Option<IpAddr>,
+        dev: Option<String>,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::MissingInterface { name } => write!(f, "interface {} is missing", name),
+            Mismatch::MacMismatch {
+                interface,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "interface {} has MAC {}, expected {}",
+                interface,
+                actual.as_deref().unwrap_or("none"),
+                expected
+            ),
+            Mismatch::MissingAddress {
+                interface,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "address {} is missing on {}, found {:?}",
+                expected,
+                interface.as_deref().unwrap_or("any interface"),
+                actual.iter().map(|a| a.to_string()).collect::<Vec<_>>()
+            ),
+            Mismatch::MissingRoute { dst, gw, dev } => {
+                write!(f, "route to {}", dst)?;
+                if let Some(gw) = gw {
+                    write!(f, " via {}", gw)?;
+                }
+                if let Some(dev) = dev {
+                    write!(f, " dev {}", dev)?;
+                }
+                write!(f, " is missing")
+            }
+        }
+    }
+}
+
+/// The state found in the namespace and how it differs from the result.
+#[derive(Clone, Debug, Default)]
+pub struct VerifyReport {
+    pub links: Vec<LinkState>,
+    pub routes: Vec<RouteState>,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl fmt::Display for VerifyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mismatches: Vec<String> = self.mismatches.iter().map(|m| m.to_string()).collect();
+        write!(f, "{}", mismatches.join("; "))
+    }
+}
+
+/// Enters `netns` and checks that the interfaces, addresses and routes of
+/// `result` are there. Interfaces without a `sandbox` live on the host and
+/// are not checked.
+pub fn verify_result(netns: &NetNs, result: &dyn APIResult) -> Result<VerifyReport, String> {
+    let result: result100::Result = serde_json::from_str(&result.get_json().dump())
+        .map_err(|e| format!("Failed to decode result: {}", e))?;
+
+    debug!("Verifying result in netns {}", netns.path().display());
+    let (links, routes) = netns.run(read_state)??;
+    trace!("Found links {:?} and routes {:?}", links, routes);
+
+    let mismatches = diff(&result, &links, &routes);
+    Ok(VerifyReport {
+        links,
+        routes,
+        mismatches,
+    })
+}
+
+/// Compares `result` with the given namespace state.
+pub fn diff(
+    result: &result100::Result,
+    links: &[LinkState],
+    routes: &[RouteState],
+) -> Vec<Mismatch> {
+    let mut mismatches = Vec::new();
+    let interfaces = result.interfaces.as_deref().unwrap_or_default();
+    let in_sandbox =
+        |iface: &result100::Interface| iface.sandbox.as_deref().is_some_and(|s| !s.is_empty());
+
+    for iface in interfaces.iter().filter(|i| in_sandbox(i)) {
+        let name = match &iface.name {
+            Some(name) => name,
+            None => continue,
+        };
+        let link = match links.iter().find(|l| &l.name == name) {
+            Some(link) => link,
+            None => {
+                mismatches.push(Mismatch::MissingInterface { name: name.clone() });
+                continue;
+            }
+        };
+        if let Some(expected) = iface.mac.as_deref().filter(|m| !m.is_empty()) {
+            let matches = link
+                .mac
+                .as_deref()
+                .is_some_and(|actual| actual.eq_ignore_ascii_case(expected));
+            if !matches {
+                mismatches.push(Mismatch::MacMismatch {
+                    interface: name.clone(),
+                    expected: expected.to_string(),
+                    actual: link.mac.clone(),
+                });
+            }
+        }
+    }
+
+    for ip in result.ips.as_deref().unwrap_or_default() {
+        let expected = match ip.address {
+            Some(address) => address,
+            None => continue,
+        };
+        let iface = ip.interface.and_then(|i| interfaces.get(i));
+        // Addresses of host side interfaces are not ours to check
+        if iface.is_some_and(|i| !in_sandbox(i)) {
+            continue;
+        }
+        let name = iface.and_then(|i| i.name.clone());
+        let candidates: Vec<&LinkState> = links
+            .iter()
+            .filter(|l| name.as_ref().is_none_or(|n| &l.name == n))
+            .collect();
+        if name.is_some() && candidates.is_empty() {
+            // Already reported as a missing interface
+            continue;
+        }
+        let found = candidates.iter().any(|l| {
+            l.addresses
+                .iter()
+                .any(|a| a.ip() == expected.ip() && a.prefix() == expected.prefix())
+        });
+        if !found {
+            mismatches.push(Mismatch::MissingAddress {
+                interface: name,
+                expected,
+                actual: candidates
+                    .iter()
+                    .flat_map(|l| l.addresses.iter().copied())
+                    .collect(),
+            });
+        }
+    }
+
+    // Routes have no interface of their own. One via a gateway goes out of
+    // the interface whose address covers it, any other out of one of the
+    // interfaces the result put in the sandbox.
+    let sandbox_names: Vec<&String> = interfaces
+        .iter()
+        .filter(|i| in_sandbox(i))
+        .filter_map(|i| i.name.as_ref())
+        .collect();
+    let gateway_dev = |gw: IpAddr| {
+        result
+            .ips
+            .as_deref()
+            .unwrap_or_default()
+            .iter()
+            .filter(|ip| ip.address.is_some_and(|a| a.contains(gw)))
+            .filter_map(|ip| ip.interface.and_then(|i| interfaces.get(i)))
+            .find(|i| in_sandbox(i))
+            .and_then(|i| i.name.clone())
+    };
+    for route in result.routes.as_deref().unwrap_or_default() {
+        let dst = match route.dst {
+            Some(dst) => dst,
+            None => continue,
+        };
+        let dev = route.gw.and_then(gateway_dev);
+        let found = routes.iter().any(|r| {
+            r.dst.network() == dst.network()
+                && r.dst.prefix() == dst.prefix()
+                && route.gw.is_none_or(|gw| r.gw == Some(gw))
+                && match &dev {
+                    Some(dev) => &r.dev == dev,
+                    None => sandbox_names.is_empty() || sandbox_names.contains(&&r.dev),
+                }
+        });
+        if !found {
+            mismatches.push(Mismatch::MissingRoute {
+                dst,
+                gw: route.gw,
+                dev,
+            });
+        }
+    }
+
+    mismatches
+}
+
+/// Reads the links and routes of the calling thread's network namespace.
+pub fn read_state() -> Result<(Vec<LinkState>, Vec<RouteState>), String> {
+    let links = read_links()?;
+    let mut routes = read_ipv4_routes()?;
+    routes.extend(read_ipv6_routes()?);
+    Ok((links, routes))
+}
+
+fn read_links() -> Result<Vec<LinkState>, String> {
+    let mut links: Vec<LinkState> = Vec::new();
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return Err(format!(
+            "Failed to list interfaces: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+
+    let mut cur = addrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .to_string();
+        let idx = match links.iter().position(|l| l.name == name) {
+            Some(idx) => idx,
+            None => {
+                links.push(LinkState {
+                    name,
+                    ..Default::default()
+                });
+                links.len() - 1
+            }
+        };
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+
+        match unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_PACKET => {
+                let ll = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_ll) };
+                let len = (ll.sll_halen as usize).min(ll.sll_addr.len());
+                if len > 0 {
+                    let mac: Vec<String> = ll.sll_addr[..len]
+                        .iter()
+                        .map(|b| format!("{:02x}", b))
+                        .collect();
+                    links[idx].mac = Some(mac.join(":"));
+                }
+            }
+            libc::AF_INET | libc::AF_INET6 => {
+                let ip = sockaddr_ip(ifa.ifa_addr);
+                let prefix = if ifa.ifa_netmask.is_null() {
+                    None
+                } else {
+                    sockaddr_ip(ifa.ifa_netmask).map(|mask| match mask {
+                        IpAddr::V4(m) => u32::from(m).count_ones() as u8,
+                        IpAddr::V6(m) => u128::from(m).count_ones() as u8,
+                    })
+                };
+                if let (Some(ip), Some(prefix)) = (ip, prefix) {
+                    if let Ok(net) = IpNetwork::new(ip, prefix) {
+                        links[idx].addresses.push(net);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    Ok(links)
+}
+
+fn sockaddr_ip(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    match unsafe { (*addr).sa_family } as libc::c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(addr as *const libc::sockaddr_in) };
+            Some(IpAddr::V4(Ipv4Addr::from(
+                sin.sin_addr.s_addr.to_ne_bytes(),
+            )))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(addr as *const libc::sockaddr_in6) };
+            Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+        }
+        _ => None,
+    }
+}
+
+// Columns: Iface Destination Gateway Flags RefCnt Use Metric Mask ..., the
+// addresses being printed as host order integers of network order bytes.
+fn read_ipv4_routes() -> Result<Vec<RouteState>, String> {
+    let table = read_route_table("/proc/thread-self/net/route")?;
+    let hex_ip = |s: &str| {
+        u32::from_str_radix(s, 16)
+            .ok()
+            .map(|v| Ipv4Addr::from(v.to_ne_bytes()))
+    };
+
+    let mut routes = Vec::new();
+    for line in table.lines().skip(1) {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 8 {
+            continue;
+        }
+        let (Some(dst), Some(gw), Some(mask)) = (hex_ip(cols[1]), hex_ip(cols[2]), hex_ip(cols[7]))
+        else {
+            continue;
+        };
+        let prefix = u32::from(mask).count_ones() as u8;
+        if let Ok(dst) = IpNetwork::new(IpAddr::V4(dst), prefix) {
+            routes.push(RouteState {
+                dst,
+                gw: (!gw.is_unspecified()).then_some(IpAddr::V4(gw)),
+                dev: cols[0].to_string(),
+            });
+        }
+    }
+    Ok(routes)
+}
+
+// Columns: dest dest_len src src_len next_hop metric refcnt use flags dev
+fn read_ipv6_routes() -> Result<Vec<RouteState>, String> {
+    let table = read_route_table("/proc/thread-self/net/ipv6_route")?;
+    let hex_ip = |s: &str| u128::from_str_radix(s, 16).ok().map(Ipv6Addr::from);
+
+    let mut routes = Vec::new();
+    for line in table.lines() {
+        let cols: Vec<&str> = line.split_whitespace().collect();
+        if cols.len() < 10 {
+            continue;
+        }
+        let (Some(dst), Ok(prefix), Some(gw)) = (
+            hex_ip(cols[0]),
+            u8::from_str_radix(cols[1], 16),
+            hex_ip(cols[4]),
+        ) else {
+            continue;
+        };
+        if let Ok(dst) = IpNetwork::new(IpAddr::V6(dst), prefix) {
+            routes.push(RouteState {
+                dst,
+                gw: (!gw.is_unspecified()).then_some(IpAddr::V6(gw)),
+                dev: cols[9].to_string(),
+            });
+        }
+    }
+    Ok(routes)
+}
+
+fn read_route_table(path: &str) -> Result<String, String> {
+    match fs::read_to_string(path) {
+        Ok(table) => Ok(table),
+        // No IPv6 in this kernel
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(format!("Failed to read {}: {}", path, e)),
+    }
+}
@@ -1,9 +1,43 @@
 use log::{debug, error, info, warn, LevelFilter};
 use once_cell::sync::OnceCell;
-use rust_cni::{cni::Libcni, namespace::Namespace};
+use rust_cni::{
+    cni::Libcni,
+    ipam::{
+        self,
+        host_local::{HostLocal, HostLocalConf},
+        range::Range,
+        static_ipam::{StaticConf, StaticIpam},
+    },
+    libcni::{
+        api::{CNIConfig, NetworkConfigList, RuntimeConf, CNI},
+        conf::ConfigFile,
+        error::CNIError,
+        exec::{Exec, RawExec},
+        limits::ExecLimits,
+        lint::{LintKind, Linter},
+        models::{
+            BridgeConf, DhcpConf, HostDeviceConf, IpamConf, PluginConf, PortmapConf, PtpConf,
+            TuningConf,
+        },
+        plugin::PluginInfoT,
+        registry::{LookupOrder, NativePlugin, PluginRegistry},
+        result::{result100, APIResult, ResultCNI},
+        retry::RetryPolicy,
+        security::PluginSecurity,
+        types::{IfnamePolicy, NetConf, NetworkConfig, PluginDirs, SetupMode},
+        validate::validate_ifname,
+    },
+    namespace::{Namespace, NetNs, NetworkSelection},
+    skel,
+    verify::{diff, Mismatch},
+};
+use std::collections::HashMap;
 use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::Path;
 use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
 
 fn init_logger() {
     static LOGGER: OnceCell<()> = OnceCell::new();
@@ -100,7 +134,7 @@ fn create_netns(name: &str) -> Result<String, String> {
 
     // create netns
     let output = Command::new("ip")
-        .args(&["netns", "add", name])
+        .args(["netns", "add", name])
         .output()
         .map_err(|e| format!("Failed to create netns: {}", e))?;
 
@@ -119,7 +153,7 @@ fn create_netns(name: &str) -> Result<String, String> {
 // test helper function
 fn delete_netns(name: &str) -> Result<(), String> {
     let output = Command::new("ip")
-        .args(&["netns", "delete", name])
+        .args(["netns", "delete", name])
         .output()
         .map_err(|e| format!("Failed to delete netns: {}", e))?;
 
@@ -134,6 +168,28 @@ fn delete_netns(name: &str) -> Result<(), String> {
     Ok(())
 }
 
+// test helper function
+fn create_fake_plugin(dir: &str, name: &str, script: &str) -> std::io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let path = format!("{}/{}", dir, name);
+    // consume the config like a real plugin before running the script body
+    fs::write(&path, format!("#!/bin/sh\ncat > /dev/null\n{}\n", script))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    Ok(path)
+}
+
+// test helper function
+fn fake_network_config(plugin_type: &str) -> NetworkConfig {
+    let bytes = format!(r#"{{"type":"{}"}}"#, plugin_type);
+    NetworkConfig {
+        network: NetConf {
+            _type: plugin_type.to_string(),
+            ..Default::default()
+        },
+        bytes: bytes.into_bytes(),
+    }
+}
+
 // test: initialize CNI and load config
 #[test]
 fn test_cni_initialization() {
@@ -524,3 +580,2371 @@ fn test_custom_network_config_with_plugins() {
 
     info!("Custom network config test with multiple plugins completed successfully");
 }
+
+// test: plugin VERSION probes are cached until the binary changes
+#[test]
+fn test_plugin_version_cache() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let probe_log = format!("{}/probes", test_dir);
+    let script = format!(
+        r#"echo probe >> {}
+echo '{{"cniVersion":"1.0.0","supportedVersions":["0.4.0","1.0.0"]}}'"#,
+        probe_log
+    );
+    let plugin_path = create_fake_plugin(&test_dir, "fake", &script).unwrap();
+
+    let cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+
+    for _ in 0..3 {
+        let versions = cni.validate_network(fake_network_config("fake")).unwrap();
+        assert_eq!(versions, vec!["0.4.0".to_string(), "1.0.0".to_string()]);
+    }
+    let stats = cni.plugin_cache_stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (2, 1, 1));
+    assert_eq!(fs::read_to_string(&probe_log).unwrap().lines().count(), 1);
+
+    // Replacing the binary gives it a new inode, which must invalidate the entry
+    fs::remove_file(&plugin_path).unwrap();
+    create_fake_plugin(&test_dir, "fake", &script).unwrap();
+    cni.validate_network(fake_network_config("fake")).unwrap();
+    let stats = cni.plugin_cache_stats();
+    assert_eq!((stats.hits, stats.misses), (2, 2));
+    assert_eq!(fs::read_to_string(&probe_log).unwrap().lines().count(), 2);
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+// test: per-network plugin dirs are searched and passed on as CNI_PATH
+#[test]
+fn test_network_plugin_dirs() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let default_dir = format!("{}/bin", test_dir);
+    let vendor_dir = format!("{}/vendor", test_dir);
+    let seen_path = format!("{}/cni_path", test_dir);
+    fs::create_dir_all(&default_dir).unwrap();
+    create_fake_plugin(
+        &vendor_dir,
+        "vendor-plugin",
+        &format!(
+            r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+echo "$CNI_PATH" > {}
+echo '{{"cniVersion":"1.0.0"}}'"#,
+            seen_path
+        ),
+    )
+    .unwrap();
+
+    let conflist = format!(
+        r#"{{
+        "cniVersion": "1.0.0",
+        "name": "vendor-net",
+        "pluginDirs": ["{}"],
+        "plugins": [{{ "type": "vendor-plugin" }}]
+    }}"#,
+        vendor_dir
+    );
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    assert_eq!(
+        net.plugin_dirs,
+        PluginDirs::Extend(vec![vendor_dir.clone()])
+    );
+    for bad in [
+        r#"{"cniVersion":"1.0.0","name":"n","pluginDirsOverride":true,"plugins":[{"type":"p"}]}"#,
+        r#"{"cniVersion":"1.0.0","name":"n","pluginDirs":["/x"],"pluginDirsOverride":"yes","plugins":[{"type":"p"}]}"#,
+    ] {
+        assert!(
+            ConfigFile::config_from_bytes(bad.as_bytes()).is_err(),
+            "{}",
+            bad
+        );
+    }
+
+    let cni = CNIConfig {
+        path: vec![default_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let rt = RuntimeConf {
+        container_id: "plugin-dirs".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+    cni.add_network_list(net.clone(), rt.clone()).unwrap();
+    assert_eq!(
+        fs::read_to_string(&seen_path).unwrap().trim(),
+        format!("{}:{}", vendor_dir, default_dir)
+    );
+
+    // Without the extension the plugin is not found
+    let mut plain = net;
+    plain.plugin_dirs = PluginDirs::Inherit;
+    assert!(cni.add_network_list(plain, rt).is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+// test: hardened plugin lookup and digest pinning
+#[test]
+fn test_hardened_plugin_lookup() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let plugin_path = create_fake_plugin(&test_dir, "pinned", "exit 0").unwrap();
+    let paths = vec![test_dir.clone()];
+
+    let exec = RawExec::hardened(PluginSecurity::new());
+    assert!(exec
+        .find_in_path("pinned".to_string(), paths.clone())
+        .is_ok());
+    assert!(exec
+        .find_in_path("../pinned".to_string(), paths.clone())
+        .is_err());
+
+    fs::set_permissions(&plugin_path, fs::Permissions::from_mode(0o777)).unwrap();
+    assert!(exec
+        .find_in_path("pinned".to_string(), paths.clone())
+        .is_err());
+    fs::set_permissions(&plugin_path, fs::Permissions::from_mode(0o775)).unwrap();
+    assert!(exec
+        .find_in_path("pinned".to_string(), paths.clone())
+        .is_err());
+    fs::set_permissions(&plugin_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // symlinks are refused even to trusted binaries
+    std::os::unix::fs::symlink("/bin/sh", format!("{}/shell", test_dir)).unwrap();
+    assert!(exec
+        .find_in_path("shell".to_string(), paths.clone())
+        .is_err());
+
+    // so is a plugin dir others can write to
+    fs::set_permissions(&test_dir, fs::Permissions::from_mode(0o775)).unwrap();
+    assert!(exec
+        .find_in_path("pinned".to_string(), paths.clone())
+        .is_err());
+    fs::set_permissions(&test_dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+    // allowlist in sha256sum format
+    let output = Command::new("sha256sum")
+        .arg(&plugin_path)
+        .output()
+        .unwrap();
+    let allowlist = format!("{}/allowlist", test_dir);
+    fs::write(&allowlist, &output.stdout).unwrap();
+    let exec = RawExec::hardened(
+        PluginSecurity::new()
+            .with_allowlist_file(&allowlist)
+            .unwrap(),
+    );
+    assert_eq!(
+        exec.find_in_path("pinned".to_string(), paths.clone())
+            .unwrap(),
+        plugin_path
+    );
+    // the verified binary is run from its descriptor
+    let exec = exec.with_limits(ExecLimits::default().with_close_fds());
+    assert!(exec
+        .exec_plugins(plugin_path.clone(), b"{}", Vec::new())
+        .is_ok());
+
+    // a modified binary no longer matches its pinned digest
+    fs::write(&plugin_path, "#!/bin/sh\nexit 1\n").unwrap();
+    assert!(exec
+        .find_in_path("pinned".to_string(), paths.clone())
+        .is_err());
+
+    // binaries missing from the allowlist are refused
+    create_fake_plugin(&test_dir, "unpinned", "exit 0").unwrap();
+    assert!(exec.find_in_path("unpinned".to_string(), paths).is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+// test: plugin processes run under the configured limits
+#[test]
+fn test_plugin_exec_limits() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let chatty = create_fake_plugin(&test_dir, "chatty", "head -c 1000000 /dev/zero").unwrap();
+    let report = create_fake_plugin(
+        &test_dir,
+        "report",
+        r#"echo "nofile=$(ulimit -n) leader=$(( $(cut -d' ' -f5 /proc/$$/stat) == $$ )) fds=$(ls /proc/$$/fd | wc -l)""#,
+    )
+    .unwrap();
+
+    let exec = RawExec::default().with_limits(ExecLimits::default().with_max_output(1024));
+    assert!(exec.exec_plugins(chatty, b"{}", Vec::new()).is_err());
+
+    // a failing plugin is an error even without an error object
+    let failing = create_fake_plugin(&test_dir, "failing", "echo '{}'; exit 3").unwrap();
+    assert!(exec.exec_plugins(failing, b"{}", Vec::new()).is_err());
+
+    // leak a descriptor without close-on-exec into children
+    let leaked = unsafe { libc::dup(2) };
+    let unlimited = RawExec::default()
+        .exec_plugins(report.clone(), b"{}", Vec::new())
+        .unwrap();
+    let limited = RawExec::default()
+        .with_limits(
+            ExecLimits::default()
+                .with_open_files(64)
+                .with_new_process_group()
+                .with_close_fds(),
+        )
+        .exec_plugins(report, b"{}", Vec::new())
+        .unwrap();
+    unsafe { libc::close(leaked) };
+
+    let unlimited = String::from_utf8(unlimited).unwrap();
+    let limited = String::from_utf8(limited).unwrap();
+    assert!(limited.starts_with("nofile=64 leader=1"), "{}", limited);
+    let fds = |s: &str| -> usize { s.trim().rsplit('=').next().unwrap().parse().unwrap() };
+    assert!(
+        fds(&limited) < fds(&unlimited),
+        "{} / {}",
+        limited,
+        unlimited
+    );
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+// test: plugins failing with TryAgainLater are retried
+#[test]
+fn test_try_again_later_retry() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let attempts = format!("{}/attempts", test_dir);
+    create_fake_plugin(
+        &test_dir,
+        "busy",
+        &format!(
+            r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+echo x >> {0}
+if [ $(wc -l < {0}) -lt 3 ]; then
+  echo '{{"code":11,"msg":"busy"}}'
+  exit 1
+fi
+echo '{{"cniVersion":"1.0.0"}}'"#,
+            attempts
+        ),
+    )
+    .unwrap();
+
+    let conflist = r#"{"cniVersion":"1.0.0","name":"busy-net","plugins":[{"type":"busy"}]}"#;
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let rt = RuntimeConf {
+        container_id: "retry".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+    let backoff = Duration::from_millis(1);
+
+    let impatient = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        retry: RetryPolicy::new(2).with_backoff(backoff, backoff),
+        ..Default::default()
+    };
+    let err = impatient
+        .add_network_list(net.clone(), rt.clone())
+        .err()
+        .unwrap();
+    assert!(err.is_try_again_later());
+    assert!(err.to_string().contains("2 attempts"), "{}", err);
+
+    fs::remove_file(&attempts).unwrap();
+    let patient = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        retry: RetryPolicy::new(5).with_backoff(backoff, backoff),
+        ..Default::default()
+    };
+    patient.add_network_list(net.clone(), rt.clone()).unwrap();
+    assert_eq!(fs::read_to_string(&attempts).unwrap().lines().count(), 3);
+
+    // Out of range values set on the fields directly are clamped
+    fs::remove_file(&attempts).unwrap();
+    let unclamped = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        retry: RetryPolicy {
+            jitter: 5.0,
+            multiplier: -2.0,
+            ..RetryPolicy::new(5).with_backoff(backoff, backoff)
+        },
+        ..Default::default()
+    };
+    unclamped.add_network_list(net, rt).unwrap();
+    assert_eq!(fs::read_to_string(&attempts).unwrap().lines().count(), 3);
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+// test: reloading with other plugin dirs keeps the cache dir
+#[test]
+fn test_load_keeps_cache_dir() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    let cache_dir = format!("{}/cache", test_dir);
+    create_fake_plugin(
+        &bin_dir,
+        "cached",
+        r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}'
+  exit 0
+fi
+echo '{"cniVersion":"1.0.0"}'"#,
+    )
+    .unwrap();
+    fs::create_dir_all(&conf_dir).unwrap();
+    fs::write(
+        format!("{}/10-cached.conflist", conf_dir),
+        r#"{"cniVersion":"1.0.0","name":"cached-net","plugins":[{"type":"cached"}]}"#,
+    )
+    .unwrap();
+
+    let mut cni = Libcni::new(None, None, Some(cache_dir.clone()));
+    cni.load(Some(conf_dir), Some(vec![bin_dir])).unwrap();
+    cni.setup("cached".to_string(), "/proc/self/ns/net".to_string())
+        .unwrap();
+    assert!(fs::read_dir(format!("{}/cached-net", cache_dir))
+        .unwrap()
+        .next()
+        .is_some());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_parallel_network_setup() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    let lo_marker = format!("{}/lo-up", test_dir);
+    let version = r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{"cniVersion":"1.0.0","supportedVersions":["0.3.1","1.0.0"]}'
+  exit 0
+fi
+[ "$CNI_COMMAND" = "ADD" ] || exit 0"#;
+    create_fake_plugin(
+        &bin_dir,
+        "loopback",
+        &format!("{}\nsleep 0.2\ntouch {}\necho '{{}}'", version, lo_marker),
+    )
+    .unwrap();
+    create_fake_plugin(
+        &bin_dir,
+        "slow",
+        &format!(
+            r#"{}
+if [ ! -e {} ]; then
+  echo '{{"code":999,"msg":"lo is not up"}}'
+  exit 1
+fi
+sleep 1
+echo '{{}}'"#,
+            version, lo_marker
+        ),
+    )
+    .unwrap();
+    create_fake_plugin(
+        &bin_dir,
+        "broken",
+        &format!(
+            "{}\necho '{{\"code\":7,\"msg\":\"broken plugin\"}}'\nexit 1",
+            version
+        ),
+    )
+    .unwrap();
+
+    fs::create_dir_all(&conf_dir).unwrap();
+    for (file, name, plugin) in [
+        ("10-a.conflist", "net-a", "slow"),
+        ("20-b.conflist", "net-b", "broken"),
+        ("30-c.conflist", "net-c", "slow"),
+        ("40-d.conflist", "net-d", "slow"),
+    ] {
+        let conflist = format!(
+            r#"{{"cniVersion":"1.0.0","name":"{}","plugins":[{{"type":"{}"}}]}}"#,
+            name, plugin
+        );
+        fs::write(format!("{}/{}", conf_dir, file), conflist).unwrap();
+    }
+
+    let setup = |mode: SetupMode| {
+        fs::remove_file(&lo_marker).unwrap_or_default();
+        let mut cni = Libcni::new(
+            Some(vec![bin_dir.clone()]),
+            Some(conf_dir.clone()),
+            Some(format!("{}/cache", test_dir)),
+        );
+        cni.set_max_conf_num(4);
+        cni.set_setup_mode(mode);
+        cni.load_default_conf();
+        // Added last, but still attached before the other networks
+        cni.add_lo_network().unwrap();
+        assert_eq!(cni.get_networks().len(), 5);
+
+        let start = std::time::Instant::now();
+        let err = cni
+            .setup("parallel".to_string(), "/proc/self/ns/net".to_string())
+            .err()
+            .unwrap();
+        (err, start.elapsed())
+    };
+
+    let (serial_err, _) = setup(SetupMode::Serial);
+    assert!(serial_err.contains("lo is not up"), "{}", serial_err);
+
+    let (err, elapsed) = setup(SetupMode::Parallel);
+    assert_eq!(
+        err, "Failed to attach network net-b : plugin error 7: broken plugin",
+        "{}",
+        err
+    );
+    assert!(elapsed < Duration::from_millis(2500), "took {:?}", elapsed);
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_selected_networks() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    let calls = format!("{}/calls", test_dir);
+    create_fake_plugin(
+        &bin_dir,
+        "recorder",
+        &format!(
+            r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+echo "$CNI_COMMAND $CNI_IFNAME $CNI_ARGS" >> {}
+echo '{{"cniVersion":"1.0.0"}}'"#,
+            calls
+        ),
+    )
+    .unwrap();
+
+    fs::create_dir_all(&conf_dir).unwrap();
+    for name in ["net-a", "net-b", "net-c"] {
+        let conflist = format!(
+            r#"{{"cniVersion":"1.0.0","name":"{}","plugins":[{{"type":"recorder"}}]}}"#,
+            name
+        );
+        fs::write(format!("{}/{}.conflist", conf_dir, name), conflist).unwrap();
+    }
+
+    let mut cni = Libcni::new(
+        Some(vec![bin_dir.clone()]),
+        Some(conf_dir.clone()),
+        Some(format!("{}/cache", test_dir)),
+    );
+    cni.set_max_conf_num(3);
+    cni.load_default_conf();
+    let default_ifname = cni
+        .get_networks()
+        .iter()
+        .find(|net| net.config.name == "net-a")
+        .unwrap()
+        .ifname
+        .clone();
+
+    let id = "selected".to_string();
+    let path = "/proc/self/ns/net".to_string();
+
+    // Unknown networks are rejected before any plugin runs
+    let err = cni
+        .setup_networks(
+            id.clone(),
+            path.clone(),
+            vec![
+                NetworkSelection::new("net-a"),
+                NetworkSelection::new("nope"),
+            ],
+        )
+        .err()
+        .unwrap();
+    assert!(err.contains("nope"), "{}", err);
+    assert!(!Path::new(&calls).exists());
+
+    let args = [("FOO".to_string(), "bar".to_string())].into();
+    cni.setup_networks(
+        id.clone(),
+        path.clone(),
+        vec![
+            NetworkSelection::new("net-a"),
+            NetworkSelection::new("net-c")
+                .with_ifname("net1")
+                .with_args(args),
+        ],
+    )
+    .unwrap();
+    cni.check_networks(id.clone(), path.clone()).unwrap();
+    cni.remove_networks(id.clone(), path.clone()).unwrap();
+
+    let calls = fs::read_to_string(&calls).unwrap();
+    assert_eq!(
+        calls.lines().collect::<Vec<_>>(),
+        vec![
+            format!("ADD {} ", default_ifname),
+            "ADD net1 FOO=bar".to_string(),
+            format!("CHECK {} ", default_ifname),
+            "CHECK net1 FOO=bar".to_string(),
+            "DEL net1 FOO=bar".to_string(),
+            format!("DEL {} ", default_ifname),
+        ]
+    );
+
+    // Nothing is recorded any more
+    assert!(cni.check_networks(id.clone(), path.clone()).is_err());
+    cni.remove_networks(id, path).unwrap();
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_setup_with_namespace() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    let stdin_log = format!("{}/stdin", test_dir);
+    let args_log = format!("{}/args", test_dir);
+    fs::create_dir_all(&bin_dir).unwrap();
+    let plugin = format!("{}/portmap", bin_dir);
+    // Keeps the config it was given, so it cannot use create_fake_plugin
+    fs::write(
+        &plugin,
+        format!(
+            r#"#!/bin/sh
+if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+cat > {}
+echo "$CNI_COMMAND $CNI_ARGS" >> {}
+echo '{{"cniVersion":"1.0.0"}}'
+"#,
+            stdin_log, args_log
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755)).unwrap();
+
+    fs::create_dir_all(&conf_dir).unwrap();
+    fs::write(
+        format!("{}/10-pod.conflist", conf_dir),
+        r#"{"cniVersion":"1.0.0","name":"pod-net","plugins":[
+            {"type":"portmap","capabilities":{"portMappings":true}}]}"#,
+    )
+    .unwrap();
+
+    let mut cni = Libcni::new(
+        Some(vec![bin_dir.clone()]),
+        Some(conf_dir.clone()),
+        Some(format!("{}/cache", test_dir)),
+    );
+    cni.load_default_conf();
+
+    let mut ns = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string());
+    ns.add_arg("K8S_POD_NAME", "web");
+    ns.add_capability(
+        "portMappings",
+        r#"[{"hostPort":8080,"containerPort":80,"protocol":"tcp"}]"#,
+    );
+    // Not declared by the plugin, so not passed on
+    ns.add_capability("bandwidth", r#"{"ingressRate":1}"#);
+
+    cni.setup_namespace(&ns).unwrap();
+    let conf: serde_json::Value = serde_json::from_slice(&fs::read(&stdin_log).unwrap()).unwrap();
+    assert_eq!(
+        conf["runtimeConfig"],
+        serde_json::json!({
+            "portMappings": [{"hostPort":8080,"containerPort":80,"protocol":"tcp"}]
+        })
+    );
+
+    cni.check_namespace(&ns).unwrap();
+    cni.remove_namespace(&ns).unwrap();
+    assert_eq!(
+        fs::read_to_string(&args_log).unwrap(),
+        "ADD K8S_POD_NAME=web\nCHECK K8S_POD_NAME=web\nDEL K8S_POD_NAME=web\n"
+    );
+
+    // The shorthand passes no args
+    cni.setup("pod".to_string(), "/proc/self/ns/net".to_string())
+        .unwrap();
+    let conf: serde_json::Value = serde_json::from_slice(&fs::read(&stdin_log).unwrap()).unwrap();
+    assert!(conf.get("runtimeConfig").is_none());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_labels_to_cni_args() {
+    init_logger();
+
+    let labels: HashMap<String, String> = [
+        ("K8S_POD_NAMESPACE", "default"),
+        ("K8S_POD_NAME", "web-0"),
+        ("K8S_POD_INFRA_CONTAINER_ID", "0123abcd"),
+        ("K8S_POD_UID", "a4f1-77"),
+        ("app.kubernetes.io/name", "web"),
+        ("", "empty"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect();
+
+    let ns = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string())
+        .with_labels(&labels)
+        .unwrap();
+    assert_eq!(
+        ns.config("eth0".to_string()).unwrap().cni_args(),
+        "IgnoreUnknown=1;K8S_POD_INFRA_CONTAINER_ID=0123abcd;K8S_POD_NAME=web-0;\
+         K8S_POD_NAMESPACE=default;K8S_POD_UID=a4f1-77"
+    );
+
+    // Explicit args are merged with the labels and sorted the same way
+    let mut ns = ns;
+    ns.add_arg("A_FIRST", "x");
+    assert!(ns
+        .config("eth0".to_string())
+        .unwrap()
+        .cni_args()
+        .starts_with("IgnoreUnknown=1;A_FIRST=x;K8S_POD_INFRA_CONTAINER_ID="));
+
+    // No labels still enables IgnoreUnknown
+    let ns = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string())
+        .with_labels(&HashMap::new())
+        .unwrap();
+    assert_eq!(
+        ns.config("eth0".to_string()).unwrap().cni_args(),
+        "IgnoreUnknown=1"
+    );
+
+    for bad in ["a;b", "a=b"] {
+        let labels = [("K8S_POD_NAME".to_string(), bad.to_string())].into();
+        let err = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string())
+            .with_labels(&labels)
+            .err()
+            .unwrap();
+        assert!(err.contains("K8S_POD_NAME"), "{}", err);
+    }
+
+    // Args set without with_labels are checked when the config is built
+    for (key, value) in [("K8S_POD_NAME", "a;b"), ("A=B", "x"), ("", "x")] {
+        let mut ns = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string());
+        ns.add_arg(key, value);
+        assert!(ns.config("eth0".to_string()).is_err(), "{}={}", key, value);
+        let ns = Namespace::new("pod".to_string(), "/proc/self/ns/net".to_string())
+            .with_args([(key.to_string(), value.to_string())].into());
+        assert!(ns.config("eth0".to_string()).is_err(), "{}={}", key, value);
+    }
+}
+
+#[test]
+fn test_ifname_policy() {
+    init_logger();
+
+    let net = |name: &str, ifname: Option<&str>| {
+        let mut net = ConfigFile::config_from_bytes(
+            format!(
+                r#"{{"cniVersion":"1.0.0","name":"{}","plugins":[{{"type":"bridge"}}]}}"#,
+                name
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        net.ifname = ifname.map(|s| s.to_string());
+        net
+    };
+    let k8s = IfnamePolicy::Kubernetes;
+    assert_eq!(k8s.ifname("veth", 0, &net("a", None)), "eth0");
+    assert_eq!(k8s.ifname("veth", 2, &net("b", None)), "net2");
+    assert_eq!(k8s.ifname("veth", 2, &net("b", Some("data0"))), "data0");
+    assert_eq!(
+        IfnamePolicy::Prefix.ifname("veth", 3, &net("c", None)),
+        "veth3"
+    );
+
+    for bad in [
+        "",
+        ".",
+        "..",
+        "a/b",
+        "a b",
+        "eth\t0",
+        "a:1",
+        "sixteen-bytes-xx",
+    ] {
+        assert!(validate_ifname(bad).is_err(), "{:?}", bad);
+    }
+    for good in ["eth0", "net1", "lo", "fifteen-bytes-x", "veth.100"] {
+        assert!(validate_ifname(good).is_ok(), "{:?}", good);
+    }
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    let calls = format!("{}/calls", test_dir);
+    create_fake_plugin(
+        &bin_dir,
+        "recorder",
+        &format!(
+            r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+echo "$CNI_COMMAND $CNI_IFNAME" >> {}
+echo '{{"cniVersion":"1.0.0"}}'"#,
+            calls
+        ),
+    )
+    .unwrap();
+    fs::create_dir_all(&conf_dir).unwrap();
+    fs::write(format!("{}/00-broken.conflist", conf_dir), "{").unwrap();
+    fs::write(
+        format!("{}/10-a.conflist", conf_dir),
+        r#"{"cniVersion":"1.0.0","name":"net-a","plugins":[{"type":"recorder"}]}"#,
+    )
+    .unwrap();
+    fs::write(
+        format!("{}/20-b.conflist", conf_dir),
+        r#"{"cniVersion":"1.0.0","name":"net-b","interfaceName":"data0",
+            "plugins":[{"type":"recorder"}]}"#,
+    )
+    .unwrap();
+
+    let load = |policy: IfnamePolicy| {
+        let mut cni = Libcni::new(
+            Some(vec![bin_dir.clone()]),
+            Some(conf_dir.clone()),
+            Some(format!("{}/cache", test_dir)),
+        );
+        cni.set_max_conf_num(3);
+        cni.set_ifname_policy(policy);
+        cni.load_default_conf();
+        cni
+    };
+
+    // The unreadable config does not leave a gap in the names
+    let cni = load(IfnamePolicy::Prefix);
+    let mut ifnames: Vec<_> = cni
+        .get_networks()
+        .iter()
+        .map(|n| n.ifname.clone())
+        .collect();
+    ifnames.sort();
+    assert_eq!(ifnames.len(), 2);
+    assert!(
+        ifnames == ["data0", "vethcni0"] || ifnames == ["data0", "vethcni1"],
+        "{:?}",
+        ifnames
+    );
+    // but the network is still missing, so the runtime is not ready
+    assert!(cni.status().is_err());
+    fs::remove_file(format!("{}/00-broken.conflist", conf_dir)).unwrap();
+    assert!(load(IfnamePolicy::Prefix).status().is_ok());
+
+    // An invalid name fails the setup before any plugin runs
+    let cni = load(IfnamePolicy::Custom(Arc::new(|_, net| {
+        format!("{}-interface0", net.name)
+    })));
+    let err = cni
+        .setup("naming".to_string(), "/proc/self/ns/net".to_string())
+        .err()
+        .unwrap();
+    assert!(err.contains("net-a-interface0"), "{}", err);
+    assert!(!Path::new(&calls).exists());
+
+    let cni = load(IfnamePolicy::Custom(Arc::new(|i, _| format!("pod{}", i))));
+    cni.setup("naming".to_string(), "/proc/self/ns/net".to_string())
+        .unwrap();
+    let mut calls = fs::read_to_string(&calls)
+        .unwrap()
+        .lines()
+        .map(|l| l.to_string())
+        .collect::<Vec<_>>();
+    calls.sort();
+    assert!(calls[0] == "ADD data0", "{:?}", calls);
+    assert!(calls[1].starts_with("ADD pod"), "{:?}", calls);
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_runtime_conf_validation() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let calls = format!("{}/calls", test_dir);
+    create_fake_plugin(
+        &test_dir,
+        "recorder",
+        &format!(
+            r#"echo "$CNI_COMMAND" >> {}
+echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'"#,
+            calls
+        ),
+    )
+    .unwrap();
+    let conflist = r#"{"cniVersion":"1.0.0","name":"valid-net","plugins":[{"type":"recorder"}]}"#;
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let valid = RuntimeConf {
+        container_id: "abc-1.2_3".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+
+    let invalid = [
+        RuntimeConf {
+            container_id: "".to_string(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            container_id: "-leading-dash".to_string(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            container_id: "has/slash".to_string(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            if_name: "eth 0".to_string(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            net_ns: "proc/self/ns/net".to_string(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            net_ns: test_dir.clone(),
+            ..valid.clone()
+        },
+        RuntimeConf {
+            net_ns: format!("{}/gone", test_dir),
+            ..valid.clone()
+        },
+    ];
+    for rt in invalid {
+        let err = cni.add_network_list(net.clone(), rt).err().unwrap();
+        assert_eq!(err.code(), Some(4), "{}", err);
+    }
+    assert!(!Path::new(&calls).exists(), "no plugin should have run");
+
+    // DEL still works once the namespace is gone
+    let gone = RuntimeConf {
+        net_ns: format!("{}/gone", test_dir),
+        ..valid.clone()
+    };
+    cni.delete_network_list(net.clone(), gone).unwrap();
+    cni.add_network_list(net, valid).unwrap();
+    assert_eq!(fs::read_to_string(&calls).unwrap(), "DEL\nVERSION\nADD\n");
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_managed_netns() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let netns_dir = format!("{}/netns", test_dir);
+
+    let netns = NetNs::create_in(&netns_dir, "managed").unwrap();
+    let ino = fs::metadata(netns.path()).unwrap().ino();
+    assert_ne!(ino, fs::metadata("/proc/self/ns/net").unwrap().ino());
+    assert!(NetNs::create_in(&netns_dir, "managed").is_err());
+
+    // Only the closure runs inside, the calling thread is left alone
+    let inside = netns
+        .run(|| fs::read_link("/proc/thread-self/ns/net").unwrap())
+        .unwrap();
+    assert_eq!(inside.to_string_lossy(), format!("net:[{}]", ino));
+    let links = netns
+        .run(|| fs::read_to_string("/proc/thread-self/net/dev").unwrap())
+        .unwrap();
+    assert_eq!(links.lines().skip(2).count(), 1, "{}", links);
+    assert!(links.contains("lo:"));
+    assert_ne!(fs::read_link("/proc/thread-self/ns/net").unwrap(), inside);
+
+    // Opened namespaces are not deleted when dropped
+    let opened = NetNs::open(&netns.path_str()).unwrap();
+    drop(opened);
+    assert!(netns.path().exists());
+    assert!(NetNs::from_pid(std::process::id()).is_ok());
+    assert!(NetNs::open(&test_dir).is_err());
+
+    // The Namespace keeps the netns alive for as long as it is used
+    let calls = format!("{}/calls", test_dir);
+    create_fake_plugin(
+        &test_dir,
+        "recorder",
+        &format!(
+            r#"echo "$CNI_COMMAND $CNI_NETNS" >> {}
+echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'"#,
+            calls
+        ),
+    )
+    .unwrap();
+    let conflist = r#"{"cniVersion":"1.0.0","name":"netns-net","plugins":[{"type":"recorder"}]}"#;
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let path = netns.path_str();
+    let ns = Namespace::with_netns("managed".to_string(), netns);
+    cni.add_network_list(net, ns.clone().config("eth0".to_string()).unwrap())
+        .unwrap();
+    assert!(fs::read_to_string(&calls)
+        .unwrap()
+        .ends_with(&format!("ADD {}\n", path)));
+    drop(ns);
+    assert!(!Path::new(&path).exists());
+
+    let netns = NetNs::create_in(&netns_dir, "deleted").unwrap();
+    let path = netns.path().to_path_buf();
+    netns.delete().unwrap();
+    assert!(!path.exists());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_verify_result_in_netns() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let netns = NetNs::create_in(&format!("{}/netns", test_dir), "verify").unwrap();
+    let netns_path = netns.path_str();
+
+    // What a bridge-like plugin would have set up
+    netns
+        .run(|| {
+            for args in [
+                "link add v0 address 02:00:00:00:00:01 type veth peer name v1",
+                "link set v1 up",
+                "link set v0 up",
+                "addr add 10.9.0.2/24 dev v0",
+                "route add 10.10.0.0/16 via 10.9.0.1",
+                "-6 addr add fd00::2/64 dev v0 nodad",
+                "-6 route add fd01::/64 via fd00::1",
+                "route add 10.11.0.0/16 dev v1",
+            ] {
+                let status = Command::new("ip").args(args.split(' ')).status().unwrap();
+                assert!(status.success(), "ip {}", args);
+            }
+        })
+        .unwrap();
+
+    let result = format!(
+        r#"{{"cniVersion":"1.0.0",
+  "interfaces":[{{"name":"vethhost","mac":"aa:aa:aa:aa:aa:aa"}},
+                {{"name":"v0","mac":"02:00:00:00:00:01","sandbox":"{}"}}],
+  "ips":[{{"interface":1,"address":"10.9.0.2/24","gateway":"10.9.0.1"}},
+         {{"interface":1,"address":"fd00::2/64"}},
+         {{"interface":0,"address":"192.0.2.1/24"}}],
+  "routes":[{{"dst":"10.10.0.0/16","gw":"10.9.0.1"}},{{"dst":"fd01::/64"}}]}}"#,
+        netns_path
+    );
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    create_fake_plugin(
+        &bin_dir,
+        "bridge-like",
+        &format!(
+            r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}}'
+  exit 0
+fi
+cat <<'EOF'
+{}
+EOF"#,
+            result
+        ),
+    )
+    .unwrap();
+    fs::create_dir_all(&conf_dir).unwrap();
+    fs::write(
+        format!("{}/10-verify.conflist", conf_dir),
+        r#"{"cniVersion":"1.0.0","name":"verify-net","plugins":[{"type":"bridge-like"}]}"#,
+    )
+    .unwrap();
+
+    let mut cni = Libcni::new(
+        Some(vec![bin_dir.clone()]),
+        Some(conf_dir.clone()),
+        Some(format!("{}/cache", test_dir)),
+    );
+    cni.load_default_conf();
+    let ns = Namespace::with_netns("verify".to_string(), netns);
+    cni.setup_namespace(&ns).unwrap();
+
+    let reports = cni.verify_namespace(&ns).unwrap();
+    assert_eq!(reports.len(), 1);
+    let report = &reports[0].1;
+    assert!(report.is_ok(), "{}", report);
+    let v0 = report.links.iter().find(|l| l.name == "v0").unwrap();
+    assert_eq!(v0.mac.as_deref(), Some("02:00:00:00:00:01"));
+
+    // The same state against a result it does not match
+    let mut wrong: result100::Result = serde_json::from_str(&result).unwrap();
+    let ifaces = wrong.interfaces.as_mut().unwrap();
+    ifaces[1].mac = Some("02:00:00:00:00:02".to_string());
+    ifaces.push(serde_json::from_str(r#"{"name":"net1","sandbox":"x"}"#).unwrap());
+    wrong.ips.as_mut().unwrap()[0].address = Some("10.9.0.3/24".parse().unwrap());
+    wrong.routes.as_mut().unwrap()[0].gw = Some("10.9.0.254".parse().unwrap());
+    // v1 is in the namespace, but not an interface of the result
+    wrong
+        .routes
+        .as_mut()
+        .unwrap()
+        .push(serde_json::from_str(r#"{"dst":"10.11.0.0/16"}"#).unwrap());
+    let mismatches = diff(&wrong, &report.links, &report.routes);
+    assert_eq!(
+        mismatches,
+        vec![
+            Mismatch::MacMismatch {
+                interface: "v0".to_string(),
+                expected: "02:00:00:00:00:02".to_string(),
+                actual: Some("02:00:00:00:00:01".to_string()),
+            },
+            Mismatch::MissingInterface {
+                name: "net1".to_string()
+            },
+            Mismatch::MissingAddress {
+                interface: Some("v0".to_string()),
+                expected: "10.9.0.3/24".parse().unwrap(),
+                actual: v0.addresses.clone(),
+            },
+            Mismatch::MissingRoute {
+                dst: "10.10.0.0/16".parse().unwrap(),
+                gw: Some("10.9.0.254".parse().unwrap()),
+                dev: Some("v0".to_string()),
+            },
+            Mismatch::MissingRoute {
+                dst: "10.11.0.0/16".parse().unwrap(),
+                gw: None,
+                dev: None,
+            },
+        ]
+    );
+
+    // Opted in, CHECK fails once the namespace no longer matches the result
+    cni.set_verify_results(true);
+    cni.check_namespace(&ns).unwrap();
+    ns.get_netns()
+        .unwrap()
+        .run(|| {
+            let status = Command::new("ip")
+                .args(["route", "del", "10.10.0.0/16"])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        })
+        .unwrap();
+    let err = cni.check_namespace(&ns).unwrap_err();
+    assert!(err.contains("10.10.0.0/16 via 10.9.0.1 dev v0"), "{}", err);
+
+    drop(ns);
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_native_loopback() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let netns = NetNs::create_in(&format!("{}/netns", test_dir), "native-lo").unwrap();
+    let lo_flags = |netns: &NetNs| {
+        netns
+            .run(|| {
+                let out = Command::new("ip")
+                    .args(["-o", "link", "show", "lo"])
+                    .output()
+                    .unwrap();
+                String::from_utf8_lossy(&out.stdout).to_string()
+            })
+            .unwrap()
+    };
+    assert!(!lo_flags(&netns).contains(",UP"));
+
+    // No loopback binary in the plugin dirs, nothing is exec'd
+    let mut cni = Libcni::new(
+        Some(vec![test_dir.clone()]),
+        Some(format!("{}/conf", test_dir)),
+        Some(format!("{}/cache", test_dir)),
+    );
+    cni.add_native_lo_network().unwrap();
+    // Rebuilding the exec backed interface keeps the native one
+    cni.set_retry_policy(RetryPolicy::default());
+
+    let ns = Namespace::with_netns("native-lo".to_string(), netns);
+    cni.setup_namespace(&ns).unwrap();
+    assert!(lo_flags(ns.get_netns().unwrap()).contains(",UP"));
+    cni.check_namespace(&ns).unwrap();
+
+    let reports = cni.verify_namespace(&ns).unwrap();
+    assert_eq!(reports.len(), 1);
+    assert!(reports[0].1.is_ok(), "{}", reports[0].1);
+
+    cni.remove_namespace(&ns).unwrap();
+    assert!(!lo_flags(ns.get_netns().unwrap()).contains(",UP"));
+    assert!(cni.check_namespace(&ns).is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_native_plugin_registry() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let calls = format!("{}/calls", test_dir);
+    // Second in the chain, after the native plugin
+    create_fake_plugin(
+        &test_dir,
+        "external",
+        &format!(
+            r#"echo "external $CNI_COMMAND" >> {}
+echo '{{"cniVersion":"1.0.0","supportedVersions":["1.0.0"],"ips":[{{"address":"10.1.0.2/24"}}]}}'"#,
+            calls
+        ),
+    )
+    .unwrap();
+    create_fake_plugin(
+        &test_dir,
+        "meta",
+        r#"echo '{"code":7,"msg":"the binary must not run"}'"#,
+    )
+    .unwrap();
+
+    let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let recorder = Arc::clone(&seen);
+    let meta = move |stdin: &[u8], env: &HashMap<String, String>| {
+        // VERSION gets no config
+        let conf: serde_json::Value = serde_json::from_slice(stdin).unwrap_or_default();
+        recorder.lock().unwrap().push(format!(
+            "{} {} {} {}",
+            env["CNI_COMMAND"], env["CNI_IFNAME"], conf["name"], conf["answer"]
+        ));
+        Ok(match env["CNI_COMMAND"].as_str() {
+            "VERSION" => br#"{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}"#.to_vec(),
+            "ADD" => br#"{"cniVersion":"1.0.0","interfaces":[{"name":"eth0"}]}"#.to_vec(),
+            _ => Vec::new(),
+        })
+    };
+    let failing = |_: &[u8], _: &HashMap<String, String>| {
+        Ok(br#"{"code":11,"msg":"try again later"}"#.to_vec())
+    };
+
+    let conflist = r#"{"cniVersion":"1.0.0","name":"mixed-net","plugins":[
+        {"type":"meta","answer":42},{"type":"external"}]}"#;
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let rt = RuntimeConf {
+        container_id: "native".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+    let mut cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        registry: PluginRegistry::new().with_plugin("meta", meta),
+        ..Default::default()
+    };
+
+    // Registered plugins shadow binaries by default
+    let result = cni.add_network_list(net.clone(), rt.clone()).unwrap();
+    assert!(result.get_json().dump().contains("10.1.0.2/24"));
+    cni.check_network_list(net.clone(), rt.clone()).unwrap();
+    cni.delete_network_list(net.clone(), rt.clone()).unwrap();
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            "VERSION  null null",
+            "ADD eth0 \"mixed-net\" 42",
+            "CHECK eth0 \"mixed-net\" 42",
+            "DEL eth0 \"mixed-net\" 42",
+        ]
+    );
+    assert_eq!(
+        fs::read_to_string(&calls).unwrap(),
+        "external VERSION\nexternal ADD\nexternal CHECK\nexternal DEL\n"
+    );
+
+    // Error objects are handled like those printed by binaries
+    cni.registry.register("external", failing);
+    match cni.add_network_list(net.clone(), rt.clone()) {
+        Err(e) => assert!(e.to_string().contains("try again later"), "{}", e),
+        Ok(_) => panic!("the native plugin error was ignored"),
+    }
+
+    // Binaries win when looked up first, the registry is the fallback
+    cni.registry = PluginRegistry::new()
+        .with_order(LookupOrder::DiskFirst)
+        .with_plugin("meta", failing)
+        .with_plugin("missing", failing);
+    assert!(cni.add_network_list(net.clone(), rt.clone()).is_err());
+    fs::remove_file(format!("{}/meta", test_dir)).unwrap();
+    let err = cni.add_network_list(net.clone(), rt.clone()).err().unwrap();
+    assert!(err.to_string().contains("try again later"), "{}", err);
+    cni.registry.unregister("meta");
+    assert!(cni.validate_network_list(net).is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+struct SkelTestPlugin;
+
+impl skel::Plugin for SkelTestPlugin {
+    fn add(&self, args: &skel::CmdArgs) -> ResultCNI<Box<dyn APIResult>> {
+        let conf = args.net_conf()?;
+        Ok(Box::new(result100::Result {
+            interfaces: Some(vec![result100::Interface {
+                name: Some(format!("{}-{}", conf.name, args.ifname)),
+                mac: None,
+                sandbox: None,
+            }]),
+            ..Default::default()
+        }))
+    }
+
+    fn del(&self, args: &skel::CmdArgs) -> ResultCNI<()> {
+        match args.parsed_args().iter().any(|(k, _)| k == "FAIL") {
+            true => Err(Box::new(CNIError::Config("asked to fail".to_string()))),
+            false => Ok(()),
+        }
+    }
+
+    fn check(&self, _args: &skel::CmdArgs) -> ResultCNI<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_plugin_skel() {
+    init_logger();
+
+    let info = PluginInfoT {
+        supported_versions: vec![
+            "0.4.0".to_string(),
+            "1.0.0".to_string(),
+            "1.1.0".to_string(),
+        ],
+        ..Default::default()
+    };
+    let env = |command: &str, extra: &[(&str, &str)]| {
+        let mut env: HashMap<String, String> = [
+            ("CNI_COMMAND", command),
+            ("CNI_CONTAINERID", "skel"),
+            ("CNI_NETNS", "/proc/self/ns/net"),
+            ("CNI_IFNAME", "eth0"),
+            ("CNI_PATH", "/opt/cni/bin"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        for (k, v) in extra {
+            env.insert(k.to_string(), v.to_string());
+        }
+        env
+    };
+    let run = |env: HashMap<String, String>, conf: &str| {
+        let mut out = Vec::new();
+        let res = skel::dispatch(&SkelTestPlugin, &info, &env, conf.as_bytes(), &mut out);
+        (res, String::from_utf8(out).unwrap())
+    };
+    let conf = r#"{"cniVersion":"1.0.0","name":"skel-net","type":"skel"}"#;
+
+    let (res, out) = run(env("VERSION", &[]), "");
+    res.unwrap();
+    let version: PluginInfoT = serde_json::from_str(&out).unwrap();
+    assert_eq!(version.cni_version, "1.1.0");
+    assert_eq!(version.supported_versions, info.supported_versions);
+
+    let (res, out) = run(env("ADD", &[]), conf);
+    res.unwrap();
+    let result: result100::Result = serde_json::from_str(&out).unwrap();
+    assert_eq!(result.cni_version.as_deref(), Some("1.0.0"));
+    assert_eq!(
+        result.interfaces.unwrap()[0].name.as_deref(),
+        Some("skel-net-eth0")
+    );
+    assert_eq!(run(env("CHECK", &[]), conf), (Ok(()), String::new()));
+
+    // DEL does not need a netns, but the handler's error is reported
+    let (res, _) = run(env("DEL", &[("CNI_NETNS", "")]), conf);
+    res.unwrap();
+    let err = run(env("DEL", &[("CNI_ARGS", "FAIL=1")]), conf)
+        .0
+        .unwrap_err();
+    assert_eq!((err.code, err.cni_version.as_str()), (7, "1.0.0"));
+
+    let err = run(env("ADD", &[("CNI_NETNS", ""), ("CNI_PATH", "")]), conf)
+        .0
+        .unwrap_err();
+    assert_eq!(err.code, 4);
+    assert!(err.msg.contains("[CNI_NETNS,CNI_PATH]"), "{}", err.msg);
+    assert_eq!(
+        run(env("ADD", &[("CNI_IFNAME", "a/b")]), conf)
+            .0
+            .unwrap_err()
+            .code,
+        4
+    );
+    assert_eq!(run(env("FROB", &[]), conf).0.unwrap_err().code, 4);
+    assert_eq!(run(env("ADD", &[]), "not json").0.unwrap_err().code, 6);
+    let old = r#"{"cniVersion":"0.3.1","name":"skel-net","type":"skel"}"#;
+    assert_eq!(run(env("ADD", &[]), old).0.unwrap_err().code, 1);
+    // STATUS and GC need a 1.1.0 config
+    assert_eq!(run(env("STATUS", &[]), conf).0.unwrap_err().code, 1);
+    let conf11 = r#"{"cniVersion":"1.1.0","name":"skel-net","type":"skel"}"#;
+    assert_eq!(run(env("GC", &[]), conf11), (Ok(()), String::new()));
+
+    // A skel plugin registered as a native plugin goes through the runtime
+    let native = move |stdin: &[u8], env: &HashMap<String, String>| {
+        let mut out = Vec::new();
+        match skel::dispatch(&SkelTestPlugin, &info, env, stdin, &mut out) {
+            Ok(()) => Ok(out),
+            Err(e) => Ok(serde_json::to_vec(&e).unwrap()),
+        }
+    };
+    let cni = CNIConfig {
+        path: vec!["/nonexistent".to_string()],
+        cache_dir: format!("/tmp/cni-test-{}", uuid::Uuid::new_v4()),
+        registry: PluginRegistry::new().with_plugin("skel", native),
+        ..Default::default()
+    };
+    let list = format!(
+        r#"{{"cniVersion":"1.0.0","name":"skel-net","plugins":[{}]}}"#,
+        conf
+    );
+    let net = ConfigFile::config_from_bytes(list.as_bytes()).unwrap();
+    let rt = RuntimeConf {
+        container_id: "skel".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth1".to_string(),
+        args: vec![["FAIL".to_string(), "1".to_string()]],
+        ..Default::default()
+    };
+    let result = cni.add_network_list(net.clone(), rt.clone()).unwrap();
+    assert!(result.get_json().dump().contains("skel-net-eth1"));
+    let err = cni.delete_network(
+        "skel-net".to_string(),
+        "1.0.0".to_string(),
+        net.plugins[0].clone(),
+        rt,
+    );
+    assert_eq!(err.unwrap_err().code(), Some(7));
+    cleanup_test_environment(&cni.cache_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_ipam_delegation() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let ipam_dir = format!("{}/ipam", test_dir);
+    let calls = format!("{}/calls", test_dir);
+    fs::create_dir_all(&ipam_dir).unwrap();
+    // Records the delegated command and the config it was given
+    let script = format!(
+        r#"#!/bin/sh
+conf=$(cat)
+echo "$CNI_COMMAND $CNI_CONTAINERID $CNI_IFNAME $conf" >> {}
+case "$CNI_COMMAND" in
+ADD) echo '{{"cniVersion":"1.0.0","ips":[{{"address":"10.2.0.5/24","gateway":"10.2.0.1"}}]}}' ;;
+CHECK) echo '{{"code":11,"msg":"not yet"}}' ;;
+esac
+"#,
+        calls
+    );
+    fs::write(format!("{}/fake-ipam", ipam_dir), script).unwrap();
+    fs::set_permissions(
+        format!("{}/fake-ipam", ipam_dir),
+        fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+
+    let netconf =
+        br#"{"cniVersion":"1.0.0","name":"bridge-net","type":"bridge","ipam":{"type":"fake-ipam"}}"#;
+    assert_eq!(ipam::ipam_type(netconf).unwrap(), "fake-ipam");
+    assert!(ipam::ipam_type(br#"{"name":"no-ipam","type":"bridge"}"#).is_err());
+
+    // CNI_COMMAND is the delegated one, the rest of the invocation is kept
+    let args = skel::CmdArgs {
+        container_id: "delegating".to_string(),
+        netns: "/proc/self/ns/net".to_string(),
+        ifname: "eth0".to_string(),
+        path: format!("{}:{}", test_dir, ipam_dir),
+        ..Default::default()
+    };
+    let delegate = ipam::Delegate::from_args(&args);
+    let result = delegate.add("fake-ipam", netconf).unwrap();
+    let ip = &result.ips.unwrap()[0];
+    assert_eq!(ip.address.unwrap().to_string(), "10.2.0.5/24");
+    assert_eq!(ip.gateway.unwrap().to_string(), "10.2.0.1");
+    let err = delegate.check("fake-ipam", netconf).unwrap_err();
+    assert!(err.is_try_again_later(), "{}", err);
+    delegate.del("fake-ipam", netconf).unwrap();
+    let netconf = String::from_utf8_lossy(netconf);
+    assert_eq!(
+        fs::read_to_string(&calls).unwrap(),
+        format!(
+            "ADD delegating eth0 {0}\nCHECK delegating eth0 {0}\nDEL delegating eth0 {0}\n",
+            netconf
+        )
+    );
+
+    let delegate = ipam::Delegate::new(
+        RawExec::default(),
+        vec![
+            "CNI_COMMAND=ADD".to_string(),
+            format!("CNI_PATH={}", test_dir),
+        ],
+    );
+    assert!(delegate.del("fake-ipam", netconf.as_bytes()).is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_host_local_ipam() {
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let data_dir = format!("{}/networks", test_dir);
+    let rt = |id: &str| RuntimeConf {
+        container_id: id.to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+    let addresses = |result: &dyn APIResult| {
+        let result: result100::Result = serde_json::from_str(&result.get_json().dump()).unwrap();
+        result
+            .ips
+            .unwrap_or_default()
+            .iter()
+            .map(|ip| format!("{} via {}", ip.address.unwrap(), ip.gateway.unwrap()))
+            .collect::<Vec<_>>()
+    };
+
+    // Dual-stack, in-process
+    let conflist = format!(
+        r#"{{"cniVersion":"1.0.0","name":"dual","plugins":[{{"type":"host-local","ipam":{{
+            "type":"host-local","dataDir":"{}","routes":[{{"dst":"0.0.0.0/0"}}],
+            "ranges":[
+                [{{"subnet":"10.9.0.0/29","rangeStart":"10.9.0.2","rangeEnd":"10.9.0.4"}}],
+                [{{"subnet":"fd00:9::/120","rangeStart":"fd00:9::10","rangeEnd":"fd00:9::11"}}]]}}}}]}}"#,
+        data_dir
+    );
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        registry: PluginRegistry::new().with_plugin("host-local", HostLocal::native()),
+        ..Default::default()
+    };
+
+    let result = cni.add_network_list(net.clone(), rt("c1")).unwrap();
+    assert_eq!(
+        addresses(result.as_ref()),
+        vec!["10.9.0.2/29 via 10.9.0.1", "fd00:9::10/120 via fd00:9::1"]
+    );
+    assert!(result
+        .get_json()
+        .dump()
+        .contains(r#""routes":[{"dst":"0.0.0.0/0"}]"#));
+    let store = format!("{}/dual", data_dir);
+    assert_eq!(
+        fs::read_to_string(format!("{}/10.9.0.2", store)).unwrap(),
+        "c1\r\neth0"
+    );
+    assert_eq!(
+        fs::read_to_string(format!("{}/last_reserved_ip.1", store)).unwrap(),
+        "fd00:9::10"
+    );
+    assert!(Path::new(&format!("{}/lock", store)).exists());
+
+    let result = cni.add_network_list(net.clone(), rt("c2")).unwrap();
+    assert_eq!(
+        addresses(result.as_ref()),
+        vec!["10.9.0.3/29 via 10.9.0.1", "fd00:9::11/120 via fd00:9::1"]
+    );
+    // One address per range set and attachment
+    assert!(cni.add_network_list(net.clone(), rt("c2")).is_err());
+
+    // The IPv6 range is exhausted, the IPv4 address is given back
+    let err = cni.add_network_list(net.clone(), rt("c3")).err().unwrap();
+    assert!(
+        err.to_string().contains("no IP addresses available"),
+        "{}",
+        err
+    );
+    assert!(!Path::new(&format!("{}/10.9.0.4", store)).exists());
+
+    cni.check_network_list(net.clone(), rt("c2")).unwrap();
+    cni.delete_network_list(net.clone(), rt("c1")).unwrap();
+    cni.delete_network_list(net.clone(), rt("c1")).unwrap();
+    assert!(!Path::new(&format!("{}/10.9.0.2", store)).exists());
+    assert!(cni.check_network_list(net.clone(), rt("c1")).is_err());
+
+    // Allocation goes on after the last reserved address, 10.9.0.4 by the
+    // failed attempt, and wraps around
+    assert_eq!(
+        fs::read_to_string(format!("{}/last_reserved_ip.0", store)).unwrap(),
+        "10.9.0.4"
+    );
+    let result = cni.add_network_list(net.clone(), rt("c3")).unwrap();
+    assert_eq!(
+        addresses(result.as_ref()),
+        vec!["10.9.0.2/29 via 10.9.0.1", "fd00:9::10/120 via fd00:9::1"]
+    );
+
+    // GC releases the attachments that are not valid anymore
+    let env: HashMap<String, String> = [("CNI_COMMAND", "GC"), ("CNI_PATH", test_dir.as_str())]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    let gc_conf = format!(
+        r#"{{"cniVersion":"1.1.0","name":"dual","type":"host-local",
+            "cni.dev/valid-attachments":[{{"containerID":"c2","ifname":"eth0"}}],
+            "ipam":{{"type":"host-local","dataDir":"{}","ranges":[[{{"subnet":"10.9.0.0/29"}}]]}}}}"#,
+        data_dir
+    );
+    let out = HostLocal::native().exec(gc_conf.as_bytes(), &env).unwrap();
+    assert!(out.is_empty(), "{}", String::from_utf8_lossy(&out));
+    let mut left: Vec<String> = fs::read_dir(&store)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|n| n.parse::<std::net::IpAddr>().is_ok())
+        .collect();
+    left.sort();
+    assert_eq!(left, vec!["10.9.0.3", "fd00:9::11"]);
+    assert!(fs::read_to_string(format!("{}/10.9.0.3", store))
+        .unwrap()
+        .starts_with("c2\r\n"));
+
+    // Older single range configs, through the plugin binary
+    let bin = Path::new(env!("CARGO_BIN_EXE_host-local"));
+    let legacy = format!(
+        r#"{{"cniVersion":"1.0.0","name":"legacy","plugins":[{{"type":"host-local",
+            "ipam":{{"type":"host-local","subnet":"10.8.0.0/24","dataDir":"{}"}}}}]}}"#,
+        data_dir
+    );
+    let net = ConfigFile::config_from_bytes(legacy.as_bytes()).unwrap();
+    let cni = CNIConfig {
+        path: vec![bin.parent().unwrap().to_string_lossy().to_string()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let mut requested = rt("r1");
+    requested.args = vec![["IP".to_string(), "10.8.0.77".to_string()]];
+    let result = cni.add_network_list(net.clone(), requested).unwrap();
+    assert_eq!(
+        addresses(result.as_ref()),
+        vec!["10.8.0.77/24 via 10.8.0.1"]
+    );
+    let result = cni.add_network_list(net.clone(), rt("r2")).unwrap();
+    assert_eq!(
+        addresses(result.as_ref()),
+        vec!["10.8.0.78/24 via 10.8.0.1"]
+    );
+    let mut gateway = rt("r3");
+    gateway.args = vec![["IP".to_string(), "10.8.0.1".to_string()]];
+    assert!(cni.add_network_list(net.clone(), gateway).is_err());
+    cni.delete_network_list(net.clone(), rt("r2")).unwrap();
+    assert!(!Path::new(&format!("{}/legacy/10.8.0.78", data_dir)).exists());
+    assert!(Path::new(&format!("{}/legacy/10.8.0.77", data_dir)).exists());
+
+    // Allocations written by older stores hold only the container ID
+    let old = format!("{}/legacy/10.8.0.50", data_dir);
+    fs::write(&old, "old").unwrap();
+    cni.check_network_list(net.clone(), rt("old")).unwrap();
+    cni.delete_network_list(net.clone(), rt("old")).unwrap();
+    assert!(!Path::new(&old).exists());
+
+    // An unreadable resolvConf fails ADD before anything is reserved
+    let unresolved = format!(
+        r#"{{"cniVersion":"1.0.0","name":"unresolved","plugins":[{{"type":"host-local",
+            "ipam":{{"type":"host-local","subnet":"10.7.0.0/24","dataDir":"{}",
+            "resolvConf":"{}/missing.conf"}}}}]}}"#,
+        data_dir, test_dir
+    );
+    let net = ConfigFile::config_from_bytes(unresolved.as_bytes()).unwrap();
+    assert!(cni.add_network_list(net, rt("r5")).is_err());
+    assert!(!Path::new(&format!("{}/unresolved/10.7.0.2", data_dir)).exists());
+
+    let bad = r#"{"cniVersion":"1.0.0","name":"bad","plugins":[{"type":"host-local",
+        "ipam":{"type":"host-local","subnet":"10.8.0.1/24"}}]}"#;
+    let net = ConfigFile::config_from_bytes(bad.as_bytes()).unwrap();
+    let err = cni.add_network_list(net, rt("r4")).err().unwrap();
+    assert_eq!(err.code(), Some(7), "{}", err);
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_static_ipam() {
+    init_logger();
+
+    let env = |command: &str, netns: &str, ifname: &str, args: &str| {
+        [
+            ("CNI_COMMAND", command),
+            ("CNI_CONTAINERID", "static"),
+            ("CNI_NETNS", netns),
+            ("CNI_IFNAME", ifname),
+            ("CNI_ARGS", args),
+            ("CNI_PATH", "/opt/cni/bin"),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect::<HashMap<String, String>>()
+    };
+    let conf = |version: &str, ipam: &str| {
+        format!(
+            r#"{{"cniVersion":"{}","name":"static-net","type":"static","ipam":{}}}"#,
+            version, ipam
+        )
+    };
+    let ipam = r#"{"type":"static",
+        "addresses":[{"address":"fd00:30::5/64","gateway":"fd00:30::1"}],
+        "routes":[{"dst":"0.0.0.0/0","gw":"10.30.0.1"}],
+        "dns":{"nameservers":["10.30.0.53"],"search":["example.com"]}}"#;
+    let run = |env: HashMap<String, String>, conf: &str| {
+        let mut out = Vec::new();
+        skel::dispatch(
+            &StaticIpam,
+            &StaticIpam::plugin_info(),
+            &env,
+            conf.as_bytes(),
+            &mut out,
+        )
+        .map(|_| serde_json::from_slice::<serde_json::Value>(&out).unwrap())
+    };
+
+    // CNI_ARGS adds addresses, GATEWAY goes to the address of its subnet
+    let args = "IgnoreUnknown=1;IP=10.30.0.5/24;GATEWAY=10.30.0.1";
+    let out = run(
+        env("ADD", "/proc/self/ns/net", "eth0", args),
+        &conf("1.0.0", ipam),
+    )
+    .unwrap();
+    assert_eq!(
+        out,
+        serde_json::json!({
+            "cniVersion": "1.0.0",
+            "ips": [
+                {"address": "fd00:30::5/64", "gateway": "fd00:30::1"},
+                {"address": "10.30.0.5/24", "gateway": "10.30.0.1"}
+            ],
+            "routes": [{"dst": "0.0.0.0/0", "gw": "10.30.0.1"}],
+            "dns": {"nameservers": ["10.30.0.53"], "search": ["example.com"]}
+        })
+    );
+    // Older specs carry the version of each address
+    let out = run(
+        env("ADD", "/proc/self/ns/net", "eth0", args),
+        &conf("0.4.0", ipam),
+    )
+    .unwrap();
+    assert_eq!(out["cniVersion"], "0.4.0");
+    assert_eq!(out["ips"][0]["version"], "6");
+    assert_eq!(out["ips"][1]["version"], "4");
+
+    let bad_gw =
+        r#"{"type":"static","addresses":[{"address":"10.30.0.5/24","gateway":"fd00::1"}]}"#;
+    let no_prefix = r#"{"type":"static","addresses":[{"address":"10.30.0.5"}]}"#;
+    let none = r#"{"type":"static"}"#;
+    // Addresses without a prefix do not decode, the others are invalid
+    for (ipam, code) in [(bad_gw, 7), (no_prefix, 6), (none, 7)] {
+        let err = run(
+            env("ADD", "/proc/self/ns/net", "eth0", ""),
+            &conf("1.0.0", ipam),
+        )
+        .unwrap_err();
+        assert_eq!(err.code, code, "{}", ipam);
+    }
+    let err = run(
+        env("ADD", "/proc/self/ns/net", "eth0", "IP=10.30.0.6"),
+        &conf("1.0.0", none),
+    );
+    assert_eq!(err.unwrap_err().code, 7);
+
+    // CHECK looks for the addresses on the interface
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let netns = NetNs::create_in(&format!("{}/netns", test_dir), "static").unwrap();
+    let added = netns
+        .run(|| {
+            Command::new("ip")
+                .args(["addr", "add", "10.30.0.5/24", "dev", "lo"])
+                .status()
+                .unwrap()
+        })
+        .unwrap();
+    assert!(added.success());
+    let v4 = r#"{"type":"static","addresses":[{"address":"10.30.0.5/24"}]}"#;
+    let path = netns.path_str();
+    let check = |ifname: &str, args: &str| {
+        skel::dispatch(
+            &StaticIpam,
+            &StaticIpam::plugin_info(),
+            &env("CHECK", &path, ifname, args),
+            conf("1.0.0", v4).as_bytes(),
+            Vec::new(),
+        )
+    };
+    check("lo", "").unwrap();
+    let err = check("lo", "IP=10.30.0.6/24").unwrap_err();
+    assert!(err.msg.contains("10.30.0.6/24"), "{}", err.msg);
+    assert!(check("eth9", "").is_err());
+    drop(netns);
+
+    // The ips capability, through the plugin binary
+    let bin = Path::new(env!("CARGO_BIN_EXE_static"));
+    let conflist = r#"{"cniVersion":"0.4.0","name":"static-net","plugins":[{"type":"static",
+        "capabilities":{"ips":true},"ipam":{"type":"static"}}]}"#;
+    let net = ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap();
+    let cni = CNIConfig {
+        path: vec![bin.parent().unwrap().to_string_lossy().to_string()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let rt = RuntimeConf {
+        container_id: "static".to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        capability_args: [("ips".to_string(), r#"["10.40.0.9/16"]"#.to_string())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let result = cni.add_network_list(net.clone(), rt.clone()).unwrap();
+    let result: result100::Result = serde_json::from_str(&result.get_json().dump()).unwrap();
+    assert_eq!(result.cni_version.as_deref(), Some("0.4.0"));
+    assert_eq!(
+        result.ips.unwrap()[0].address.unwrap().to_string(),
+        "10.40.0.9/16"
+    );
+    cni.delete_network_list(net, rt).unwrap();
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_plugin_conf_models() {
+    init_logger();
+
+    let bridge = BridgeConf::new()
+        .with_cni_version("1.0.0")
+        .with_name("models")
+        .with_bridge("cni0")
+        .with_is_gateway(true)
+        .with_ip_masq(true)
+        .with_mtu(1450u32)
+        .with_ipam(
+            HostLocalConf::new()
+                .with_range(
+                    Range::new("10.40.0.0/24".parse().unwrap())
+                        .with_gateway("10.40.0.254".parse().unwrap()),
+                )
+                .with_data_dir("/tmp/models"),
+        )
+        .to_network_config()
+        .unwrap();
+    assert_eq!(bridge.network._type, "bridge");
+    assert_eq!(bridge.network.name, "models");
+    assert_eq!(bridge.network.ipam.as_ref().unwrap()._type, "host-local");
+    let json: serde_json::Value = serde_json::from_slice(&bridge.bytes).unwrap();
+    assert_eq!(json["type"], "bridge");
+    assert_eq!(json["isGateway"], true);
+    assert_eq!(json["ipMasq"], true);
+    assert_eq!(json["mtu"], 1450);
+    assert!(json.get("hairpinMode").is_none());
+    assert_eq!(json["ipam"]["ranges"][0][0]["subnet"], "10.40.0.0/24");
+    // The bytes are what the plugins read
+    let (name, conf) = ipam::host_local::load_conf(&bridge.bytes, "").unwrap();
+    assert_eq!(name, "models");
+    assert_eq!(conf.data_dir, "/tmp/models");
+    let parsed: BridgeConf = serde_json::from_slice(&bridge.bytes).unwrap();
+    assert!(matches!(parsed.ipam, Some(IpamConf::HostLocal(_))));
+    assert_eq!(parsed.bridge.as_deref(), Some("cni0"));
+
+    let static_ipam = BridgeConf::new()
+        .with_ipam(StaticConf::new().with_address("10.40.1.2/24".parse().unwrap(), None))
+        .to_network_config()
+        .unwrap();
+    assert_eq!(static_ipam.network.ipam.unwrap()._type, "static");
+    let dhcp = HostDeviceConf::new()
+        .with_pci_bus_id("0000:00:02.0")
+        .with_ipam(DhcpConf::new().with_request("subnet-mask", false))
+        .to_network_config()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&dhcp.bytes).unwrap();
+    assert_eq!(json["pciBusID"], "0000:00:02.0");
+    assert_eq!(json["ipam"]["type"], "dhcp");
+    assert_eq!(json["ipam"]["request"][0]["option"], "subnet-mask");
+
+    let portmap = PortmapConf::new()
+        .with_capability("portMappings", true)
+        .with_snat(true)
+        .to_network_config()
+        .unwrap();
+    assert_eq!(
+        portmap.network.capabilities.get("portMappings"),
+        Some(&true)
+    );
+    let tuning = TuningConf::new()
+        .with_sysctl("net.ipv4.conf.all.forwarding", "1")
+        .to_network_config()
+        .unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&tuning.bytes).unwrap();
+    assert_eq!(json["sysctl"]["net.ipv4.conf.all.forwarding"], "1");
+
+    // IPAM types without a model are kept, but need a type
+    let other: BridgeConf =
+        serde_json::from_str(r#"{"ipam":{"type":"whereabouts","range":"10.0.0.0/8"}}"#).unwrap();
+    assert!(matches!(other.ipam, Some(IpamConf::Other(_))));
+    assert!(serde_json::from_str::<BridgeConf>(r#"{"ipam":{"range":"10.0.0.0/8"}}"#).is_err());
+
+    // Keys without a field survive a round trip
+    let parsed: BridgeConf = serde_json::from_str(
+        r#"{"type":"bridge","bridge":"cni0","macspoofchk":true,"ipam":{"type":"dhcp"}}"#,
+    )
+    .unwrap();
+    assert_eq!(parsed.extra["macspoofchk"], true);
+    let json: serde_json::Value =
+        serde_json::from_slice(&parsed.to_network_config().unwrap().bytes).unwrap();
+    assert_eq!(json["macspoofchk"], true);
+    let again: BridgeConf = serde_json::from_value(json).unwrap();
+    assert_eq!(again, parsed);
+    assert_eq!(again.ipam, Some(IpamConf::from(DhcpConf::new())));
+
+    // So do those of typed ipam sections
+    for ipam in [
+        r#"{"type":"host-local","subnet":"10.0.0.0/24","newKey":[1]}"#,
+        r#"{"type":"static","addresses":[{"address":"10.0.0.2/24"}],"newKey":[1]}"#,
+        r#"{"type":"dhcp","newKey":[1]}"#,
+    ] {
+        let parsed: BridgeConf = serde_json::from_str(&format!(r#"{{"ipam":{}}}"#, ipam)).unwrap();
+        assert!(!matches!(parsed.ipam, Some(IpamConf::Other(_))), "{}", ipam);
+        let json: serde_json::Value =
+            serde_json::from_slice(&parsed.to_network_config().unwrap().bytes).unwrap();
+        assert_eq!(json["ipam"]["newKey"], serde_json::json!([1]), "{}", ipam);
+        let again: BridgeConf = serde_json::from_value(json).unwrap();
+        assert_eq!(again.ipam, parsed.ipam);
+    }
+}
+
+#[test]
+fn test_network_config_list_builder() {
+    init_logger();
+
+    let bridge = BridgeConf::new()
+        .with_bridge("cni0")
+        .with_ipam(HostLocalConf::new().with_range(Range::new("10.41.0.0/24".parse().unwrap())))
+        .to_network_config()
+        .unwrap();
+    let portmap = PortmapConf::new()
+        .with_capability("portMappings", true)
+        .to_network_config()
+        .unwrap();
+    let list = NetworkConfigList::builder("built-net", "1.0.0")
+        .with_plugin(bridge)
+        .with_plugin(portmap)
+        .with_disable_check(true)
+        .with_plugin_dirs(PluginDirs::Override(vec!["/opt/built/bin".to_string()]))
+        .with_ifname("net0")
+        .build()
+        .unwrap();
+    assert_eq!(list.bytes, list.to_bytes().unwrap());
+    let json: serde_json::Value = serde_json::from_slice(&list.bytes).unwrap();
+    assert_eq!(json["name"], "built-net");
+    assert_eq!(json["cniVersion"], "1.0.0");
+    assert_eq!(json["disableCheck"], true);
+    assert_eq!(json["pluginDirsOverride"], true);
+    assert_eq!(json["interfaceName"], "net0");
+    assert_eq!(json["plugins"][0]["type"], "bridge");
+    assert_eq!(json["plugins"][1]["capabilities"]["portMappings"], true);
+
+    // Parsing the bytes gives back the same list
+    let parsed = ConfigFile::config_from_bytes(&list.bytes).unwrap();
+    assert_eq!(parsed.name, list.name);
+    assert_eq!(parsed.cni_version, list.cni_version);
+    assert_eq!(parsed.disable_check, list.disable_check);
+    assert_eq!(parsed.plugin_dirs, list.plugin_dirs);
+    assert_eq!(parsed.ifname, list.ifname);
+    assert_eq!(parsed.plugins.len(), 2);
+    for (a, b) in parsed.plugins.iter().zip(&list.plugins) {
+        assert_eq!(a.bytes, b.bytes);
+        assert_eq!(a.network._type, b.network._type);
+    }
+    assert_eq!(parsed.to_bytes().unwrap(), list.bytes);
+
+    let plugin = || PortmapConf::new().to_network_config().unwrap();
+    let named = PortmapConf::new()
+        .with_name("other-net")
+        .to_network_config()
+        .unwrap();
+    let untyped = NetworkConfig {
+        network: NetConf::default(),
+        bytes: br#"{"mtu":1500}"#.to_vec(),
+    };
+    for (name, version, plugins) in [
+        ("-bad", "1.0.0", vec![plugin()]),
+        ("bad name", "1.0.0", vec![plugin()]),
+        ("net", "1.0", vec![plugin()]),
+        ("net", "9.9.9", vec![plugin()]),
+        ("net", "0.2.0", vec![plugin()]),
+        ("net", "1.0.0", vec![]),
+        ("net", "1.0.0", vec![named.clone()]),
+        ("net", "1.0.0", vec![untyped.clone()]),
+    ] {
+        let builder = plugins
+            .into_iter()
+            .fold(NetworkConfigList::builder(name, version), |b, p| {
+                b.with_plugin(p)
+            });
+        assert!(builder.build().is_err(), "{} {}", name, version);
+    }
+}
+
+#[test]
+fn test_config_dir_install() {
+    init_logger();
+    let dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let list = |name: &str, mtu: u32| {
+        NetworkConfigList::builder(name, "1.0.0")
+            .with_plugin(PtpConf::new().with_mtu(mtu).to_network_config().unwrap())
+            .build()
+            .unwrap()
+    };
+
+    let path = ConfigFile::install(&dir, 10, &list("net-a", 1500)).unwrap();
+    assert_eq!(path, format!("{}/10-net-a.conflist", dir));
+    assert_eq!(fs::read(&path).unwrap(), list("net-a", 1500).bytes);
+    ConfigFile::install(&dir, 5, &list("net-b", 1500)).unwrap();
+    // A single plugin config is listed too
+    fs::write(
+        format!("{}/20-net-c.conf", dir),
+        r#"{"cniVersion":"0.4.0","name":"net-c","type":"ptp"}"#,
+    )
+    .unwrap();
+    fs::write(format!("{}/30-broken.conflist", dir), "{").unwrap();
+
+    let networks = ConfigFile::list_networks(&dir).unwrap();
+    let names: Vec<(&str, Option<u32>)> = networks
+        .iter()
+        .map(|n| (n.name.as_str(), n.priority))
+        .collect();
+    assert_eq!(
+        names,
+        vec![("net-b", Some(5)), ("net-a", Some(10)), ("net-c", Some(20))]
+    );
+    assert_eq!(networks[2].cni_version, "0.4.0");
+    assert_eq!(networks[2].path, format!("{}/20-net-c.conf", dir));
+
+    // Updating a network in place is fine, moving or clobbering it is not
+    ConfigFile::install(&dir, 10, &list("net-a", 9000)).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+    assert_eq!(json["plugins"][0]["mtu"], 9000);
+    let err = ConfigFile::install(&dir, 50, &list("net-a", 1500)).unwrap_err();
+    assert!(err.contains("already installed"), "{}", err);
+    fs::write(
+        format!("{}/40-net-d.conflist", dir),
+        list("other", 1500).bytes,
+    )
+    .unwrap();
+    assert!(ConfigFile::install(&dir, 40, &list("net-d", 1500)).is_err());
+    assert!(ConfigFile::install(&dir, 30, &list("broken", 1500)).is_err());
+    let mut invalid = list("net-e", 1500);
+    invalid.name = "bad name".to_string();
+    assert!(ConfigFile::install(&dir, 60, &invalid).is_err());
+    // The file follows the fields, whatever the bytes say
+    let mut stale = list("net-f", 1500);
+    stale.disable_check = true;
+    let path_f = ConfigFile::install(&dir, 70, &stale).unwrap();
+    assert_eq!(fs::read(&path_f).unwrap(), stale.to_bytes().unwrap());
+    ConfigFile::remove(&dir, "net-f").unwrap();
+    // Of concurrent installs of a network in different files, one wins
+    let installed: Vec<bool> = std::thread::scope(|s| {
+        let handles: Vec<_> = (80..88)
+            .map(|priority| {
+                let dir = &dir;
+                s.spawn(move || ConfigFile::install(dir, priority, &list("net-g", 1500)).is_ok())
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(installed.iter().filter(|ok| **ok).count(), 1);
+    ConfigFile::remove(&dir, "net-g").unwrap();
+    // Nothing is left behind by the writes
+    let hidden = fs::read_dir(&dir)
+        .unwrap()
+        .filter(|e| {
+            e.as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .starts_with('.')
+        })
+        .count();
+    assert_eq!(hidden, 0);
+    assert!(!Path::new(&dir).join("lock").exists());
+
+    let removed = ConfigFile::remove(&dir, "net-c").unwrap();
+    assert_eq!(removed, vec![format!("{}/20-net-c.conf", dir)]);
+    assert!(ConfigFile::remove(&dir, "net-c").unwrap().is_empty());
+    let names: Vec<String> = ConfigFile::list_networks(&dir)
+        .unwrap()
+        .into_iter()
+        .map(|n| n.name)
+        .collect();
+    assert_eq!(names, vec!["net-b", "net-a", "other"]);
+
+    cleanup_test_environment(&dir).unwrap_or_default();
+}
+
+#[test]
+fn test_config_upgrade() {
+    init_logger();
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    create_fake_plugin(
+        &test_dir,
+        "new-plugin",
+        r#"echo '{"cniVersion":"1.0.0","supportedVersions":["0.3.1","0.4.0","1.0.0"]}'"#,
+    )
+    .unwrap();
+    create_fake_plugin(
+        &test_dir,
+        "old-plugin",
+        r#"echo '{"cniVersion":"0.4.0","supportedVersions":["0.3.1","0.4.0"]}'"#,
+    )
+    .unwrap();
+    let cni = CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+
+    // A .conf becomes a conflist holding the plugin
+    let conf_bytes = br#"{"cniVersion":"0.3.1","name":"legacy","type":"new-plugin","mtu":1400}"#;
+    let conf = NetworkConfig {
+        network: serde_json::from_slice(conf_bytes).unwrap(),
+        bytes: conf_bytes.to_vec(),
+    };
+    let upgrade = cni.upgrade_network(&conf, "1.0.0").unwrap();
+    assert_eq!(upgrade.list.name, "legacy");
+    assert_eq!(upgrade.list.cni_version, "1.0.0");
+    let json: serde_json::Value = serde_json::from_slice(&upgrade.list.bytes).unwrap();
+    assert_eq!(
+        json["plugins"],
+        serde_json::json!([{"type": "new-plugin", "mtu": 1400}])
+    );
+    let paths: Vec<&str> = upgrade.changes.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(
+        paths,
+        vec![
+            "$.plugins[0].cniVersion",
+            "$.plugins[0].name",
+            "$.plugins",
+            "$.cniVersion"
+        ]
+    );
+    let version = upgrade.changes.last().unwrap();
+    assert_eq!(version.old, Some("0.3.1".into()));
+    assert_eq!(version.new, Some("1.0.0".into()));
+    let parsed = ConfigFile::config_from_bytes(&upgrade.list.bytes).unwrap();
+    assert_eq!(parsed.plugins[0].bytes, upgrade.list.plugins[0].bytes);
+
+    // Only versions every plugin supports are allowed
+    let list = ConfigFile::config_from_bytes(
+        br#"{"cniVersion":"0.3.1","name":"chain","plugins":[
+            {"type":"new-plugin","cniVersion":"0.3.1"},{"type":"old-plugin"}]}"#,
+    )
+    .unwrap();
+    let err = cni.upgrade_network_list(&list, "1.0.0").err().unwrap();
+    assert!(err.to_string().contains("old-plugin"), "{}", err);
+    assert!(!err.to_string().contains("new-plugin"), "{}", err);
+    let upgrade = cni.upgrade_network_list(&list, "0.4.0").unwrap();
+    assert_eq!(upgrade.list.cni_version, "0.4.0");
+    let paths: Vec<&str> = upgrade.changes.iter().map(|c| c.path.as_str()).collect();
+    assert_eq!(paths, vec!["$.cniVersion", "$.plugins[0].cniVersion"]);
+    let json: serde_json::Value = serde_json::from_slice(&upgrade.list.bytes).unwrap();
+    assert_eq!(json["plugins"][0]["cniVersion"], "0.4.0");
+
+    assert!(cni
+        .upgrade_network_list(&list, "0.3.1")
+        .unwrap()
+        .changes
+        .is_empty());
+    assert!(cni.upgrade_network_list(&list, "0.3.0").is_err());
+    assert!(cni.upgrade_network_list(&list, "2.0.0").is_err());
+
+    // No version means the first spec
+    let bare = br#"{"name":"bare","type":"new-plugin"}"#;
+    let conf = NetworkConfig {
+        network: serde_json::from_slice(bare).unwrap(),
+        bytes: bare.to_vec(),
+    };
+    let upgrade =
+        ConfigFile::upgrade_conf(&conf, "0.4.0", |_| Ok(vec!["0.4.0".to_string()])).unwrap();
+    let version = upgrade.changes.last().unwrap();
+    assert_eq!(version.path, "$.cniVersion");
+    assert_eq!(version.old, None);
+    assert_eq!(upgrade.list.cni_version, "0.4.0");
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[test]
+fn test_config_lint() {
+    init_logger();
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let bin_dir = format!("{}/bin", test_dir);
+    let conf_dir = format!("{}/net.d", test_dir);
+    create_fake_plugin(
+        &bin_dir,
+        "good",
+        r#"echo '{"cniVersion":"1.0.0","supportedVersions":["0.4.0","1.0.0"]}'"#,
+    )
+    .unwrap();
+    fs::create_dir_all(&conf_dir).unwrap();
+    let files = [
+        (
+            "10-a.conflist",
+            r#"{"cniVersion":"1.0.0","name":"net-a","plugins":[{"type":"good",
+                "capabilities":{"portMappings":true,"custom":true,"mac":false},
+                "ipam":{"type":"host-local","subnet":"10.50.0.0/24"}}]}"#,
+        ),
+        (
+            "20-b.conflist",
+            r#"{"cniVersion":"0.3.1","name":"net-a","plugins":[{"type":"good",
+                "ipam":{"type":"host-local","ranges":[[{"subnet":"10.50.0.128/25"}]]}},
+                {"type":"missing","prevResult":{}}]}"#,
+        ),
+        (
+            "30-c.conf",
+            r#"{"cniVersion":"0.9.0","name":"bad name","type":"good","runtimeConfig":{}}"#,
+        ),
+        ("40-d.json", "{"),
+        (
+            "50-e.conflist",
+            r#"{"cniVersion":"0.4.0","name":"net-e","plugins":[{"type":"good",
+                "capabilities":{"io.kubernetes.cri.pod-annotations":true},
+                "ipam":{"type":"host-local","subnet":"10.51.0.0/24"}}]}"#,
+        ),
+    ];
+    for (name, conf) in files {
+        fs::write(format!("{}/{}", conf_dir, name), conf).unwrap();
+    }
+
+    let cni = CNIConfig {
+        path: vec![bin_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+    let findings = Linter::new(&cni).lint_dir(&conf_dir).unwrap();
+    for finding in &findings {
+        info!("{}", finding);
+    }
+    let mut found: Vec<(String, &str, LintKind)> = findings
+        .iter()
+        .map(|f| {
+            let file = Path::new(&f.file).file_name().unwrap();
+            (file.to_string_lossy().to_string(), f.path.as_str(), f.kind)
+        })
+        .collect();
+    found.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+    let expected = vec![
+        (
+            "10-a.conflist",
+            "$.plugins[0].capabilities.custom",
+            LintKind::UnsuppliedCapability,
+        ),
+        (
+            "20-b.conflist",
+            "$.cniVersion",
+            LintKind::UnsupportedVersion,
+        ),
+        ("20-b.conflist", "$.name", LintKind::DuplicateName),
+        (
+            "20-b.conflist",
+            "$.plugins[0].ipam",
+            LintKind::OverlappingRanges,
+        ),
+        (
+            "20-b.conflist",
+            "$.plugins[1].prevResult",
+            LintKind::RuntimeKey,
+        ),
+        (
+            "20-b.conflist",
+            "$.plugins[1].type",
+            LintKind::MissingPlugin,
+        ),
+        ("30-c.conf", "$.cniVersion", LintKind::UnknownVersion),
+        ("30-c.conf", "$.name", LintKind::InvalidName),
+        ("30-c.conf", "$.runtimeConfig", LintKind::RuntimeKey),
+        ("40-d.json", "$", LintKind::InvalidConfig),
+        (
+            "50-e.conflist",
+            r#"$.plugins[0].capabilities["io.kubernetes.cri.pod-annotations"]"#,
+            LintKind::UnsuppliedCapability,
+        ),
+    ];
+    let expected: Vec<(String, &str, LintKind)> = expected
+        .into_iter()
+        .map(|(file, path, kind)| (file.to_string(), path, kind))
+        .collect();
+    assert_eq!(found, expected);
+    let overlap = findings
+        .iter()
+        .find(|f| f.kind == LintKind::OverlappingRanges)
+        .unwrap();
+    assert!(overlap.message.contains("10-a.conflist"), "{}", overlap);
+
+    // Capabilities are checked against what the runtime says it supplies
+    let findings = Linter::new(&cni)
+        .with_capabilities([
+            "portMappings",
+            "custom",
+            "io.kubernetes.cri.pod-annotations",
+        ])
+        .lint_dir(&conf_dir)
+        .unwrap();
+    assert!(!findings
+        .iter()
+        .any(|f| f.kind == LintKind::UnsuppliedCapability));
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn test_async_network_lists() {
+    use rust_cni::libcni::async_api::{AsyncCNI, AsyncCNIConfig};
+    use rust_cni::libcni::async_exec::{AsyncExec, TokioExec};
+    use std::sync::Arc;
+
+    init_logger();
+
+    let test_dir = format!("/tmp/cni-test-{}", uuid::Uuid::new_v4());
+    let marker = format!("{}/finished", test_dir);
+    let version = r#"if [ "$CNI_COMMAND" = "VERSION" ]; then
+  echo '{"cniVersion":"1.0.0","supportedVersions":["1.0.0"]}'
+  exit 0
+fi"#;
+    create_fake_plugin(
+        &test_dir,
+        "quick",
+        &format!("{}\necho '{{\"cniVersion\":\"1.0.0\"}}'", version),
+    )
+    .unwrap();
+    create_fake_plugin(
+        &test_dir,
+        "slow",
+        &format!(
+            "{}\nsleep 1\ntouch {}\necho '{{\"cniVersion\":\"1.0.0\"}}'",
+            version, marker
+        ),
+    )
+    .unwrap();
+
+    let net = |name: &str, plugin: &str| {
+        let conflist = format!(
+            r#"{{"cniVersion":"1.0.0","name":"{}","plugins":[{{"type":"{}"}}]}}"#,
+            name, plugin
+        );
+        ConfigFile::config_from_bytes(conflist.as_bytes()).unwrap()
+    };
+    let rt = |id: &str| RuntimeConf {
+        container_id: id.to_string(),
+        net_ns: "/proc/self/ns/net".to_string(),
+        if_name: "eth0".to_string(),
+        ..Default::default()
+    };
+    let config = || CNIConfig {
+        path: vec![test_dir.clone()],
+        cache_dir: format!("{}/cache", test_dir),
+        ..Default::default()
+    };
+
+    // Networks are attached concurrently, results keep the input order
+    let cni = Arc::new(AsyncCNIConfig::new(
+        config(),
+        TokioExec::default().with_timeout(Duration::from_millis(300)),
+    ));
+    let results = cni
+        .add_network_lists(vec![
+            (net("slow-net", "slow"), rt("a")),
+            (net("quick-net", "quick"), rt("b")),
+        ])
+        .await;
+    assert_eq!(results.len(), 2);
+    let err = results[0].as_ref().err().unwrap();
+    assert!(err.to_string().contains("timed out"), "{}", err);
+    assert!(results[1].is_ok());
+    cni.delete_network_list(net("quick-net", "quick"), rt("b"))
+        .await
+        .unwrap();
+
+    // Dropping the future kills the plugin
+    let cni = AsyncCNIConfig::new(config(), TokioExec::default());
+    let dropped = tokio::time::timeout(
+        Duration::from_millis(300),
+        cni.add_network_list(net("slow-net", "slow"), rt("c")),
+    )
+    .await;
+    assert!(dropped.is_err());
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert!(!Path::new(&marker).exists());
+
+    // A failing plugin is an error even without an error object
+    let failing = create_fake_plugin(&test_dir, "failing", "echo '{}'; exit 1").unwrap();
+    let exec = TokioExec::default();
+    assert!(exec.exec_plugins(failing, b"{}", Vec::new()).await.is_err());
+
+    cleanup_test_environment(&test_dir).unwrap_or_default();
+}